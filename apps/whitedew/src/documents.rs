@@ -1,13 +1,22 @@
 
-use std::{collections::LinkedList, path::Path};
+use std::{
+    collections::LinkedList,
+    ffi::OsStr,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
 use whitedew_core::{
     apperr,
-    buffer::{RcTextBuffer, TextBuffer},
+    buffer::{RcTextBuffer, ReadProgress, TextBuffer},
+    helpers::{CoordType, Point},
 };
 
 #[derive(Default)]
 pub struct DocumentManager {
     list: LinkedList<Document>,
+    /// The number of untitled documents created so far, to name the next one.
+    untitled_count: usize,
 }
 
 impl DocumentManager {
@@ -15,42 +24,239 @@ impl DocumentManager {
     pub fn active(&self) -> Option<&Document> {
         self.list.front()
     }
-    
-    pub fn add_file_path(&mut self, path: &Path) -> apperr::Result<&mut Document> {
-        let buffer = Self::create_buffer()?;
 
-        let mut doc = Document {
-            buffer,
-            filename: Default::default(),
+    #[inline]
+    pub fn active_mut(&mut self) -> Option<&mut Document> {
+        self.list.front_mut()
+    }
+
+    /// Iterates over all documents, starting with the active one
+    /// and continuing in the order they were last active.
+    pub fn iter(&self) -> impl Iterator<Item = &Document> {
+        self.list.iter()
+    }
+
+    /// Makes the document at `index` in the order of [`DocumentManager::iter`] the active one.
+    pub fn activate(&mut self, index: usize) {
+        let mut i = 0;
+        self.update_active(|_| {
+            i += 1;
+            i - 1 == index
+        });
+    }
+
+    /// Closes the active document. The previously active one takes its place.
+    pub fn remove_active(&mut self) {
+        self.list.pop_front();
+    }
+
+    /// Opens the file at `path`, or creates a new document for it if it doesn't exist yet.
+    /// If `goto` is given, the cursor is moved to that logical position afterwards.
+    ///
+    /// If the file is already open, its document is activated instead of opening it twice.
+    /// `progress` is called while the file is read, see [`TextBuffer::read_file_with_progress`].
+    pub fn add_file_path(
+        &mut self,
+        path: &Path,
+        goto: Option<Point>,
+        progress: &mut dyn FnMut(ReadProgress) -> bool,
+    ) -> apperr::Result<&mut Document> {
+        // The canonical path resolves symlinks and the like, so that
+        // two different spellings of the same file are recognized as such.
+        let (path, exists) = match fs::canonicalize(path) {
+            Ok(path) => (path, true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (path.to_path_buf(), false),
+            Err(err) => return Err(err.into()),
         };
 
+        if self.update_active(|doc| doc.path.as_deref() == Some(&path)) {
+            let doc = self.list.front_mut().unwrap();
+            if let Some(goto) = goto {
+                doc.goto(goto);
+            }
+            return Ok(doc);
+        }
+
+        let buffer = Self::create_buffer()?;
+
+        if exists {
+            if path.is_dir() {
+                return Err(apperr::APP_FILE_IS_DIRECTORY);
+            }
+
+            let mut file = File::open(&path)?;
+            buffer.borrow_mut().read_file_with_progress(&mut file, None, progress)?;
+        }
+
+        let filename = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let doc = Document { buffer, path: Some(path), filename };
+        if let Some(goto) = goto {
+            doc.goto(goto);
+        }
+
         self.list.push_front(doc);
         Ok(self.list.front_mut().unwrap())
     }
 
     pub fn add_untitled(&mut self) -> apperr::Result<&mut Document> {
         let buffer = Self::create_buffer()?;
-        let mut doc = Document {
-            buffer,
-            filename: Default::default(),
-        };
+        self.untitled_count += 1;
+        let filename = format!("Untitled-{}", self.untitled_count);
+        let doc = Document { buffer, path: None, filename };
 
         self.list.push_front(doc);
         Ok(self.list.front_mut().unwrap())
     }
 
+    /// Splits a trailing `:LINE` or `:LINE:COLUMN` off of `path`, as in `foo.txt:123:45`.
+    ///
+    /// Line and column are 1-based in the path, like in compiler diagnostics,
+    /// but the returned position is 0-based. Files whose name genuinely ends in something
+    /// like ":12" take precedence: the suffixes are peeled off one at a time and it stops
+    /// at the first path for which `exists` returns `true`. If there's no such suffix,
+    /// `path` is returned unchanged.
+    pub fn parse_filename_goto(path: &Path, exists: impl Fn(&Path) -> bool) -> (&Path, Option<Point>) {
+        fn parse(s: &[u8]) -> Option<CoordType> {
+            if s.is_empty() || !s.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            let n = str::from_utf8(s).ok()?.parse::<CoordType>().ok()?;
+            Some((n - 1).max(0))
+        }
+
+        if exists(path) {
+            return (path, None);
+        }
+
+        let bytes = path.as_os_str().as_encoded_bytes();
+        let mut len = bytes.len();
+        let mut numbers = Vec::with_capacity(2);
+        let mut result = (path, None);
+
+        while numbers.len() < 2 {
+            let Some(colon) = bytes[..len].iter().rposition(|&b| b == b':') else {
+                break;
+            };
+            let Some(n) = parse(&bytes[colon + 1..len]) else {
+                break;
+            };
+            // There must be a filename left in front of the numbers.
+            if colon == 0 || bytes[colon - 1] == b'/' {
+                break;
+            }
+            numbers.push(n);
+            len = colon;
+
+            let goto = match numbers[..] {
+                [line] => Point { x: 0, y: line },
+                [column, line] => Point { x: column, y: line },
+                _ => unreachable!(),
+            };
+            // SAFETY: We only split the string at an ASCII character.
+            let path = Path::new(unsafe { OsStr::from_encoded_bytes_unchecked(&bytes[..len]) });
+            result = (path, Some(goto));
+            if exists(path) {
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Moves the first document for which `pred` returns `true` to the front,
+    /// making it the active one. Returns `false` if there's no such document.
+    fn update_active<F: FnMut(&Document) -> bool>(&mut self, pred: F) -> bool {
+        let Some(idx) = self.list.iter().position(pred) else {
+            return false;
+        };
+
+        let mut tail = self.list.split_off(idx);
+        let doc = tail.pop_front().unwrap();
+        self.list.append(&mut tail);
+        self.list.push_front(doc);
+        true
+    }
+
     fn create_buffer() -> apperr::Result<RcTextBuffer> {
         let buffer = TextBuffer::new_rc(false)?;
 
         Ok(buffer)
     }
 
+    /// Drops the word wrap of all documents, so that it gets computed anew.
+    /// Needed when the width of characters changes.
     pub fn reflow_all(&self) {
-
+        for doc in &self.list {
+            doc.buffer.borrow_mut().reflow();
+        }
     }
 }
 
 pub struct Document {
     pub buffer: RcTextBuffer,
+    /// The full path of the file. Canonical, if the file existed when it was opened.
+    /// `None` for untitled documents.
+    pub path: Option<PathBuf>,
     pub filename: String,
 }
+
+impl Document {
+    /// Moves the cursor to the given logical position.
+    pub fn goto(&self, pos: Point) {
+        let mut tb = self.buffer.borrow_mut();
+        let off = tb.logical_to_offset(pos);
+        tb.set_cursor_offset(off);
+    }
+
+    /// Reads the file again, converting it from `encoding`. Unsaved changes are lost.
+    /// If the encoding isn't available, e.g. because ICU is missing, the document stays as it is.
+    pub fn reopen(&self, encoding: &'static str) -> apperr::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut file = File::open(path)?;
+        self.buffer.borrow_mut().read_file(&mut file, Some(encoding))
+    }
+
+    /// Gives the document a new path, e.g. after it was saved under a different name.
+    pub fn set_path(&mut self, path: PathBuf) {
+        let path = fs::canonicalize(&path).unwrap_or(path);
+        self.filename = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        self.path = Some(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filename_goto() {
+        let at = |y, x| Some(Point { x, y });
+        // (argument, existing files, expected path, expected position)
+        let cases: &[(&str, &[&str], &str, Option<Point>)] = &[
+            ("foo", &[], "foo", None),
+            ("foo:12", &[], "foo", at(11, 0)),
+            ("foo:12:3", &[], "foo", at(11, 2)),
+            ("foo:0", &[], "foo", at(0, 0)),
+            ("a/foo:1:2:3", &[], "a/foo:1", at(1, 2)),
+            // Not a number, or nothing in front of it.
+            ("foo:", &[], "foo:", None),
+            ("foo:x", &[], "foo:x", None),
+            ("foo:1x", &[], "foo:1x", None),
+            (":12", &[], ":12", None),
+            ("a/:12", &[], "a/:12", None),
+            // Existing files take precedence.
+            ("foo:12", &["foo:12"], "foo:12", None),
+            ("foo:12:3", &["foo:12"], "foo:12", at(2, 0)),
+            ("foo:12:3", &["foo", "foo:12"], "foo:12", at(2, 0)),
+            ("foo:12:3", &["foo"], "foo", at(11, 2)),
+        ];
+
+        for &(arg, existing, path, goto) in cases {
+            let exists = |p: &Path| existing.iter().any(|e| Path::new(e) == p);
+            let res = DocumentManager::parse_filename_goto(Path::new(arg), exists);
+            assert_eq!(res, (Path::new(path), goto), "{arg}");
+        }
+    }
+}
@@ -0,0 +1,301 @@
+
+use crate::draw_editor::{close_active, save_document};
+use crate::localization::{LocId, loc};
+use crate::state::{FormatApperr, State, StateClose};
+use std::{
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use stdext::arena::scratch_arena;
+use whitedew_core::{
+    buffer::ReadProgress,
+    helpers::{KIBI, MEBI, Rect},
+    sys,
+    tui::{Context, Layout},
+};
+
+/// How long loading a file may take before [`LoadProgress`] shows up.
+const LOAD_PROGRESS_DELAY: Duration = Duration::from_millis(200);
+/// How often [`LoadProgress`] updates what it shows.
+const LOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn draw_dialog_about(ctx: &mut Context, state: &mut State) {
+    ctx.modal_begin("about", loc(LocId::AboutDialogTitle));
+    ctx.label("version", &format!("{}{}", loc(LocId::AboutDialogVersion), env!("CARGO_PKG_VERSION")));
+    let ok = dialog_ok_button(ctx);
+    if ctx.modal_end() || ok {
+        state.wants_about = false;
+    }
+}
+
+pub fn draw_dialog_error(ctx: &mut Context, state: &mut State) {
+    let Some(err) = state.error else {
+        return;
+    };
+
+    ctx.modal_begin("error", loc(LocId::ErrorDialogTitle));
+    ctx.label("message", &FormatApperr::from(err).to_string());
+    let ok = dialog_ok_button(ctx);
+    if ctx.modal_end() || ok {
+        state.error = None;
+    }
+}
+
+/// Lists the open documents, most recently used first, and activates the chosen one.
+pub fn draw_dialog_goto_file(ctx: &mut Context, state: &mut State) {
+    ctx.modal_begin("goto-file", loc(LocId::ViewGoToFile));
+
+    let mut activate = None;
+    for (i, doc) in state.documents.iter().enumerate() {
+        let dirty = if doc.buffer.borrow().is_dirty() { "● " } else { "" };
+        ctx.next_block_id_mixin(i as u64);
+        if ctx.button("document", &format!("{dirty}{}", doc.filename)) {
+            activate = Some(i);
+        }
+    }
+
+    if ctx.modal_end() {
+        state.wants_goto_file = false;
+    } else if let Some(i) = activate {
+        state.documents.activate(i);
+        state.wants_goto_file = false;
+    }
+}
+
+/// Asks what to do with the unsaved changes before closing the active document
+/// or exiting, one document after the other. Once none are left, it closes or exits.
+pub fn draw_dialog_unsaved_changes(ctx: &mut Context, state: &mut State) {
+    let exit = state.wants_close == StateClose::Exit;
+    // When closing, only the active document matters, which is the first one.
+    let dirty: Vec<usize> = state
+        .documents
+        .iter()
+        .take(if exit { usize::MAX } else { 1 })
+        .enumerate()
+        .filter(|(_, doc)| {
+            doc.buffer.borrow().is_dirty() && !state.close_discarded.iter().any(|b| Rc::ptr_eq(b, &doc.buffer))
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    let Some(&current) = dirty.first() else {
+        state.wants_close = StateClose::None;
+        state.close_discarded.clear();
+        if exit {
+            state.exit = true;
+        } else {
+            close_active(state);
+        }
+        return;
+    };
+
+    ctx.modal_begin("unsaved-changes", loc(LocId::UnsavedChangesDialogTitle));
+    ctx.label("description", loc(LocId::UnsavedChangesDialogDescription));
+
+    ctx.block_begin("files");
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    for (i, doc) in state.documents.iter().enumerate().filter(|(i, _)| dirty.contains(i)) {
+        let marker = if i == current { "▸" } else { " " };
+        ctx.next_block_id_mixin(i as u64);
+        ctx.label("file", &format!("{marker} {}", doc.filename));
+    }
+    ctx.block_end();
+
+    ctx.block_begin("buttons");
+    ctx.attr_layout(Layout::Row);
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    let save = ctx.button("save", loc(LocId::UnsavedChangesDialogYes));
+    ctx.label("spacer", " ");
+    let discard = ctx.button("discard", loc(LocId::UnsavedChangesDialogNo));
+    ctx.label("spacer", " ");
+    let save_all = dirty.len() > 1 && ctx.button("save-all", loc(LocId::UnsavedChangesDialogSaveAll));
+    if dirty.len() > 1 {
+        ctx.label("spacer", " ");
+    }
+    let cancel = ctx.button("cancel", loc(LocId::Cancel));
+    ctx.block_end();
+
+    if ctx.modal_end() || cancel {
+        state.wants_close = StateClose::None;
+        state.close_discarded.clear();
+    } else if save {
+        // Once it's saved, the next frame moves on to the next document.
+        save_document(state, current);
+    } else if discard {
+        if exit {
+            // The next frame moves on to the next document. Nothing gets closed until they're all done.
+            let buffer = state.documents.iter().nth(current).map(|doc| doc.buffer.clone());
+            state.close_discarded.extend(buffer);
+        } else {
+            state.documents.activate(current);
+            state.wants_close = StateClose::None;
+            close_active(state);
+        }
+    } else if save_all {
+        // Stop at the first document that fails to save, so that the user sees the error,
+        // or that needs a path, so that they can pick one.
+        for &i in &dirty {
+            if !save_document(state, i) {
+                break;
+            }
+        }
+    }
+}
+
+/// Sends new clipboard contents to the host terminal, unless they're large. Between
+/// [`State::osc_clipboard_warn_size`] and [`State::osc_clipboard_max_size`] the user is asked first,
+/// and above that they're told that the contents stay in the internal clipboard.
+pub fn draw_handle_clipboard_change(ctx: &mut Context, state: &mut State) {
+    let len = ctx.clipboard_mut().read().len();
+    let too_large = len >= state.osc_clipboard_max_size;
+    if len < state.osc_clipboard_warn_size || (state.osc_clipboard_always_send && !too_large) {
+        state.osc_clipboard_sync = true;
+        return;
+    }
+
+    let mut send = None;
+
+    ctx.modal_begin("clipboard-warning", loc(LocId::WarningDialogTitle));
+    ctx.label("line1", loc(LocId::LargeClipboardWarningLine1));
+    if too_large {
+        ctx.label("line2", loc(LocId::SuperLargeClipboardWarning));
+        if dialog_ok_button(ctx) {
+            send = Some(false);
+        }
+    } else {
+        ctx.label("line2", &loc(LocId::LargeClipboardWarningLine2).replace("{size}", &format_size(len)));
+        ctx.label("line3", loc(LocId::LargeClipboardWarningLine3));
+
+        ctx.block_begin("buttons");
+        ctx.attr_layout(Layout::Row);
+        ctx.attr_padding(Rect { top: 1, ..Default::default() });
+        if ctx.button("always", loc(LocId::Always)) {
+            state.osc_clipboard_always_send = true;
+            send = Some(true);
+        }
+        ctx.label("spacer", " ");
+        if ctx.button("yes", loc(LocId::Yes)) {
+            send = Some(true);
+        }
+        ctx.label("spacer", " ");
+        if ctx.button("no", loc(LocId::No)) {
+            send = Some(false);
+        }
+        ctx.block_end();
+    }
+    if ctx.modal_end() {
+        send = Some(false);
+    }
+
+    match send {
+        Some(true) => state.osc_clipboard_sync = true,
+        // The contents stay in the internal clipboard, and the dialog doesn't come back for them.
+        Some(false) => ctx.clipboard_mut().mark_as_synced(),
+        None => {}
+    }
+}
+
+/// Shows how much of a file has been read, and lets the user cancel with Esc or Ctrl+C.
+///
+/// Files are read in the middle of a frame, which blocks the event loop. So this can't use
+/// the [`Context`], and instead writes a line straight to the bottom of the terminal.
+/// [`LoadProgress::finish`] then makes the next frame redraw the screen.
+pub struct LoadProgress {
+    start: Instant,
+    last_shown: Option<Instant>,
+}
+
+impl LoadProgress {
+    pub fn new() -> Self {
+        Self { start: Instant::now(), last_shown: None }
+    }
+
+    /// The progress callback for [`whitedew_core::buffer::TextBuffer::read_file_with_progress`].
+    /// Returns `false` if the user cancelled.
+    pub fn report(&mut self, progress: ReadProgress) -> bool {
+        let now = Instant::now();
+        if now - self.start < LOAD_PROGRESS_DELAY {
+            return true;
+        }
+
+        if self.last_shown.is_none_or(|last| now - last >= LOAD_PROGRESS_INTERVAL) {
+            self.last_shown = Some(now);
+            let read = format_size(progress.read);
+            let size = match progress.total {
+                Some(total) => format!("{read} / {}", format_size(total)),
+                None => read,
+            };
+            let text = loc(LocId::LoadingFile).replace("{size}", &size);
+            // Save the cursor, print in inverse video in the bottom line,
+            // clear the rest of it and restore the cursor.
+            sys::write_stdout(&format!("\x1b7\x1b[999;1H\x1b[0;7m {text} \x1b[0m\x1b[K\x1b8"));
+        }
+
+        let scratch = scratch_arena(None);
+        match sys::read_stdin(&scratch, Duration::ZERO) {
+            Some(input) => !is_cancel_input(input.as_bytes()),
+            // stdin is gone. There's no one left who'd wait for the file.
+            None => false,
+        }
+    }
+
+    /// Call this once the file has been read (or not).
+    pub fn finish(self, ctx: &mut Context) {
+        if self.last_shown.is_some() {
+            ctx.invalidate();
+            // Any input that arrived in the meantime was dropped, which
+            // may have included the reply to a window size request.
+            sys::inject_window_size_into_stdin();
+        }
+    }
+}
+
+/// Returns `true` if `input` contains Ctrl+C or a lone Esc, as opposed to the start of an escape sequence.
+fn is_cancel_input(input: &[u8]) -> bool {
+    input
+        .iter()
+        .enumerate()
+        .any(|(i, &b)| b == 0x03 || b == 0x1b && !matches!(input.get(i + 1), Some(b'[' | b'O')))
+}
+
+/// Formats a byte count for humans, e.g. "1.5 MiB".
+fn format_size(bytes: usize) -> String {
+    if bytes >= MEBI {
+        format!("{:.1} MiB", bytes as f64 / MEBI as f64)
+    } else if bytes >= KIBI {
+        format!("{:.1} KiB", bytes as f64 / KIBI as f64)
+    } else {
+        format!("{bytes} B")
+    }
+}
+
+fn dialog_ok_button(ctx: &mut Context) -> bool {
+    ctx.block_begin("buttons");
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    let ok = ctx.button("ok", loc(LocId::Ok));
+    ctx.block_end();
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_cancel_input() {
+        assert!(is_cancel_input(b"\x1b"));
+        assert!(is_cancel_input(b"ab\x03"));
+        assert!(is_cancel_input(b"\x1b[A\x1b"));
+        assert!(!is_cancel_input(b"\x1b[<0;1;1M\x1bOP"));
+        assert!(!is_cancel_input(b"abc"));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(KIBI), "1.0 KiB");
+        assert_eq!(format_size(700 * KIBI + 512), "700.5 KiB");
+        assert_eq!(format_size(3 * MEBI / 2), "1.5 MiB");
+    }
+}
@@ -0,0 +1,134 @@
+
+use crate::draw_search;
+use crate::state::{State, StateClose, StateFilePicker, StateSearchKind};
+use std::rc::Rc;
+use whitedew_core::{
+    input::{kbmod, vk},
+    tui::Context,
+};
+
+pub fn draw_editor(ctx: &mut Context, state: &mut State) {
+    let Some(doc) = state.documents.active() else {
+        return;
+    };
+
+    // Give each document its own textarea, so that switching between them
+    // doesn't carry the scroll position over from one to the other.
+    ctx.next_block_id_mixin(Rc::as_ptr(&doc.buffer) as usize as u64);
+    ctx.textarea("textarea", doc.buffer.clone());
+    ctx.attr_fill();
+
+    // The editor gets the focus back whenever menus or dialogs give it up.
+    if ctx.is_focus_unset() {
+        ctx.steal_focus();
+    }
+}
+
+/// Handles the shortcuts of the commands in the menubar, while its menus are closed.
+pub fn draw_handle_shortcuts(ctx: &mut Context, state: &mut State) {
+    if ctx.consume_shortcut(kbmod::CTRL | vk::N) {
+        file_new(state);
+    } else if ctx.consume_shortcut(kbmod::CTRL | vk::O) {
+        file_open(state);
+    } else if ctx.consume_shortcut(kbmod::CTRL | vk::S) {
+        file_save(state);
+    } else if ctx.consume_shortcut(kbmod::CTRL_SHIFT | vk::S) {
+        file_save_as(state);
+    } else if ctx.consume_shortcut(kbmod::CTRL | vk::W) {
+        file_close(state);
+    } else if ctx.consume_shortcut(kbmod::CTRL | vk::Q) {
+        file_exit(state);
+    } else if ctx.consume_shortcut(kbmod::CTRL | vk::P) {
+        state.wants_goto_file = true;
+    } else if ctx.consume_shortcut(kbmod::ALT | vk::Z) {
+        view_word_wrap(state);
+    } else if ctx.consume_shortcut(kbmod::CTRL | vk::F) {
+        draw_search::edit_find(state, StateSearchKind::Search);
+    } else if ctx.consume_shortcut(kbmod::CTRL | vk::R) {
+        draw_search::edit_find(state, StateSearchKind::Replace);
+    } else if ctx.consume_shortcut(vk::F3) {
+        draw_search::search_next(state, false);
+    } else if ctx.consume_shortcut(kbmod::SHIFT | vk::F3) {
+        draw_search::search_next(state, true);
+    } else if state.wants_search.kind != StateSearchKind::Hidden && ctx.consume_shortcut(vk::ESCAPE) {
+        draw_search::search_close(state);
+    }
+}
+
+pub fn file_new(state: &mut State) {
+    if let Err(err) = state.documents.add_untitled() {
+        state.error = Some(err);
+    }
+}
+
+pub fn file_open(state: &mut State) {
+    state.wants_file_picker = StateFilePicker::Open;
+}
+
+pub fn file_save(state: &mut State) {
+    save_document(state, 0);
+}
+
+/// Saves the document at `index` in the order of [`DocumentManager::iter`].
+/// Untitled documents are activated and saved via the Save As dialog instead.
+///
+/// Returns `false` if the document isn't saved yet, e.g. because writing it failed.
+///
+/// [`DocumentManager::iter`]: crate::documents::DocumentManager::iter
+pub fn save_document(state: &mut State, index: usize) -> bool {
+    let Some(doc) = state.documents.iter().nth(index) else {
+        return false;
+    };
+    let Some(path) = &doc.path else {
+        state.documents.activate(index);
+        file_save_as(state);
+        return false;
+    };
+
+    if let Err(err) = doc.buffer.borrow_mut().write_file(path) {
+        state.error = Some(err);
+        return false;
+    }
+    true
+}
+
+pub fn file_save_as(state: &mut State) {
+    state.wants_file_picker = StateFilePicker::SaveAs;
+}
+
+/// Closes the active document, after asking what to do with its unsaved changes.
+pub fn file_close(state: &mut State) {
+    if state.documents.active().is_some_and(|doc| doc.buffer.borrow().is_dirty()) {
+        state.wants_close = StateClose::Document;
+    } else {
+        close_active(state);
+    }
+}
+
+/// Closes the active document. There's always at least one document, so closing the last
+/// one replaces it with an empty one.
+pub fn close_active(state: &mut State) {
+    state.documents.remove_active();
+    if state.documents.active().is_none() {
+        file_new(state);
+    }
+}
+
+/// Exits, after asking what to do with the unsaved changes of all documents.
+pub fn file_exit(state: &mut State) {
+    if state.documents.iter().any(|doc| doc.buffer.borrow().is_dirty()) {
+        state.wants_close = StateClose::Exit;
+        state.close_discarded.clear();
+    } else {
+        state.exit = true;
+    }
+}
+
+/// Turns word wrap on or off for the active document.
+pub fn view_word_wrap(state: &mut State) {
+    if let Some(doc) = state.documents.active() {
+        let mut tb = doc.buffer.borrow_mut();
+        let enabled = tb.is_word_wrap_enabled();
+        tb.set_word_wrap(!enabled);
+    }
+}
@@ -0,0 +1,284 @@
+
+use crate::draw_dialogs::LoadProgress;
+use crate::localization::{LocId, loc};
+use crate::state::{DisplayablePathBuf, State, StateFilePicker};
+use std::{
+    cmp::Ordering,
+    ffi::OsString,
+    fs,
+    path::{MAIN_SEPARATOR, MAIN_SEPARATOR_STR, Path, PathBuf},
+};
+use whitedew_core::{
+    apperr,
+    helpers::{Rect, Size, natural_cmp},
+    icu,
+    input::vk,
+    path,
+    tui::{Context, Layout},
+};
+
+/// The file picker for Open and Save As: a directory listing to navigate
+/// and a file name input with Tab completion.
+pub fn draw_file_picker(ctx: &mut Context, state: &mut State) {
+    if state.wants_file_picker == StateFilePicker::SaveAs {
+        // Start out where the document already lives, under its current name.
+        if let Some(doc) = state.documents.active() {
+            if let Some(dir) = doc.path.as_deref().and_then(Path::parent) {
+                state.file_picker_pending_dir = DisplayablePathBuf::from_path(dir.to_path_buf());
+                state.file_picker_entries = None;
+            }
+            state.file_picker_pending_name = doc.filename.clone();
+        }
+        state.wants_file_picker = StateFilePicker::SaveAsShown;
+    }
+
+    if state.file_picker_overwrite_warning.is_some() {
+        draw_dialog_overwrite_warning(ctx, state);
+        return;
+    }
+
+    if state.file_picker_entries.is_none() {
+        let entries = read_entries(state.file_picker_pending_dir.as_path(), state.file_picker_show_hidden);
+        state.file_picker_entries = Some(entries);
+    }
+
+    let title = match state.wants_file_picker {
+        StateFilePicker::Open => loc(LocId::FileOpen),
+        _ => loc(LocId::FileSaveAs),
+    };
+    let screen = ctx.size();
+    let width = (screen.width - 10).clamp(20, 70);
+    let height = (screen.height - 14).max(3);
+
+    let mut accept = false;
+    let mut activated = None;
+    let mut focus_list = false;
+
+    ctx.modal_begin("file-picker", title);
+
+    ctx.block_begin("path");
+    ctx.attr_layout(Layout::Row);
+    ctx.label("label", loc(LocId::SaveAsDialogPathLabel));
+    ctx.label("spacer", " ");
+    ctx.label("dir", state.file_picker_pending_dir.as_str());
+    ctx.block_end();
+
+    ctx.block_begin("name");
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    ctx.label("label", loc(LocId::SaveAsDialogNameLabel));
+    ctx.editline("input", &mut state.file_picker_pending_name);
+    ctx.attr_intrinsic_size(Size { width, height: 1 });
+    if ctx.is_focused() {
+        // Without anything to complete, Tab moves the focus on as usual.
+        if ctx.keyboard_input() == Some(vk::TAB) && complete_name(state) {
+            ctx.set_input_consumed();
+        } else if ctx.consume_shortcut(vk::RETURN) {
+            accept = true;
+        } else if ctx.consume_shortcut(vk::DOWN) {
+            focus_list = true;
+        }
+    }
+    ctx.block_end();
+
+    ctx.block_begin("entries");
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    ctx.scrollarea_begin("list", Size { width, height });
+    if state.file_picker_pending_dir.as_path().parent().is_some() {
+        if ctx.list_item("parent", "..") {
+            activated = Some(PathBuf::from(".."));
+        }
+        if focus_list {
+            ctx.steal_focus();
+            focus_list = false;
+        }
+    }
+    for (i, entry) in state.file_picker_entries.iter().flatten().enumerate() {
+        ctx.next_block_id_mixin(i as u64);
+        if ctx.list_item("entry", entry.as_str()) {
+            activated = Some(entry.as_path().to_path_buf());
+        }
+        if focus_list {
+            ctx.steal_focus();
+            focus_list = false;
+        }
+    }
+    ctx.scrollarea_end();
+    ctx.block_end();
+
+    ctx.block_begin("options");
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    if ctx.checkbox("show-hidden", loc(LocId::SaveAsDialogShowHidden), &mut state.file_picker_show_hidden) {
+        state.file_picker_entries = None;
+    }
+    ctx.block_end();
+
+    ctx.block_begin("buttons");
+    ctx.attr_layout(Layout::Row);
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    accept |= ctx.button("ok", loc(LocId::Ok));
+    ctx.label("spacer", " ");
+    let cancel = ctx.button("cancel", loc(LocId::Cancel));
+    ctx.block_end();
+
+    if ctx.modal_end() || cancel {
+        close(state);
+        return;
+    }
+
+    if let Some(name) = activated {
+        accept_path(ctx, state, &name);
+    } else if accept && !state.file_picker_pending_name.is_empty() {
+        let name = PathBuf::from(&state.file_picker_pending_name);
+        accept_path(ctx, state, &name);
+    }
+}
+
+fn draw_dialog_overwrite_warning(ctx: &mut Context, state: &mut State) {
+    ctx.modal_begin("overwrite-warning", loc(LocId::FileOverwriteWarning));
+    ctx.label("description", loc(LocId::FileOverwriteWarningDescription));
+
+    ctx.block_begin("buttons");
+    ctx.attr_layout(Layout::Row);
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    let yes = ctx.button("yes", loc(LocId::Yes));
+    ctx.label("spacer", " ");
+    let no = ctx.button("no", loc(LocId::No));
+    ctx.block_end();
+
+    if ctx.modal_end() || no {
+        state.file_picker_overwrite_warning = None;
+    } else if yes && let Some(path) = state.file_picker_overwrite_warning.take() {
+        save_as(state, path);
+    }
+}
+
+/// Navigates into `name` if it's a directory, and otherwise opens or saves it.
+/// `name` is relative to the current directory, unless it's absolute.
+fn accept_path(ctx: &mut Context, state: &mut State, name: &Path) {
+    let path = path::normalize(&state.file_picker_pending_dir.as_path().join(name));
+
+    if path.is_dir() {
+        state.file_picker_pending_dir = DisplayablePathBuf::from_path(path);
+        state.file_picker_entries = None;
+        if state.wants_file_picker == StateFilePicker::Open {
+            state.file_picker_pending_name.clear();
+        }
+        return;
+    }
+
+    if state.wants_file_picker == StateFilePicker::Open {
+        let mut progress = LoadProgress::new();
+        let res = state.documents.add_file_path(&path, None, &mut |p| progress.report(p)).map(|_| ());
+        progress.finish(ctx);
+        match res {
+            Ok(()) => close(state),
+            // The user pressed Esc and may want to pick another file.
+            Err(apperr::APP_CANCELLED) => {}
+            Err(err) => state.error = Some(err),
+        }
+    } else if path.exists() {
+        state.file_picker_overwrite_warning = Some(path);
+    } else {
+        save_as(state, path);
+    }
+}
+
+fn save_as(state: &mut State, path: PathBuf) {
+    let Some(doc) = state.documents.active_mut() else {
+        return;
+    };
+
+    // The document keeps its old path if the write fails, so that the user can try elsewhere.
+    if let Err(err) = doc.buffer.borrow_mut().write_file(&path) {
+        state.error = Some(err);
+        return;
+    }
+
+    doc.set_path(path);
+    close(state);
+}
+
+fn close(state: &mut State) {
+    state.wants_file_picker = StateFilePicker::None;
+    state.file_picker_pending_name.clear();
+    state.file_picker_entries = None;
+    state.file_picker_overwrite_warning = None;
+}
+
+/// Completes the file name to the longest prefix that all matching entries share.
+/// If only a directory matches, a `/` is appended to continue inside it.
+/// Returns `false` if there was nothing to complete.
+fn complete_name(state: &mut State) -> bool {
+    let name = &state.file_picker_pending_name;
+    let (dir_part, prefix) = match name.rfind(MAIN_SEPARATOR) {
+        Some(i) => name.split_at(i + 1),
+        None => ("", name.as_str()),
+    };
+
+    // Hidden files are offered if they are shown, or if the name asks for one.
+    let show_hidden = state.file_picker_show_hidden || prefix.starts_with('.');
+    let dir = path::normalize(&state.file_picker_pending_dir.as_path().join(dir_part));
+    let entries = read_entries(&dir, show_hidden);
+    let mut matches = entries.iter().map(DisplayablePathBuf::as_str).filter(|e| e.starts_with(prefix));
+
+    let Some(first) = matches.next() else {
+        return false;
+    };
+
+    let mut common = first;
+    for m in matches {
+        let len = common
+            .char_indices()
+            .zip(m.chars())
+            .find(|&((_, a), b)| a != b)
+            .map_or(common.len().min(m.len()), |((i, _), _)| i);
+        common = &common[..len];
+    }
+
+    let completed = format!("{dir_part}{common}");
+    let changed = completed != state.file_picker_pending_name;
+    state.file_picker_pending_name = completed;
+    changed
+}
+
+/// Reads the contents of `dir`, sorted naturally, with directories first.
+/// Directories have a trailing `/`. Unreadable directories appear empty.
+///
+/// The order follows the Unicode collation rules if ICU is available.
+/// Names that aren't valid UTF-8 are kept as they are, so that they can still be opened;
+/// only their displayed and sorted form has U+FFFD in place of the invalid bytes.
+fn read_entries(dir: &Path, show_hidden: bool) -> Vec<DisplayablePathBuf> {
+    // The lossy name for sorting, whether it's a directory, and the actual name.
+    let mut entries: Vec<(String, bool, OsString)> = Vec::new();
+
+    if let Ok(iter) = fs::read_dir(dir) {
+        for entry in iter.flatten() {
+            let name = entry.file_name();
+            let lossy = name.to_string_lossy().into_owned();
+            if !show_hidden && lossy.starts_with('.') {
+                continue;
+            }
+            // Follows symlinks, so that links to directories can be navigated into.
+            let is_dir = entry.path().is_dir();
+            entries.push((lossy, is_dir, name));
+        }
+    }
+
+    entries.sort_by(|a, b| match (a.1, b.1) {
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => icu::compare_strings(&a.0, &b.0)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| natural_cmp(&a.0, &b.0)),
+    });
+
+    entries
+        .into_iter()
+        .map(|(_, is_dir, mut name)| {
+            if is_dir {
+                name.push(MAIN_SEPARATOR_STR);
+            }
+            DisplayablePathBuf::from_path(PathBuf::from(name))
+        })
+        .collect()
+}
@@ -0,0 +1,107 @@
+
+use crate::draw_editor;
+use crate::draw_search;
+use crate::localization::{LocId, loc};
+use crate::state::{State, StateSearchKind};
+use whitedew_core::{
+    input::{kbmod, vk},
+    tui::Context,
+};
+
+pub fn draw_menubar(ctx: &mut Context, state: &mut State) {
+    ctx.menubar_begin();
+    ctx.attr_background_rgba(state.menubar_color_bg);
+    ctx.attr_foreground_rgba(state.menubar_color_fg);
+    {
+        if ctx.menubar_menu_begin(loc(LocId::File), 'F') {
+            draw_menu_file(ctx, state);
+        }
+        if ctx.menubar_menu_begin(loc(LocId::Edit), 'E') {
+            draw_menu_edit(ctx, state);
+        }
+        if ctx.menubar_menu_begin(loc(LocId::View), 'V') {
+            draw_menu_view(ctx, state);
+        }
+        if ctx.menubar_menu_begin(loc(LocId::Help), 'H') {
+            draw_menu_help(ctx, state);
+        }
+    }
+    ctx.menubar_end();
+}
+
+fn draw_menu_file(ctx: &mut Context, state: &mut State) {
+    if ctx.menubar_menu_button(loc(LocId::FileNew), 'N', kbmod::CTRL | vk::N) {
+        draw_editor::file_new(state);
+    }
+    if ctx.menubar_menu_button(loc(LocId::FileOpen), 'O', kbmod::CTRL | vk::O) {
+        draw_editor::file_open(state);
+    }
+    if ctx.menubar_menu_button(loc(LocId::FileSave), 'S', kbmod::CTRL | vk::S) {
+        draw_editor::file_save(state);
+    }
+    if ctx.menubar_menu_button(loc(LocId::FileSaveAs), 'A', kbmod::CTRL_SHIFT | vk::S) {
+        draw_editor::file_save_as(state);
+    }
+    if ctx.menubar_menu_button(loc(LocId::FileClose), 'C', kbmod::CTRL | vk::W) {
+        draw_editor::file_close(state);
+    }
+    if ctx.menubar_menu_button(loc(LocId::FileExit), 'X', kbmod::CTRL | vk::Q) {
+        draw_editor::file_exit(state);
+    }
+    ctx.menubar_menu_end();
+}
+
+fn draw_menu_edit(ctx: &mut Context, state: &mut State) {
+    let Some(doc) = state.documents.active() else {
+        ctx.menubar_menu_end();
+        return;
+    };
+    let mut tb = doc.buffer.borrow_mut();
+
+    if ctx.menubar_menu_button(loc(LocId::EditUndo), 'U', kbmod::CTRL | vk::Z) {
+        tb.undo();
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditRedo), 'R', kbmod::CTRL | vk::Y) {
+        tb.redo();
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditCut), 'T', kbmod::CTRL | vk::X) {
+        tb.cut(ctx.clipboard_mut());
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditCopy), 'C', kbmod::CTRL | vk::C) {
+        tb.copy(ctx.clipboard_mut());
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditPaste), 'P', kbmod::CTRL | vk::V) {
+        tb.paste(ctx.clipboard_mut());
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditSelectAll), 'A', kbmod::CTRL | vk::A) {
+        tb.select_all();
+    }
+    drop(tb);
+    if ctx.menubar_menu_button(loc(LocId::EditFind), 'F', kbmod::CTRL | vk::F) {
+        draw_search::edit_find(state, StateSearchKind::Search);
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditReplace), 'L', kbmod::CTRL | vk::R) {
+        draw_search::edit_find(state, StateSearchKind::Replace);
+    }
+    ctx.menubar_menu_end();
+}
+
+fn draw_menu_view(ctx: &mut Context, state: &mut State) {
+    if let Some(doc) = state.documents.active() {
+        let word_wrap = doc.buffer.borrow().is_word_wrap_enabled();
+        if ctx.menubar_menu_checkbox(loc(LocId::ViewWordWrap), 'W', kbmod::ALT | vk::Z, word_wrap) {
+            draw_editor::view_word_wrap(state);
+        }
+    }
+    if ctx.menubar_menu_button(loc(LocId::ViewGoToFile), 'G', kbmod::CTRL | vk::P) {
+        state.wants_goto_file = true;
+    }
+    ctx.menubar_menu_end();
+}
+
+fn draw_menu_help(ctx: &mut Context, state: &mut State) {
+    if ctx.menubar_menu_button(loc(LocId::HelpAbout), 'A', vk::NULL) {
+        state.wants_about = true;
+    }
+    ctx.menubar_menu_end();
+}
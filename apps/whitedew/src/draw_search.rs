@@ -0,0 +1,138 @@
+
+use crate::localization::{LocId, loc};
+use crate::state::{FormatApperr, State, StateSearch, StateSearchKind};
+use whitedew_core::{
+    helpers::Rect,
+    input::{kbmod, vk},
+    tui::{Context, Layout},
+};
+
+/// The find (and replace) bar above the editor.
+pub fn draw_search(ctx: &mut Context, state: &mut State) {
+    let Some(doc) = state.documents.active() else {
+        return;
+    };
+    let buffer = doc.buffer.clone();
+
+    ctx.block_begin("search");
+    ctx.attr_background_rgba(state.menubar_color_bg);
+    ctx.attr_foreground_rgba(state.menubar_color_fg);
+
+    ctx.block_begin("needle");
+    ctx.attr_layout(Layout::Row);
+    ctx.label("label", loc(LocId::SearchNeedleLabel));
+    ctx.attr_padding(Rect::two(0, 1));
+    let mut changed = ctx.editline("needle", &mut state.search_needle);
+    ctx.attr_fill();
+    if state.wants_search.focus {
+        state.wants_search.focus = false;
+        ctx.steal_focus();
+    }
+    if ctx.is_focused() {
+        if ctx.consume_shortcut(vk::RETURN) {
+            buffer.borrow_mut().find_next(false);
+        } else if ctx.consume_shortcut(kbmod::SHIFT | vk::RETURN) {
+            buffer.borrow_mut().find_next(true);
+        }
+    }
+
+    let options = &mut state.search_options;
+    ctx.label("spacer", " ");
+    changed |= ctx.checkbox("match-case", loc(LocId::SearchMatchCase), &mut options.match_case);
+    ctx.label("spacer", " ");
+    changed |= ctx.checkbox("whole-word", loc(LocId::SearchWholeWord), &mut options.whole_word);
+    ctx.label("spacer", " ");
+    changed |= ctx.checkbox("use-regex", loc(LocId::SearchUseRegex), &mut options.use_regex);
+
+    // Another document may have become active, which doesn't know about the search yet.
+    if changed || !buffer.borrow().is_searching() {
+        state.search_error = buffer.borrow_mut().set_search(&state.search_needle, state.search_options).err();
+    }
+
+    let status = match state.search_error {
+        Some(err) => FormatApperr::from(err).to_string(),
+        None if state.search_needle.is_empty() => String::new(),
+        None => {
+            let count = buffer.borrow_mut().search_count();
+            if !count.complete {
+                ctx.needs_rerender();
+            }
+            let current = count.current.map_or("-".to_string(), |i| (i + 1).to_string());
+            let more = if count.complete { "" } else { "+" };
+            format!("{current}/{}{more}", count.total)
+        }
+    };
+    ctx.label("status", &status);
+    ctx.attr_padding(Rect::two(0, 1));
+    let close = ctx.button("close", loc(LocId::SearchClose));
+    ctx.block_end();
+
+    if state.wants_search.kind == StateSearchKind::Replace {
+        ctx.block_begin("replacement");
+        ctx.attr_layout(Layout::Row);
+        ctx.label("label", loc(LocId::SearchReplacementLabel));
+        ctx.attr_padding(Rect::two(0, 1));
+        ctx.editline("replacement", &mut state.search_replacement);
+        ctx.attr_fill();
+        let mut replace = ctx.is_focused() && ctx.consume_shortcut(vk::RETURN);
+        ctx.label("spacer", " ");
+        replace |= ctx.button("replace", loc(LocId::EditReplace));
+        ctx.label("spacer", " ");
+        let replace_all = ctx.button("replace-all", loc(LocId::SearchReplaceAll));
+        ctx.label("spacer", " ");
+        ctx.block_end();
+
+        if replace {
+            buffer.borrow_mut().replace_next(&state.search_replacement);
+        } else if replace_all {
+            buffer.borrow_mut().replace_all(&state.search_replacement);
+        }
+    }
+
+    ctx.block_end();
+
+    if close {
+        search_close(state);
+    }
+}
+
+/// Shows the search bar (or the replace bar with `kind`) and focuses it.
+/// A selection within a single line becomes the new needle,
+/// unless it's a match of the current one.
+pub fn edit_find(state: &mut State, kind: StateSearchKind) {
+    if let Some(doc) = state.documents.active() {
+        let mut tb = doc.buffer.borrow_mut();
+        if let Some(range) = tb.selection_range()
+            && !tb.is_match_selected()
+        {
+            let mut text = Vec::new();
+            tb.extract_raw(range, &mut text);
+            if !text.contains(&b'\n') {
+                state.search_needle = String::from_utf8_lossy(&text).into_owned();
+                // Makes the search bar pick up the new needle.
+                tb.clear_search();
+            }
+        }
+    }
+
+    // Opening the plain search bar doesn't hide an open replace bar.
+    let kind = if state.wants_search.kind == StateSearchKind::Replace { StateSearchKind::Replace } else { kind };
+    state.wants_search = StateSearch { kind, focus: true };
+}
+
+/// Selects the next match (or the previous one with `reverse`), if the search bar is shown.
+pub fn search_next(state: &mut State, reverse: bool) {
+    if state.wants_search.kind != StateSearchKind::Hidden
+        && let Some(doc) = state.documents.active()
+    {
+        doc.buffer.borrow_mut().find_next(reverse);
+    }
+}
+
+/// Hides the search bar and the highlighted matches.
+pub fn search_close(state: &mut State) {
+    state.wants_search = StateSearch { kind: StateSearchKind::Hidden, focus: false };
+    for doc in state.documents.iter() {
+        doc.buffer.borrow_mut().clear_search();
+    }
+}
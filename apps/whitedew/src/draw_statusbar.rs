@@ -0,0 +1,258 @@
+use crate::localization::{LocId, loc};
+use crate::state::{State, StateEncodingChange};
+use whitedew_core::{
+    buffer::LineEndings,
+    encoding,
+    helpers::{CoordType, Rect, Size},
+    tui::{Context, Layout},
+};
+
+pub fn draw_statusbar(ctx: &mut Context, state: &mut State) {
+    ctx.block_begin("statusbar");
+    ctx.attr_layout(Layout::Row);
+    ctx.attr_background_rgba(state.menubar_color_bg);
+    ctx.attr_foreground_rgba(state.menubar_color_fg);
+
+    if let Some(doc) = state.documents.active() {
+        let tb = doc.buffer.borrow();
+        let pos = tb.offset_to_logical(tb.cursor_offset());
+
+        // Shown 1-based, like the FILE:LINE:COLUMN arguments.
+        ctx.label("position", &format!("{}:{}", pos.y + 1, pos.x + 1));
+        ctx.attr_padding(Rect::two(0, 1));
+
+        ctx.block_begin("filler");
+        ctx.attr_fill();
+        ctx.block_end();
+
+        if ctx.list_item("indentation", &indentation_label(tb.indent_with_tabs(), tb.tab_size())) {
+            state.wants_indentation = true;
+        }
+        ctx.attr_padding(Rect::two(0, 1));
+
+        if ctx.list_item("line-endings", line_endings_label(tb.line_endings())) {
+            state.wants_line_endings = true;
+        }
+        ctx.attr_padding(Rect::two(0, 1));
+
+        if ctx.list_item("encoding", &encoding_label(tb.encoding(), tb.has_bom())) {
+            state.wants_encoding_change = StateEncodingChange::Prompt;
+        }
+        ctx.attr_padding(Rect::two(0, 1));
+
+        let dirty = if tb.is_dirty() { "● " } else { "" };
+        ctx.label("filename", &format!("{dirty}{}", doc.filename));
+        ctx.attr_padding(Rect::two(0, 1));
+    }
+
+    ctx.block_end();
+}
+
+/// Asks whether to reopen the active document in a different encoding or to convert it,
+/// and then which encoding that should be.
+pub fn draw_dialog_encoding_change(ctx: &mut Context, state: &mut State) {
+    let Some(doc) = state.documents.active() else {
+        close_encoding_change(state);
+        return;
+    };
+    // Reopening throws away the changes, so it's only offered if there are none.
+    let can_reopen = doc.path.is_some() && !doc.buffer.borrow().is_dirty();
+
+    if state.wants_encoding_change == StateEncodingChange::Prompt {
+        let tb = doc.buffer.borrow();
+        ctx.modal_begin("encoding", &encoding_label(tb.encoding(), tb.has_bom()));
+        drop(tb);
+
+        let reopen = can_reopen && ctx.button("reopen", loc(LocId::EncodingReopen));
+        let convert = ctx.button("convert", loc(LocId::EncodingConvert));
+
+        ctx.block_begin("buttons");
+        ctx.attr_padding(Rect { top: 1, ..Default::default() });
+        let cancel = ctx.button("cancel", loc(LocId::Cancel));
+        ctx.block_end();
+
+        if ctx.modal_end() || cancel {
+            close_encoding_change(state);
+        } else if reopen {
+            state.wants_encoding_change = StateEncodingChange::Reopen;
+        } else if convert {
+            state.wants_encoding_change = StateEncodingChange::Convert;
+        }
+        return;
+    }
+
+    let reopen = state.wants_encoding_change == StateEncodingChange::Reopen;
+    let first_frame = state.encoding_picker_entries.is_none();
+    let entries = state.encoding_picker_entries.get_or_insert_with(encoding::all);
+
+    let (title, current) = {
+        let tb = doc.buffer.borrow();
+        if reopen {
+            (loc(LocId::EncodingReopen), (tb.encoding(), false))
+        } else {
+            (loc(LocId::EncodingConvert), (tb.encoding(), tb.has_bom()))
+        }
+    };
+    let height = (ctx.size().height - 10).clamp(3, 15);
+    let mut activated = None;
+
+    ctx.modal_begin("encoding-picker", title.trim_end_matches('…'));
+    ctx.scrollarea_begin("list", Size { width: 30, height });
+    for (i, &name) in entries.iter().enumerate() {
+        // UTF-8 is the only encoding that is commonly used both with and without a BOM.
+        // Converting to any of the others adds one, so that other programs recognize it.
+        let boms: &[bool] = match name {
+            _ if reopen || encoding::bom(name).is_empty() => &[false],
+            encoding::UTF8 => &[false, true],
+            _ => &[true],
+        };
+
+        for &bom in boms {
+            ctx.next_block_id_mixin((i * 2 + bom as usize) as u64);
+            let label = if name == encoding::UTF8 { encoding_label(name, bom) } else { name.to_string() };
+            if ctx.list_item("entry", &label) {
+                activated = Some((name, bom));
+            }
+            if first_frame && (name, bom) == current {
+                ctx.steal_focus();
+            }
+        }
+    }
+    ctx.scrollarea_end();
+
+    ctx.block_begin("buttons");
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    let cancel = ctx.button("cancel", loc(LocId::Cancel));
+    ctx.block_end();
+
+    if ctx.modal_end() || cancel {
+        close_encoding_change(state);
+        return;
+    }
+
+    let Some((encoding, bom)) = activated else {
+        return;
+    };
+    if reopen {
+        if let Err(err) = doc.reopen(encoding) {
+            state.error = Some(err);
+        }
+    } else {
+        doc.buffer.borrow_mut().set_encoding(encoding, bom);
+    }
+    close_encoding_change(state);
+}
+
+/// Offers to convert all line endings of the active document to either kind.
+pub fn draw_dialog_line_endings(ctx: &mut Context, state: &mut State) {
+    let Some(doc) = state.documents.active() else {
+        state.wants_line_endings = false;
+        return;
+    };
+    let current = doc.buffer.borrow().line_endings();
+
+    ctx.modal_begin("line-endings", line_endings_label(current));
+    let mut choice = None;
+    for (classname, kind) in [("lf", LineEndings::Lf), ("crlf", LineEndings::Crlf)] {
+        if ctx.list_item(classname, line_endings_label(kind)) {
+            choice = Some(kind);
+        }
+    }
+
+    ctx.block_begin("buttons");
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    let cancel = ctx.button("cancel", loc(LocId::Cancel));
+    ctx.block_end();
+
+    if ctx.modal_end() || cancel {
+        state.wants_line_endings = false;
+    } else if let Some(kind) = choice {
+        doc.buffer.borrow_mut().set_line_endings(kind == LineEndings::Crlf);
+        state.wants_line_endings = false;
+    }
+}
+
+/// Switches the active document between indenting with tabs and spaces, sets the indentation width,
+/// and offers to convert the existing indentation to match.
+pub fn draw_dialog_indentation(ctx: &mut Context, state: &mut State) {
+    let Some(doc) = state.documents.active() else {
+        state.wants_indentation = false;
+        return;
+    };
+    let (tabs, width) = {
+        let tb = doc.buffer.borrow();
+        (tb.indent_with_tabs(), tb.tab_size())
+    };
+    let marker = |checked: bool| if checked { '●' } else { '○' };
+
+    ctx.modal_begin("indentation", &indentation_label(tabs, width));
+
+    ctx.block_begin("kind");
+    ctx.attr_layout(Layout::Row);
+    let mut choice = None;
+    let kinds = [("tabs", true, LocId::IndentationTabs), ("spaces", false, LocId::IndentationSpaces)];
+    for (classname, kind, id) in kinds {
+        if ctx.list_item(classname, &format!("{} {}", marker(kind == tabs), loc(id))) {
+            choice = Some(kind);
+        }
+        ctx.attr_padding(Rect::two(0, 1));
+    }
+    ctx.block_end();
+
+    ctx.block_begin("widths");
+    ctx.attr_layout(Layout::Row);
+    let mut new_width = None;
+    for w in 1..=8 {
+        ctx.next_block_id_mixin(w as u64);
+        if ctx.list_item("width", &format!("{}{w}", marker(w == width))) {
+            new_width = Some(w);
+        }
+        ctx.attr_padding(Rect::two(0, 1));
+    }
+    ctx.block_end();
+
+    ctx.block_begin("buttons");
+    ctx.attr_padding(Rect { top: 1, ..Default::default() });
+    let convert = ctx.button("convert", loc(LocId::IndentationConvert));
+    let ok = ctx.button("ok", loc(LocId::Ok));
+    ctx.block_end();
+
+    if ctx.modal_end() || ok {
+        state.wants_indentation = false;
+        return;
+    }
+
+    let mut tb = doc.buffer.borrow_mut();
+    if let Some(kind) = choice {
+        tb.set_indent_with_tabs(kind);
+    }
+    if let Some(w) = new_width {
+        tb.set_tab_size(w);
+    }
+    if convert {
+        tb.reindent();
+        state.wants_indentation = false;
+    }
+}
+
+fn close_encoding_change(state: &mut State) {
+    state.wants_encoding_change = StateEncodingChange::None;
+    state.encoding_picker_entries = None;
+}
+
+fn line_endings_label(kind: LineEndings) -> &'static str {
+    match kind {
+        LineEndings::Lf => "LF",
+        LineEndings::Crlf => "CRLF",
+        LineEndings::Mixed => "LF/CRLF",
+    }
+}
+
+fn indentation_label(tabs: bool, width: CoordType) -> String {
+    let kind = if tabs { LocId::IndentationTabs } else { LocId::IndentationSpaces };
+    format!("{}: {width}", loc(kind))
+}
+
+fn encoding_label(encoding: &str, bom: bool) -> String {
+    if bom { format!("{encoding} BOM") } else { encoding.to_string() }
+}
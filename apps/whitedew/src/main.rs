@@ -1,33 +1,54 @@
 #![feature(allocator_api)]
 
 mod documents;
+mod draw_dialogs;
+mod draw_editor;
+mod draw_filepicker;
+mod draw_menubar;
+mod draw_search;
+mod draw_statusbar;
 mod localization;
 mod state;
 
-use crate::state::{DisplayablePathBuf, FormatApperr, State, StateFilePicker};
+use crate::documents::DocumentManager;
+use crate::draw_dialogs::{
+    draw_dialog_about, draw_dialog_error, draw_dialog_goto_file, draw_dialog_unsaved_changes,
+    draw_handle_clipboard_change,
+};
+use crate::draw_editor::{draw_editor, draw_handle_shortcuts};
+use crate::draw_filepicker::draw_file_picker;
+use crate::draw_menubar::draw_menubar;
+use crate::draw_search::draw_search;
+use crate::draw_statusbar::{
+    draw_dialog_encoding_change, draw_dialog_indentation, draw_dialog_line_endings, draw_statusbar,
+};
+use crate::state::{
+    DisplayablePathBuf, FormatApperr, State, StateClose, StateEncodingChange, StateFilePicker, StateSearchKind,
+};
 use localization::{loc, LocId};
 use std::{
     borrow::Cow,
     env,
+    ffi::OsStr,
     path::{Path, PathBuf},
     process,
     time::Duration,
 };
-use stdext::{
-    arena::{self, Arena, ArenaString, scratch_arena},
-    arena_format,
-};
+use stdext::arena::{self, Arena, ArenaString, scratch_arena};
+#[cfg(feature = "debug-latency")]
+use {std::fmt::Write as _, stdext::arena_format};
 use whitedew_core::{
     apperr,
     base64,
     framebuffer::{self, IndexedColor},
-    helpers::{CoordType, MEBI},
+    helpers::{CoordType, GIBI, KIBI, MEBI, Point},
+    icu,
     input,
     oklab::StraightRgba,
     path,
     sys,
     unicode,
-    tui::{ModifierTranslations, Tui},
+    tui::{Context, ModifierTranslations, Tui},
     vt::{self, Token},
 };
 
@@ -55,8 +76,16 @@ fn run() -> apperr::Result<()> {
     let _sys_deinit = sys::init();
     arena::init(SCRATCH_ARENA_CAPACITY)?;
     localization::init();
+    icu::configure(icu::IcuConfig {
+        icuuc_soname: env!("EDIT_CFG_ICUUC_SONAME"),
+        icui18n_soname: env!("EDIT_CFG_ICUI18N_SONAME"),
+        export_prefix: env!("EDIT_CFG_ICU_EXPORT_PREFIX"),
+        export_suffix: env!("EDIT_CFG_ICU_EXPORT_SUFFIX"),
+        renaming_auto_detect: cfg!(edit_icu_renaming_auto_detect),
+    });
 
     let mut state = State::new()?;
+    handle_env(&mut state);
     if handle_args(&mut state)? {
         return Ok(());
     }
@@ -99,25 +128,37 @@ fn run() -> apperr::Result<()> {
 
     sys::inject_window_size_into_stdin();
 
+    #[cfg(feature = "debug-latency")]
     let mut last_latency_width = 0;
 
     loop {
+        #[cfg(feature = "debug-latency")]
         let time_beg;
+        #[cfg(feature = "debug-latency")]
         let mut passes;
+        let mut events = 0usize;
+        let timer_fired;
 
         // Process a batch of input.
         {
             let scratch = scratch_arena(None);
             let read_timeout = vt_parser.read_timeout().min(tui.read_timeout());
             let Some(input) = sys::read_stdin(&scratch, read_timeout) else {
+                // stdin is gone, e.g. because the terminal was closed.
+                // There's no one left to draw for, so we have to quit.
                 break;
             };
 
+            #[cfg(feature = "debug-latency")]
             {
                 time_beg = std::time::Instant::now();
                 passes = 0usize;
             }
 
+            // An empty read means that the timeout was reached. This either completes a lone
+            // ESC keypress in the VT parser or it's a timer that the TUI asked for.
+            timer_fired = input.is_empty();
+
             let vt_iter = vt_parser.parse(&input);
             let mut input_iter = input_parser.parse(vt_iter);
 
@@ -128,9 +169,11 @@ fn run() -> apperr::Result<()> {
 
                 draw(&mut ctx, &mut state);
 
+                #[cfg(feature = "debug-latency")]
                 {
                     passes += 1;
                 }
+                events += more as usize;
 
                 more
             } {}
@@ -143,6 +186,7 @@ fn run() -> apperr::Result<()> {
 
             draw(&mut ctx, &mut state);
 
+            #[cfg(feature = "debug-latency")]
             {
                 passes += 1;
             }
@@ -152,6 +196,11 @@ fn run() -> apperr::Result<()> {
             break;
         }
 
+        // The read only contained the beginning of a VT sequence. Wait for the rest of it.
+        if events == 0 && !timer_fired {
+            continue;
+        }
+
         // Render the UI and write it to the terminal.
         {
             let scratch = scratch_arena(None);
@@ -163,6 +212,12 @@ fn run() -> apperr::Result<()> {
                 write_osc_clipboard(&mut tui, &mut state, &mut output);
             }
 
+            // Nothing on the screen changed.
+            if output.is_empty() {
+                continue;
+            }
+
+            #[cfg(feature = "debug-latency")]
             {
                 // Print the number of passes and latency in the top right corner.
                 let time_end = std::time::Instant::now();
@@ -204,7 +259,7 @@ fn run() -> apperr::Result<()> {
                     tui.size().width - cols - padding + 1,
                     "",
                     padding as usize,
-                    status
+                    status.as_str()
                 );
 
                 last_latency_width = cols;
@@ -212,9 +267,8 @@ fn run() -> apperr::Result<()> {
 
             sys::write_stdout(&output);
         }
-        break;
     }
-    
+
     Ok(())
 }
 
@@ -222,10 +276,12 @@ fn run() -> apperr::Result<()> {
 fn handle_args(state: &mut State) -> apperr::Result<bool> {
     let scratch = scratch_arena(None);
     // 읽을 파일 리스트 저장소
-    let mut paths: Vec<PathBuf, &Arena> = Vec::new_in(&*scratch);
+    let mut paths: Vec<(PathBuf, Option<Point>), &Arena> = Vec::new_in(&*scratch);
     let cwd = env::current_dir()?;
     let mut dir = None;
     let mut parse_args = true;
+    // The line of a preceding `+LINE` argument. Applies to the next file.
+    let mut goto_line = None;
 
     // The best CLI argument parser in the world.
     for arg in env::args_os().skip(1) {
@@ -246,6 +302,10 @@ fn handle_args(state: &mut State) -> apperr::Result<bool> {
                 print_version();
                 return Ok(true);
             }
+            if let Some(line) = parse_plus_line(&arg) {
+                goto_line = Some(line);
+                continue;
+            }
         }
 
         let p = cwd.join(Path::new(&arg));
@@ -253,13 +313,22 @@ fn handle_args(state: &mut State) -> apperr::Result<bool> {
         if p.is_dir() {
             state.wants_file_picker = StateFilePicker::Open;
             dir = Some(p);
-        } else {
-            paths.push(p);
+            continue;
         }
+
+        let (p, goto) = if parse_args {
+            let (p, goto) = DocumentManager::parse_filename_goto(&p, Path::exists);
+            (p.to_path_buf(), goto)
+        } else {
+            (p, None)
+        };
+        let goto = goto.or(goto_line.take().map(|y| Point { x: 0, y }));
+        paths.push((p, goto));
     }
 
-    for p in &paths {
-        state.documents.add_file_path(p)?;
+    for (p, goto) in &paths {
+        // Ctrl+C still works at this point, because the terminal isn't in raw mode yet.
+        state.documents.add_file_path(p, *goto, &mut |_| true)?;
     }
 
     if let Some(mut file) = sys::open_stdin_if_redirected() {
@@ -272,7 +341,7 @@ fn handle_args(state: &mut State) -> apperr::Result<bool> {
         state.documents.add_untitled()?;
     }
 
-    if dir.is_none() && let Some(parent) = paths.last().and_then(|p| p.parent()) {
+    if dir.is_none() && let Some(parent) = paths.last().and_then(|(p, _)| p.parent()) {
         // dir이 없고, 읽을 파일이 있는 경우는 읽을 파일의 parent dir을 dir로 설정
         dir = Some(parent.to_path_buf());
     }
@@ -281,15 +350,57 @@ fn handle_args(state: &mut State) -> apperr::Result<bool> {
     Ok(false)
 }
 
+/// Applies the settings that can be made with environment variables.
+/// Invalid values are ignored, keeping the defaults.
+fn handle_env(state: &mut State) {
+    let size = |key| env::var(key).ok().and_then(|val| parse_size(&val));
+    if let Some(size) = size("WD_CLIPBOARD_WARN_SIZE") {
+        state.osc_clipboard_warn_size = size;
+    }
+    if let Some(size) = size("WD_CLIPBOARD_MAX_SIZE") {
+        state.osc_clipboard_max_size = size;
+    }
+}
+
+/// Parses a size in bytes, with an optional `K`, `M` or `G` suffix for KiB, MiB and GiB.
+fn parse_size(val: &str) -> Option<usize> {
+    let val = val.trim();
+    let (digits, unit) = match val.as_bytes().last()?.to_ascii_uppercase() {
+        b'K' => (&val[..val.len() - 1], KIBI),
+        b'M' => (&val[..val.len() - 1], MEBI),
+        b'G' => (&val[..val.len() - 1], GIBI),
+        _ => (val, 1),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+/// Parses the `+LINE` argument that many tools pass to `$EDITOR`. Returns the 0-based line.
+fn parse_plus_line(arg: &OsStr) -> Option<CoordType> {
+    let digits = arg.to_str()?.strip_prefix('+')?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let line = digits.parse::<CoordType>().ok()?;
+    Some((line - 1).max(0))
+}
+
 fn print_help() {
     sys::write_stdout(concat!(
-        "Usage: wd [OPTIONS] [FILE[:LINE[:COLUMN]]]\n",
+        "Usage: wd [OPTIONS] [+LINE] [FILE[:LINE[:COLUMN]]]\n",
         "Options:\n",
         "    -h, --help       Print this help message\n",
         "    -v, --version    Print the version number\n",
         "\n",
         "Arguments:\n",
         "    FILE[:LINE[:COLUMN]]    The file to open, optionally with line and column (e.g., foo.txt:123:45)\n",
+        "    +LINE                   The line to go to in the following file (e.g., +123 foo.txt)\n",
+        "\n",
+        "Environment:\n",
+        "    WD_CLIPBOARD_WARN_SIZE  Ask before sharing copies this large with the terminal (default: 128K)\n",
+        "    WD_CLIPBOARD_MAX_SIZE   Never share copies this large with the terminal (default: 700K)\n",
     ));
 }
 
@@ -298,7 +409,44 @@ fn print_version() {
 }
 
 fn draw(ctx: &mut Context, state: &mut State) {
+    draw_menubar(ctx, state);
+    if state.wants_search.kind != StateSearchKind::Hidden {
+        draw_search(ctx, state);
+    }
+    draw_editor(ctx, state);
+    draw_statusbar(ctx, state);
+
+    if state.wants_file_picker != StateFilePicker::None {
+        draw_file_picker(ctx, state);
+    }
+    if state.wants_indentation {
+        draw_dialog_indentation(ctx, state);
+    }
+    if state.wants_line_endings {
+        draw_dialog_line_endings(ctx, state);
+    }
+    if state.wants_encoding_change != StateEncodingChange::None {
+        draw_dialog_encoding_change(ctx, state);
+    }
+    if state.wants_goto_file {
+        draw_dialog_goto_file(ctx, state);
+    }
+    if state.wants_about {
+        draw_dialog_about(ctx, state);
+    }
+    // Untitled documents are saved via the file picker, which takes precedence.
+    if state.wants_close != StateClose::None && state.wants_file_picker == StateFilePicker::None {
+        draw_dialog_unsaved_changes(ctx, state);
+    }
+    if state.error.is_some() {
+        draw_dialog_error(ctx, state);
+    }
 
+    draw_handle_shortcuts(ctx, state);
+
+    if !state.osc_clipboard_sync && ctx.clipboard_mut().wants_host_sync() {
+        draw_handle_clipboard_change(ctx, state);
+    }
 }
 
 struct RestoreModes;
@@ -524,6 +672,7 @@ fn write_osc_clipboard(tui: &mut Tui, state: &mut State, output: &mut ArenaStrin
         output.push_str("\x1b\\");
     }
 
+    clipboard.mark_as_synced();
     state.osc_clipboard_sync = false;
 }
 
@@ -546,3 +695,46 @@ fn sanitize_control_chars(text: &str) -> Cow<'_, str> {
         Cow::Borrowed(text)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plus_line() {
+        let cases: &[(&str, Option<CoordType>)] = &[
+            ("+1", Some(0)),
+            ("+123", Some(122)),
+            ("+0", Some(0)),
+            ("+", None),
+            ("+-1", None),
+            ("+1a", None),
+            ("1", None),
+            ("++1", None),
+            ("+99999999999999999999", None),
+        ];
+        for &(arg, expected) in cases {
+            assert_eq!(parse_plus_line(OsStr::new(arg)), expected, "{arg}");
+        }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        let cases: &[(&str, Option<usize>)] = &[
+            ("0", Some(0)),
+            ("1234", Some(1234)),
+            ("128K", Some(128 * KIBI)),
+            ("1m", Some(MEBI)),
+            (" 2G ", Some(2 * GIBI)),
+            ("", None),
+            ("K", None),
+            ("1.5M", None),
+            ("-1", None),
+            ("12KB", None),
+            ("99999999999999999999G", None),
+        ];
+        for &(val, expected) in cases {
+            assert_eq!(parse_size(val), expected, "{val:?}");
+        }
+    }
+}
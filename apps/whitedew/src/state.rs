@@ -1,7 +1,25 @@
 
 use crate::documents::DocumentManager;
-use std::{borrow::Cow, mem, path::PathBuf};
-use whitedew_core::{apperr, icu, oklab::StraightRgba, sys};
+use crate::localization::{loc, LocId};
+use std::{
+    borrow::Cow,
+    mem,
+    path::{Path, PathBuf},
+};
+use whitedew_core::{
+    apperr,
+    buffer::{RcTextBuffer, SearchOptions},
+    helpers::KIBI,
+    icu,
+    oklab::StraightRgba,
+    sys,
+};
+
+/// The default for [`State::osc_clipboard_warn_size`].
+pub const OSC_CLIPBOARD_WARN_SIZE: usize = 128 * KIBI;
+/// The default for [`State::osc_clipboard_max_size`]. By default, tmux drops OSC sequences
+/// longer than 1 MiB, which base64 encoding reaches at 768 KiB of text.
+pub const OSC_CLIPBOARD_MAX_SIZE: usize = 700 * KIBI;
 
 #[repr(transparent)]
 pub struct FormatApperr(apperr::Error);
@@ -15,7 +33,10 @@ impl From<apperr::Error> for FormatApperr {
 impl std::fmt::Display for FormatApperr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.0 {
-            //apperr::APP_ICU_MISSING => f.write_str(loc(LocId::ErrorIcuMissing)),
+            apperr::APP_ICU_MISSING => f.write_str(loc(LocId::ErrorIcuMissing)),
+            apperr::APP_CANCELLED => f.write_str(loc(LocId::ErrorOperationCancelled)),
+            apperr::APP_FILE_IS_DIRECTORY => f.write_str(loc(LocId::ErrorFileIsDirectory)),
+            apperr::APP_REGEX_INVALID => f.write_str(loc(LocId::ErrorRegexInvalid)),
             apperr::Error::App(code) => write!(f, "Unknown app error code: {code}"),
             apperr::Error::Icu(code) => icu::apperr_format(f, code),
             apperr::Error::Sys(code) => sys::apperr_format(f, code),
@@ -31,6 +52,39 @@ pub enum StateFilePicker {
     SaveAsShown, // Transitioned from SaveAs
 }
 
+/// Set when the user asked to close the active document or to exit,
+/// but there are unsaved changes that they have to decide about first.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum StateClose {
+    None,
+    Document,
+    Exit,
+}
+
+/// The steps of changing the encoding from the status bar: first the choice
+/// between reopening and converting the file, then the list of encodings.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum StateEncodingChange {
+    None,
+    Prompt,
+    Reopen,
+    Convert,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum StateSearchKind {
+    Hidden,
+    Search,
+    Replace,
+}
+
+#[derive(Clone, Copy)]
+pub struct StateSearch {
+    pub kind: StateSearchKind,
+    /// Set to move the focus into the search bar on the next frame.
+    pub focus: bool,
+}
+
 pub struct DisplayablePathBuf {
     value: PathBuf,
     str: Cow<'static, str>,
@@ -42,6 +96,14 @@ impl DisplayablePathBuf {
         let str = unsafe { mem::transmute::<Cow<'_, str>, Cow<'_, str>>(str) };
         Self { value, str }
     }
+
+    pub fn as_path(&self) -> &Path {
+        &self.value
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.str
+    }
 }
 
 impl Default for DisplayablePathBuf {
@@ -61,12 +123,46 @@ pub struct State {
     pub documents: DocumentManager,
     pub wants_file_picker: StateFilePicker,
     pub file_picker_pending_dir: DisplayablePathBuf,
+    pub file_picker_pending_name: String,
+    /// The contents of `file_picker_pending_dir`: directories first, each with a trailing `/`,
+    /// then files. `None` if they need to be read (again).
+    pub file_picker_entries: Option<Vec<DisplayablePathBuf>>,
+    pub file_picker_show_hidden: bool,
+    /// Set when Save As targets an existing file, until the user confirmed to overwrite it.
+    pub file_picker_overwrite_warning: Option<PathBuf>,
+    pub wants_indentation: bool,
+    pub wants_line_endings: bool,
+    pub wants_encoding_change: StateEncodingChange,
+    /// The encodings to choose from. `None` until the list is shown.
+    pub encoding_picker_entries: Option<Vec<&'static str>>,
     pub menubar_color_bg: StraightRgba,
     pub menubar_color_fg: StraightRgba,
+    pub wants_search: StateSearch,
+    pub search_needle: String,
+    pub search_replacement: String,
+    pub search_options: SearchOptions,
+    /// Set if the needle isn't a valid regex.
+    pub search_error: Option<apperr::Error>,
+    pub wants_about: bool,
+    pub wants_goto_file: bool,
+    pub wants_close: StateClose,
+    /// The documents whose changes the user chose to discard while exiting. They stay open
+    /// until the exit goes through, so that canceling it later keeps every document.
+    pub close_discarded: Vec<RcTextBuffer>,
+    /// An error to show to the user, e.g. because saving failed.
+    pub error: Option<apperr::Error>,
     pub exit: bool,
     pub osc_clipboard_sync: bool,
+    /// Copies of at least this many bytes ask the user before they're sent to the host terminal via OSC 52.
+    /// Can be set with the `WD_CLIPBOARD_WARN_SIZE` environment variable.
+    pub osc_clipboard_warn_size: usize,
+    /// Copies of at least this many bytes stay in the internal clipboard. Terminals like tmux
+    /// silently truncate huge OSC 52 sequences, which would make pasting them elsewhere confusing.
+    /// Can be set with the `WD_CLIPBOARD_MAX_SIZE` environment variable.
+    pub osc_clipboard_max_size: usize,
+    /// Set when the user chose "Always" in the large clipboard warning.
+    pub osc_clipboard_always_send: bool,
     pub osc_title_file_status: OscTitleFileStatus,
-    pub documents: DocumentManager,
 }
 
 impl State {
@@ -75,12 +171,32 @@ impl State {
             documents: Default::default(),
             wants_file_picker: StateFilePicker::None,
             file_picker_pending_dir: Default::default(),
+            file_picker_pending_name: String::new(),
+            file_picker_entries: None,
+            file_picker_show_hidden: false,
+            file_picker_overwrite_warning: None,
+            wants_indentation: false,
+            wants_line_endings: false,
+            wants_encoding_change: StateEncodingChange::None,
+            encoding_picker_entries: None,
             menubar_color_bg: StraightRgba::zero(),
             menubar_color_fg: StraightRgba::zero(),
+            wants_search: StateSearch { kind: StateSearchKind::Hidden, focus: false },
+            search_needle: String::new(),
+            search_replacement: String::new(),
+            search_options: Default::default(),
+            search_error: None,
+            wants_about: false,
+            wants_goto_file: false,
+            wants_close: StateClose::None,
+            close_discarded: Vec::new(),
+            error: None,
             exit: false,
             osc_clipboard_sync: false,
+            osc_clipboard_warn_size: OSC_CLIPBOARD_WARN_SIZE,
+            osc_clipboard_max_size: OSC_CLIPBOARD_MAX_SIZE,
+            osc_clipboard_always_send: false,
             osc_title_file_status: Default::default(),
-            documents: Default::default(),
         })
     }
 }
@@ -0,0 +1,180 @@
+//! Runs the editor in a pseudo terminal and drives its event loop with scripted input.
+
+#![cfg(unix)]
+
+use std::{
+    fs::File,
+    io::{Read as _, Write as _},
+    os::fd::{FromRawFd as _, OwnedFd},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Editor {
+    child: Child,
+    input: File,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Editor {
+    /// Spawns the editor on the slave side of a new pseudo terminal of the given size.
+    fn spawn(width: u16, height: u16) -> Self {
+        let (master, slave) = unsafe {
+            let mut master = -1;
+            let mut slave = -1;
+            let winsz = libc::winsize { ws_row: height, ws_col: width, ws_xpixel: 0, ws_ypixel: 0 };
+            let ret = libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &winsz);
+            assert_eq!(ret, 0, "openpty failed");
+            (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+        };
+
+        let child = Command::new(env!("CARGO_BIN_EXE_whitedew"))
+            .stdin(Stdio::from(slave.try_clone().unwrap()))
+            .stdout(Stdio::from(slave.try_clone().unwrap()))
+            .stderr(Stdio::from(slave))
+            .env("LANG", "en_US.UTF-8")
+            .spawn()
+            .unwrap();
+
+        // The editor blocks once the pty buffer is full, so we have to keep draining it.
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut reader = File::from(master.try_clone().unwrap());
+        let sink = output.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            // Reading fails with EIO once the editor exits and the slave is closed.
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                sink.lock().unwrap().extend_from_slice(&buf[..n]);
+            }
+        });
+
+        Self { child, input: File::from(master), output }
+    }
+
+    fn send(&mut self, input: &str) {
+        self.input.write_all(input.as_bytes()).unwrap();
+    }
+
+    /// Waits until the output (after `from`) contains `needle`. Returns the output length.
+    fn wait_for_output(&self, from: usize, needle: &[u8]) -> usize {
+        let beg = Instant::now();
+        loop {
+            {
+                let output = self.output.lock().unwrap();
+                if output.len() > from && output[from..].windows(needle.len()).any(|w| w == needle) {
+                    return output.len();
+                }
+            }
+            assert!(beg.elapsed() < TIMEOUT, "timed out waiting for {:?}", String::from_utf8_lossy(needle));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Waits for a frame (after `from`) that leaves the text cursor visible. The framebuffer hides
+    /// the cursor while drawing and shows it again at the very end. Returns the output length.
+    fn wait_for_frame(&self, from: usize) -> usize {
+        self.wait_for_output(from, b"\x1b[?25h")
+    }
+
+    fn output_len(&self) -> usize {
+        self.output.lock().unwrap().len()
+    }
+
+    fn wait_for_exit(&mut self) -> ExitStatus {
+        let beg = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status;
+            }
+            assert!(beg.elapsed() < TIMEOUT, "the editor didn't exit");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.child.try_wait().unwrap().is_none()
+    }
+}
+
+impl Drop for Editor {
+    fn drop(&mut self) {
+        _ = self.child.kill();
+        _ = self.child.wait();
+    }
+}
+
+#[test]
+fn test_event_loop_runs_until_exit() {
+    let mut editor = Editor::spawn(80, 24);
+
+    // On startup, the editor queries the terminal's colors and capabilities.
+    // The DA1 response ends that phase. We don't answer the rest and use the defaults.
+    editor.wait_for_output(0, b"\x1b[c");
+    editor.send("\x1b[?62c");
+
+    let mut seen = editor.wait_for_frame(0);
+
+    // Regular input and timeouts must not end the loop.
+    editor.send("hello");
+    seen = editor.wait_for_output(seen, b"hello");
+    editor.send("\x1b");
+    thread::sleep(Duration::from_millis(200));
+    assert!(editor.is_running(), "the editor exited without being asked to");
+
+    // A resize redraws the screen.
+    editor.send("\x1b[8;30;100t");
+    seen = editor.wait_for_frame(seen);
+
+    // An incomplete sequence must be buffered until the rest of it arrives.
+    editor.send("\x1b[8;2");
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(editor.output_len(), seen, "rendered before the sequence was complete");
+    editor.send("0;60t");
+    editor.wait_for_frame(seen);
+    assert!(editor.is_running());
+
+    // Ctrl+Q asks about the unsaved "hello" first. Tab + Enter picks "Don't Save" and exits.
+    editor.send("\x11");
+    editor.wait_for_output(seen, b"Don't Save");
+    editor.send("\t\r");
+    let status = editor.wait_for_exit();
+    assert!(status.success(), "{status:?}");
+
+    // The terminal modes get restored on exit.
+    editor.wait_for_output(0, b"\x1b[?1049l");
+    assert!(editor.output_len() > 0);
+}
+
+#[test]
+fn test_menubar() {
+    let mut editor = Editor::spawn(80, 24);
+    editor.wait_for_output(0, b"\x1b[c");
+    editor.send("\x1b[?62c");
+    let mut seen = editor.wait_for_frame(0);
+
+    // Alt+F opens the File menu, with the shortcuts next to the entries.
+    editor.send("\x1bf");
+    seen = editor.wait_for_output(seen, b"Ctrl+Shift+S");
+
+    // Escape closes it again and returns the focus (and cursor) to the editor.
+    editor.send("\x1b");
+    seen = editor.wait_for_frame(seen);
+    assert!(editor.is_running());
+
+    // Left wraps around to the Help menu and Right back to the File menu,
+    // where "x" activates File > Exit.
+    editor.send("\x1bf");
+    seen = editor.wait_for_output(seen, b"Ctrl+Q");
+    editor.send("\x1b[D");
+    // "About", with the "A" underlined as its accelerator.
+    seen = editor.wait_for_output(seen, b"bout");
+    editor.send("\x1b[C");
+    editor.wait_for_output(seen, b"Ctrl+Q");
+    editor.send("x");
+    let status = editor.wait_for_exit();
+    assert!(status.success(), "{status:?}");
+}
@@ -16,6 +16,11 @@ impl Arena {
         Self::Owned { arena: release::Arena::empty() }
     }
 
+    /// Creates an arena of its own, outside of the scratch arenas. Useful for tests.
+    pub fn new(capacity: usize) -> Result<Self, AllocError> {
+        Ok(Self::Owned { arena: release::Arena::new(capacity)? })
+    }
+
     #[inline]
     pub(super) fn delegate_target_unchecked(&self) -> &release::Arena {
         match self {
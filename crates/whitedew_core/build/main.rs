@@ -0,0 +1,201 @@
+
+//! Compiles `build/ucd.txt` into the lookup tables of `unicode::tables`.
+//! To update it to a new version of Unicode, run `build/ucd.py` on the Unicode Character Database.
+//!
+//! Every code point gets one byte: the grapheme cluster break property in the low 4 bits,
+//! and the width class in the 2 bits above. They're stored in a three-stage table:
+//! the first stage is indexed by the top bits of the code point and points to a block
+//! of the second stage, which in turn points to a block of the third stage, which holds
+//! the values. Identical blocks are stored only once, which is what makes it compact.
+//! The word break property gets a three-stage table of its own, so that the one that
+//! measuring text needs stays small.
+
+use std::{collections::HashMap, env, fmt::Write as _, fs};
+
+/// The values of the Grapheme_Cluster_Break column, in the order of their numbering.
+const GRAPHEME_BREAKS: [(&str, &str); 15] = [
+    ("Other", "GB_OTHER"),
+    ("CR", "GB_CR"),
+    ("LF", "GB_LF"),
+    ("Control", "GB_CONTROL"),
+    ("Extend", "GB_EXTEND"),
+    ("ZWJ", "GB_ZWJ"),
+    ("Regional_Indicator", "GB_REGIONAL_INDICATOR"),
+    ("Prepend", "GB_PREPEND"),
+    ("SpacingMark", "GB_SPACING_MARK"),
+    ("L", "GB_L"),
+    ("V", "GB_V"),
+    ("T", "GB_T"),
+    ("LV", "GB_LV"),
+    ("LVT", "GB_LVT"),
+    ("Extended_Pictographic", "GB_EXTENDED_PICTOGRAPHIC"),
+];
+
+/// The values of the Width column, in the order of their numbering.
+const WIDTHS: [(&str, &str); 4] =
+    [("0", "WIDTH_ZERO"), ("1", "WIDTH_NARROW"), ("2", "WIDTH_WIDE"), ("A", "WIDTH_AMBIGUOUS")];
+
+/// The values of the Word_Break column, in the order of their numbering.
+const WORD_BREAKS: [(&str, &str); 19] = [
+    ("Other", "WB_OTHER"),
+    ("CR", "WB_CR"),
+    ("LF", "WB_LF"),
+    ("Newline", "WB_NEWLINE"),
+    ("Extend", "WB_EXTEND"),
+    ("ZWJ", "WB_ZWJ"),
+    ("Regional_Indicator", "WB_REGIONAL_INDICATOR"),
+    ("Format", "WB_FORMAT"),
+    ("Katakana", "WB_KATAKANA"),
+    ("Hebrew_Letter", "WB_HEBREW_LETTER"),
+    ("ALetter", "WB_ALETTER"),
+    ("Single_Quote", "WB_SINGLE_QUOTE"),
+    ("Double_Quote", "WB_DOUBLE_QUOTE"),
+    ("MidNumLet", "WB_MID_NUM_LET"),
+    ("MidLetter", "WB_MID_LETTER"),
+    ("MidNum", "WB_MID_NUM"),
+    ("Numeric", "WB_NUMERIC"),
+    ("ExtendNumLet", "WB_EXTEND_NUM_LET"),
+    ("WSegSpace", "WB_WSEG_SPACE"),
+];
+
+const WIDTH_SHIFT: u8 = 4;
+/// The number of code points covered by a block of the third stage.
+const STAGE3_BITS: usize = 6;
+/// The number of third stage blocks covered by a block of the second stage.
+const STAGE2_BITS: usize = 4;
+
+const CODE_POINTS: usize = 0x110000;
+
+fn main() {
+    let ucd_path = "build/ucd.txt";
+    println!("cargo::rerun-if-changed={ucd_path}");
+
+    let ucd = fs::read_to_string(ucd_path).unwrap();
+    let (values, word_breaks) = parse(&ucd);
+    let contents = generate(&values, &word_breaks);
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(format!("{out_dir}/unicode_tables.rs"), contents).unwrap();
+}
+
+/// Returns the grapheme cluster break and width of each code point, and its word break property.
+fn parse(ucd: &str) -> (Vec<u8>, Vec<u8>) {
+    let default = 1 << WIDTH_SHIFT;
+    let mut values = vec![default; CODE_POINTS];
+    let mut word_breaks = vec![0; CODE_POINTS];
+
+    for (i, line) in ucd.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = || panic!("ucd.txt:{}: expected `code points ; break ; width ; word break`", i + 1);
+        let mut fields = line.split(';').map(str::trim);
+        let (Some(range), Some(gb), Some(width), Some(wb), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            error()
+        };
+
+        let (beg, end) = range.split_once("..").unwrap_or((range, range));
+        let beg = usize::from_str_radix(beg, 16).unwrap_or_else(|_| error());
+        let end = usize::from_str_radix(end, 16).unwrap_or_else(|_| error());
+        let gb = GRAPHEME_BREAKS.iter().position(|&(name, _)| name == gb).unwrap_or_else(|| error());
+        let width = WIDTHS.iter().position(|&(name, _)| name == width).unwrap_or_else(|| error());
+        let wb = WORD_BREAKS.iter().position(|&(name, _)| name == wb).unwrap_or_else(|| error());
+        if beg > end || end >= CODE_POINTS {
+            error();
+        }
+
+        values[beg..=end].fill(gb as u8 | (width as u8) << WIDTH_SHIFT);
+        word_breaks[beg..=end].fill(wb as u8);
+    }
+
+    (values, word_breaks)
+}
+
+/// Splits `values` into blocks of `1 << bits` and deduplicates them.
+/// Returns the unique blocks concatenated and the index of the block for each block of `values`.
+fn compress<T: Copy + Eq + std::hash::Hash>(values: &[T], bits: usize) -> (Vec<T>, Vec<usize>) {
+    let mut unique = Vec::new();
+    let mut seen: HashMap<&[T], usize> = HashMap::new();
+    let mut indices = Vec::new();
+
+    for block in values.chunks(1 << bits) {
+        let next = seen.len();
+        let idx = *seen.entry(block).or_insert_with(|| {
+            unique.extend_from_slice(block);
+            next
+        });
+        indices.push(idx);
+    }
+
+    (unique, indices)
+}
+
+fn generate(values: &[u8], word_breaks: &[u8]) -> String {
+    let mut out = String::new();
+    _ = writeln!(out, "// Generated by build/main.rs from build/ucd.txt. Do not edit.");
+    _ = writeln!(out);
+    for (i, (_, name)) in GRAPHEME_BREAKS.iter().enumerate() {
+        _ = writeln!(out, "pub(super) const {name}: u8 = {i};");
+    }
+    for (i, (_, name)) in WIDTHS.iter().enumerate() {
+        _ = writeln!(out, "pub(super) const {name}: u8 = {i};");
+    }
+    _ = writeln!(out, "pub(super) const WIDTH_SHIFT: u8 = {WIDTH_SHIFT};");
+    for (i, (_, name)) in WORD_BREAKS.iter().enumerate() {
+        _ = writeln!(out, "pub(super) const {name}: u8 = {i};");
+    }
+    _ = writeln!(out);
+
+    _ = writeln!(out, "/// Returns the grapheme cluster break property of `c` in the low bits,");
+    _ = writeln!(out, "/// and its width class shifted up by [`WIDTH_SHIFT`].");
+    write_lookup(&mut out, "ucd_lookup", "", values);
+    _ = writeln!(out, "/// Returns the word break property of `c`.");
+    write_lookup(&mut out, "word_break_lookup", "WB_", word_breaks);
+    out
+}
+
+/// Writes the three-stage table for `values`, with `prefix` in front of the names of the stages,
+/// and the function `name` that looks up a code point in it.
+fn write_lookup(out: &mut String, name: &str, prefix: &str, values: &[u8]) {
+    let (stage3, stage2) = compress(values, STAGE3_BITS);
+    let (stage2, stage1) = compress(&stage2, STAGE2_BITS);
+
+    _ = writeln!(out, "#[inline]");
+    _ = writeln!(out, "pub(super) fn {name}(c: char) -> u8 {{");
+    _ = writeln!(out, "    let cp = c as usize;");
+    _ = writeln!(out, "    let i = {prefix}STAGE1[cp >> {}] as usize;", STAGE3_BITS + STAGE2_BITS);
+    _ = writeln!(
+        out,
+        "    let j = {prefix}STAGE2[(i << {STAGE2_BITS}) | ((cp >> {STAGE3_BITS}) & {})] as usize;",
+        (1 << STAGE2_BITS) - 1
+    );
+    _ = writeln!(out, "    {prefix}STAGE3[(j << {STAGE3_BITS}) | (cp & {})]", (1 << STAGE3_BITS) - 1);
+    _ = writeln!(out, "}}");
+    _ = writeln!(out);
+
+    let stage3: Vec<_> = stage3.iter().map(|&v| v as usize).collect();
+    write_table(out, &format!("{prefix}STAGE1"), &stage1);
+    write_table(out, &format!("{prefix}STAGE2"), &stage2);
+    write_table(out, &format!("{prefix}STAGE3"), &stage3);
+}
+
+/// Writes `values` as a static array of the smallest unsigned type that fits them.
+fn write_table(out: &mut String, name: &str, values: &[usize]) {
+    let max = values.iter().copied().max().unwrap_or(0);
+    let ty = if max <= u8::MAX as usize { "u8" } else { "u16" };
+    assert!(max <= u16::MAX as usize);
+
+    _ = write!(out, "static {name}: [{ty}; {}] = [", values.len());
+    for (i, v) in values.iter().enumerate() {
+        if i % 32 == 0 {
+            out.push_str("\n   ");
+        }
+        _ = write!(out, " {v},");
+    }
+    _ = writeln!(out, "\n];");
+    _ = writeln!(out);
+}
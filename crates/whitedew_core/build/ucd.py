@@ -0,0 +1,122 @@
+#!/usr/bin/env python3
+"""Generates build/ucd.txt from the Unicode Character Database.
+
+Usage: python3 build/ucd.py <ucd-dir>
+
+<ucd-dir> is the extracted https://www.unicode.org/Public/<version>/ucd/UCD.zip. The files it needs:
+* auxiliary/GraphemeBreakProperty.txt
+* auxiliary/WordBreakProperty.txt
+* emoji/emoji-data.txt
+* EastAsianWidth.txt
+* extracted/DerivedGeneralCategory.txt
+"""
+
+import os
+import re
+import sys
+
+CODE_POINTS = 0x110000
+
+# Code points that EastAsianWidth.txt doesn't list are N, except for the unassigned
+# ones in these ranges, which are W. See the header of EastAsianWidth.txt.
+WIDE_BY_DEFAULT = [(0x3400, 0x4DBF), (0x4E00, 0x9FFF), (0xF900, 0xFAFF), (0x20000, 0x2FFFD), (0x30000, 0x3FFFD)]
+
+HEADER = """\
+# Grapheme cluster break properties, display widths and word break properties of all code points.
+#
+# Generated by build/ucd.py from the Unicode Character Database {version}. Do not edit.
+# * GraphemeBreakProperty.txt, with Extended_Pictographic from emoji-data.txt folded in,
+#   since no code point has both. See "UAX #29: Unicode Text Segmentation".
+# * EastAsianWidth.txt, reduced to the width in terminal columns. See "UAX #11: East Asian Width".
+#   0 = combining marks and other characters that don't advance, 1 = narrow,
+#   2 = wide (including emoji presentation), A = ambiguous (see `setup_ambiguous_width`).
+# * WordBreakProperty.txt. See "UAX #29: Unicode Text Segmentation".
+#
+# Adjacent ranges with identical values are merged. Code points that aren't listed are Other, 1 wide and Other.
+# build/main.rs compiles this into the lookup tables that src/unicode/tables.rs includes from OUT_DIR.
+#
+# Code points ; Grapheme_Cluster_Break ; Width ; Word_Break
+"""
+
+
+def parse(path, default, only=None):
+    """Reads a UCD file of `code points ; value` lines into a list with one value per code point.
+    If `only` is given, lines with other values are skipped. Returns the list and the UCD version."""
+    values = [default] * CODE_POINTS
+    version = None
+    with open(path, encoding="utf-8") as f:
+        for line in f:
+            if version is None:
+                m = re.search(r"-(\d+\.\d+\.\d+)\.txt", line)
+                if m:
+                    version = m.group(1)
+            line = line.split("#", 1)[0].strip()
+            if not line:
+                continue
+            cps, value = (field.strip() for field in line.split(";")[:2])
+            if only is not None and value != only:
+                continue
+            beg, _, end = cps.partition("..")
+            beg = int(beg, 16)
+            end = int(end, 16) if end else beg
+            values[beg : end + 1] = [value] * (end - beg + 1)
+    return values, version
+
+
+def width(cp, gcb, gc, eaw):
+    # The soft hyphen is invisible unless the line breaks at it, but terminals draw it anyway.
+    if cp == 0xAD:
+        return "A"
+    if gcb in ("Extend", "ZWJ", "V", "T") or gc in ("Mn", "Me"):
+        return "0"
+    # Format characters and the unassigned code points that are reserved for them.
+    if gcb == "Control" and gc in ("Cf", "Cn"):
+        return "0"
+    if eaw in ("W", "F"):
+        return "2"
+    if eaw == "A":
+        return "A"
+    return "1"
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    ucd = sys.argv[1]
+
+    gcbs, version = parse(os.path.join(ucd, "auxiliary", "GraphemeBreakProperty.txt"), "Other")
+    wbs, _ = parse(os.path.join(ucd, "auxiliary", "WordBreakProperty.txt"), "Other")
+    pictographic, _ = parse(os.path.join(ucd, "emoji", "emoji-data.txt"), None, "Extended_Pictographic")
+    gcs, _ = parse(os.path.join(ucd, "extracted", "DerivedGeneralCategory.txt"), "Cn")
+    eaws, _ = parse(os.path.join(ucd, "EastAsianWidth.txt"), "N")
+    for beg, end in WIDE_BY_DEFAULT:
+        for cp in range(beg, end + 1):
+            if gcs[cp] == "Cn" and eaws[cp] == "N":
+                eaws[cp] = "W"
+
+    rows = []
+    for cp in range(CODE_POINTS):
+        gcb = gcbs[cp]
+        if pictographic[cp]:
+            assert gcb == "Other", f"U+{cp:04X} is Extended_Pictographic and {gcb}"
+            gcb = "Extended_Pictographic"
+        value = (gcb, width(cp, gcb, gcs[cp], eaws[cp]), wbs[cp])
+        if rows and rows[-1][2] == value and rows[-1][1] == cp - 1:
+            rows[-1][1] = cp
+        else:
+            rows.append([cp, cp, value])
+
+    out = HEADER.format(version=version)
+    for beg, end, (gcb, w, wb) in rows:
+        if (gcb, w, wb) == ("Other", "1", "Other"):
+            continue
+        cps = f"{beg:04X}" if beg == end else f"{beg:04X}..{end:04X}"
+        out += f"{cps:<14}; {gcb:<21}; {w} ; {wb}\n"
+
+    path = os.path.join(os.path.dirname(os.path.abspath(__file__)), "ucd.txt")
+    with open(path, "w", encoding="utf-8", newline="\n") as f:
+        f.write(out)
+
+
+if __name__ == "__main__":
+    main()
@@ -3,6 +3,12 @@ use crate::sys;
 use std::{alloc::AllocError, io, result};
 
 pub const APP_ICU_MISSING: Error = Error::new_app(0);
+/// The user (or rather a progress callback) cancelled a long running operation.
+pub const APP_CANCELLED: Error = Error::new_app(1);
+/// A file was expected, but the path refers to a directory.
+pub const APP_FILE_IS_DIRECTORY: Error = Error::new_app(2);
+/// A search pattern is not a valid regular expression.
+pub const APP_REGEX_INVALID: Error = Error::new_app(3);
 
 pub type Result<T> = result::Result<T, Error>;
 
@@ -0,0 +1,121 @@
+
+//! Cut, copy and paste between a [`TextBuffer`] and the [`Clipboard`].
+//!
+//! Without a selection, cut and copy take the entire line the cursor is on, as in VS Code.
+//! The clipboard remembers that, and pasting such a line inserts it above the cursor's line,
+//! instead of splitting the line at the cursor.
+
+use super::{TextBuffer, line_endings::convert_newlines};
+use crate::clipboard::Clipboard;
+use std::ops::Range;
+
+impl TextBuffer {
+    /// Copies the selection, or the cursor's line if there's none, to `clipboard`.
+    pub fn copy(&mut self, clipboard: &mut Clipboard) {
+        self.copy_to_clipboard(clipboard);
+    }
+
+    /// Like [`TextBuffer::copy`], but also deletes what was copied.
+    pub fn cut(&mut self, clipboard: &mut Clipboard) {
+        let range = self.copy_to_clipboard(clipboard);
+        self.delete(range);
+    }
+
+    /// Replaces the selection with the contents of `clipboard`, or inserts them at the cursor.
+    /// Line breaks are converted to the ones that the text uses. Lines that were copied
+    /// without a selection are inserted above the cursor's line, and the cursor stays where it is.
+    pub fn paste(&mut self, clipboard: &Clipboard) {
+        let text = convert_newlines(clipboard.read(), self.newline());
+        if text.is_empty() {
+            return;
+        }
+
+        if clipboard.is_line_copy() && self.selection_range().is_none() {
+            let cursor = self.cursor;
+            let start = self.line_start_offset(self.offset_to_logical(cursor).y);
+            self.insert(start, &text);
+            self.set_cursor_offset(cursor + text.len());
+        } else {
+            let range = self.selection_range().unwrap_or(self.cursor..self.cursor);
+            self.replace(range, &text);
+        }
+    }
+
+    /// Writes the selection or the cursor's line to `clipboard`.
+    /// Returns the range that a cut deletes.
+    fn copy_to_clipboard(&self, clipboard: &mut Clipboard) -> Range<usize> {
+        let mut data = Vec::new();
+
+        if let Some(range) = self.selection_range() {
+            self.extract_raw(range.clone(), &mut data);
+            clipboard.write(data, false);
+            return range;
+        }
+
+        let mut range = self.line_range_at(self.cursor);
+        self.extract_raw(range.clone(), &mut data);
+        if !data.ends_with(b"\n") {
+            // The last line has no line break of its own. Pasting it needs one, though,
+            // and cutting it removes the one in front of it instead.
+            data.extend_from_slice(self.newline());
+            range.start = self.prev_grapheme_offset(range.start);
+        }
+        clipboard.write(data, true);
+        range
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_buffer(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb
+    }
+
+    fn contents(tb: &TextBuffer) -> String {
+        let mut out = Vec::new();
+        tb.extract_raw(0..tb.text_length(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_cut_paste_selection() {
+        let mut clipboard = Clipboard::default();
+        let mut tb = new_buffer("one\r\ntwo");
+        tb.set_selection(2, 6);
+        tb.cut(&mut clipboard);
+        assert_eq!(clipboard.read(), b"e\r\nt");
+        assert!(!clipboard.is_line_copy());
+        assert_eq!(contents(&tb), "onwo");
+
+        // Line breaks are converted to those of the target.
+        let mut tb = new_buffer("a\nb");
+        tb.set_cursor_offset(1);
+        tb.paste(&clipboard);
+        assert_eq!(contents(&tb), "ae\nt\nb");
+        assert_eq!(tb.cursor_offset(), 4);
+    }
+
+    #[test]
+    fn test_line_copy() {
+        let mut clipboard = Clipboard::default();
+        let mut tb = new_buffer("first\nlast");
+        tb.set_cursor_offset(8);
+        tb.copy(&mut clipboard);
+        assert_eq!(clipboard.read(), b"last\n");
+        assert!(clipboard.is_line_copy());
+
+        tb.set_cursor_offset(2);
+        tb.paste(&clipboard);
+        assert_eq!(contents(&tb), "last\nfirst\nlast");
+        assert_eq!(tb.cursor_offset(), 7);
+
+        // Cutting the last line takes the line break in front of it along.
+        tb.set_cursor_offset(12);
+        tb.cut(&mut clipboard);
+        assert_eq!(contents(&tb), "last\nfirst");
+    }
+}
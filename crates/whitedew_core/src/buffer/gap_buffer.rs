@@ -0,0 +1,457 @@
+
+use crate::{
+    apperr,
+    document::ReadableDocument,
+    helpers::KIBI,
+};
+use std::{
+    ops::Range,
+    ptr::{self, NonNull},
+    slice,
+};
+use stdext::sys;
+
+#[cfg(target_pointer_width = "32")]
+const LARGE_CAPACITY: usize = 128 * crate::helpers::MEBI;
+#[cfg(target_pointer_width = "64")]
+const LARGE_CAPACITY: usize = 128 * crate::helpers::GIBI;
+const LARGE_ALLOC_CHUNK: usize = 64 * KIBI;
+const LARGE_GAP_CHUNK: usize = 4 * KIBI;
+
+const SMALL_CAPACITY: usize = 128 * KIBI;
+const SMALL_ALLOC_CHUNK: usize = 256;
+const SMALL_GAP_CHUNK: usize = 16;
+
+/// Where the bytes of a [`GapBuffer`] live.
+///
+/// Large buffers reserve a huge range of address space up front and then commit it
+/// piece by piece, so that the text never needs to be moved to a new allocation.
+/// Small buffers (single-line inputs, etc.) would waste a lot of memory that way,
+/// because commits happen at page granularity, so they use a regular [`Vec`].
+enum BackingBuffer {
+    VirtualMemory(NonNull<u8>, usize),
+    Vec(Vec<u8>),
+}
+
+impl Drop for BackingBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            if let Self::VirtualMemory(ptr, reserve) = *self {
+                sys::virtual_release(ptr, reserve);
+            }
+        }
+    }
+}
+
+/// Most people know how `Vec<T>` works: It has some spare capacity at the end,
+/// so that pushing into it doesn't reallocate every single time. A gap buffer
+/// is the same thing, but the spare capacity can be anywhere in the buffer.
+/// Moving the gap to where the user types makes consecutive edits cheap.
+///
+/// ```text
+///          gap_off
+///             v
+/// |ABCDEFGHIJK        LMNOPQRSTUVWXYZ|
+///             <------>
+///              gap_len
+/// ```
+pub struct GapBuffer {
+    /// Pointer to the buffer.
+    text: NonNull<u8>,
+    /// Maximum size of the buffer, including gap.
+    reserve: usize,
+    /// Size of the buffer, including gap.
+    commit: usize,
+    /// Length of the stored text, NOT including gap.
+    text_length: usize,
+    /// Gap offset.
+    gap_off: usize,
+    /// Gap length.
+    gap_len: usize,
+    /// Increments every time the buffer is modified.
+    generation: u32,
+    /// If `Vec(..)`, the buffer is optimized for small amounts of text
+    /// and uses the standard heap. Otherwise, it uses virtual memory.
+    buffer: BackingBuffer,
+}
+
+impl GapBuffer {
+    pub fn new(small: bool) -> apperr::Result<Self> {
+        let reserve;
+        let buffer;
+        let text;
+
+        if small {
+            reserve = SMALL_CAPACITY;
+            text = NonNull::dangling();
+            buffer = BackingBuffer::Vec(Vec::new());
+        } else {
+            reserve = LARGE_CAPACITY;
+            text = unsafe { sys::virtual_reserve(reserve)? };
+            buffer = BackingBuffer::VirtualMemory(text, reserve);
+        }
+
+        Ok(Self { text, reserve, commit: 0, text_length: 0, gap_off: 0, gap_len: 0, generation: 0, buffer })
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.text_length
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    pub fn set_generation(&mut self, generation: u32) {
+        self.generation = generation;
+    }
+
+    /// Moves the gap to `off`, deletes `delete` bytes after it and ensures that
+    /// the gap can hold at least `len` bytes. Returns the gap for you to write into.
+    /// Call [`GapBuffer::commit_gap`] afterwards with the number of bytes you wrote.
+    ///
+    /// WARNING: The returned slice is not necessarily `len` bytes long (very important).
+    /// It's longer if the gap happens to be larger and it's shorter if we ran out of memory.
+    pub fn allocate_gap(&mut self, off: usize, len: usize, delete: usize) -> &mut [u8] {
+        // Sanitize parameters
+        let off = off.min(self.text_length);
+        let delete = delete.min(self.text_length - off);
+
+        // Move the existing gap if it exists
+        if off != self.gap_off {
+            self.move_gap(off);
+        }
+
+        // Delete the text
+        if delete > 0 {
+            self.delete_text(delete);
+        }
+
+        // Enlarge the gap if needed
+        if len > self.gap_len {
+            self.enlarge_gap(len);
+        }
+
+        self.generation = self.generation.wrapping_add(1);
+        unsafe { slice::from_raw_parts_mut(self.text.add(self.gap_off).as_ptr(), self.gap_len) }
+    }
+
+    fn move_gap(&mut self, off: usize) {
+        if self.gap_len > 0 {
+            //
+            //                       v gap_off
+            // left:  |ABCDEFGHIJKLMN   OPQRSTUVWXYZ|
+            //        |ABCDEFGHI   JKLMNOPQRSTUVWXYZ|
+            //                  ^ off
+            //        move: JKLMN
+            //
+            //                       v gap_off
+            // !left: |ABCDEFGHIJKLMN   OPQRSTUVWXYZ|
+            //        |ABCDEFGHIJKLMNOPQRS   TUVWXYZ|
+            //                            ^ off
+            //        move: OPQRS
+            //
+            let data = self.text.as_ptr();
+            let left = off < self.gap_off;
+            let move_src = if left { off } else { self.gap_off + self.gap_len };
+            let move_dst = if left { off + self.gap_len } else { self.gap_off };
+            let move_len = if left { self.gap_off - off } else { off - self.gap_off };
+
+            unsafe { ptr::copy(data.add(move_src), data.add(move_dst), move_len) };
+
+            if cfg!(debug_assertions) {
+                // Fill the moved-out bytes with 0xCD to make debugging easier.
+                unsafe { data.add(off).write_bytes(0xCD, self.gap_len) };
+            }
+        }
+
+        self.gap_off = off;
+    }
+
+    fn delete_text(&mut self, delete: usize) {
+        if cfg!(debug_assertions) {
+            // Fill the deleted bytes with 0xCD to make debugging easier.
+            unsafe { self.text.as_ptr().add(self.gap_off + self.gap_len).write_bytes(0xCD, delete) };
+        }
+
+        // 갭 바로 뒤의 텍스트를 갭에 흡수시키는 것으로 삭제가 끝난다.
+        self.gap_len += delete;
+        self.text_length -= delete;
+    }
+
+    fn enlarge_gap(&mut self, len: usize) {
+        let (gap_chunk, alloc_chunk) = match self.buffer {
+            BackingBuffer::VirtualMemory(..) => (LARGE_GAP_CHUNK, LARGE_ALLOC_CHUNK),
+            BackingBuffer::Vec(..) => (SMALL_GAP_CHUNK, SMALL_ALLOC_CHUNK),
+        };
+
+        let gap_len_old = self.gap_len;
+        let mut gap_len_new = (len + gap_chunk + gap_chunk - 1) & !(gap_chunk - 1);
+
+        let bytes_old = self.commit;
+        let bytes_new = self.text_length + gap_len_new;
+
+        if bytes_new > bytes_old {
+            // If we hit the reservation limit, grow as much as we still can.
+            let bytes_new = ((bytes_new + alloc_chunk - 1) & !(alloc_chunk - 1)).min(self.reserve);
+            gap_len_new = bytes_new - self.text_length;
+
+            if gap_len_new <= gap_len_old {
+                return;
+            }
+
+            match &mut self.buffer {
+                BackingBuffer::VirtualMemory(ptr, _) => unsafe {
+                    if sys::virtual_commit(ptr.add(bytes_old), bytes_new - bytes_old).is_err() {
+                        return;
+                    }
+                },
+                BackingBuffer::Vec(v) => {
+                    v.resize(bytes_new, 0);
+                    self.text = unsafe { NonNull::new_unchecked(v.as_mut_ptr()) };
+                }
+            }
+
+            self.commit = bytes_new;
+        }
+
+        // Move the text after the gap to the end of the new, larger gap.
+        let gap_beg = unsafe { self.text.add(self.gap_off) };
+        unsafe {
+            ptr::copy(
+                gap_beg.add(gap_len_old).as_ptr(),
+                gap_beg.add(gap_len_new).as_ptr(),
+                self.text_length - self.gap_off,
+            )
+        };
+
+        if cfg!(debug_assertions) {
+            // Fill the moved-out bytes with 0xCD to make debugging easier.
+            unsafe { gap_beg.add(gap_len_old).write_bytes(0xCD, gap_len_new - gap_len_old) };
+        }
+
+        self.gap_len = gap_len_new;
+    }
+
+    /// Marks the first `len` bytes of the gap returned by
+    /// [`GapBuffer::allocate_gap`] as being part of the text.
+    pub fn commit_gap(&mut self, len: usize) {
+        assert!(len <= self.gap_len);
+        self.gap_off += len;
+        self.gap_len -= len;
+        self.text_length += len;
+    }
+
+    /// Replaces the given `range` with `src`.
+    ///
+    /// If the buffer runs out of memory, `src` is only partially inserted.
+    pub fn replace(&mut self, range: Range<usize>, src: &[u8]) {
+        let gap = self.allocate_gap(range.start, src.len(), range.end.saturating_sub(range.start));
+        let len = src.len().min(gap.len());
+        gap[..len].copy_from_slice(&src[..len]);
+        self.commit_gap(len);
+    }
+
+    /// Appends the contents of the given `range` to `out`.
+    pub fn extract_raw(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        let end = range.end.min(self.text_length);
+        let mut off = range.start.min(end);
+
+        while off < end {
+            let chunk = self.read_forward(off);
+            let chunk = &chunk[..chunk.len().min(end - off)];
+            out.extend_from_slice(chunk);
+            off += chunk.len();
+        }
+    }
+}
+
+impl ReadableDocument for GapBuffer {
+    fn read_forward(&self, off: usize) -> &[u8] {
+        let off = off.min(self.text_length);
+        let beg;
+        let len;
+
+        if off < self.gap_off {
+            // Cursor is before the gap: We can read until the start of the gap.
+            beg = off;
+            len = self.gap_off - off;
+        } else {
+            // Cursor is after the gap: We can read until the end of the buffer.
+            beg = off + self.gap_len;
+            len = self.text_length - off;
+        }
+
+        unsafe { slice::from_raw_parts(self.text.add(beg).as_ptr(), len) }
+    }
+
+    fn read_backward(&self, off: usize) -> &[u8] {
+        let off = off.min(self.text_length);
+        let beg;
+        let len;
+
+        if off <= self.gap_off {
+            // Cursor is before the gap: We can read until the beginning of the buffer.
+            beg = 0;
+            len = off;
+        } else {
+            // Cursor is after the gap: We can read until the end of the gap.
+            beg = self.gap_off + self.gap_len;
+            // The cursor_off doesn't account of the gap_len.
+            // (This allows us to move the gap without recalculating the cursor position.)
+            len = off - self.gap_off;
+        }
+
+        unsafe { slice::from_raw_parts(self.text.add(beg).as_ptr(), len) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(buf: &GapBuffer) -> Vec<u8> {
+        let mut out = Vec::new();
+        buf.extract_raw(0..usize::MAX, &mut out);
+        out
+    }
+
+    fn contents_backward(buf: &GapBuffer) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut off = buf.len();
+        while off > 0 {
+            let chunk = buf.read_backward(off);
+            out.splice(0..0, chunk.iter().copied());
+            off -= chunk.len();
+        }
+        out
+    }
+
+    fn new_buffers() -> [GapBuffer; 2] {
+        [GapBuffer::new(false).unwrap(), GapBuffer::new(true).unwrap()]
+    }
+
+    #[test]
+    fn test_insert_start_middle_end() {
+        for mut buf in new_buffers() {
+            buf.replace(0..0, b"world");
+            buf.replace(0..0, b"hello ");
+            buf.replace(buf.len()..buf.len(), b"!");
+            buf.replace(5..5, b",");
+            assert_eq!(contents(&buf), b"hello, world!");
+            assert_eq!(contents_backward(&buf), b"hello, world!");
+            assert_eq!(buf.len(), 13);
+        }
+    }
+
+    #[test]
+    fn test_delete_start_middle_end() {
+        for mut buf in new_buffers() {
+            buf.replace(0..0, b"0123456789");
+            buf.replace(0..2, b"");
+            assert_eq!(contents(&buf), b"23456789");
+            buf.replace(6..8, b"");
+            assert_eq!(contents(&buf), b"234567");
+            buf.replace(2..4, b"");
+            assert_eq!(contents(&buf), b"2367");
+            assert_eq!(contents_backward(&buf), b"2367");
+            buf.replace(0..usize::MAX, b"");
+            assert_eq!(buf.len(), 0);
+            assert_eq!(buf.read_forward(0), b"");
+            assert_eq!(buf.read_backward(0), b"");
+        }
+    }
+
+    #[test]
+    fn test_replace() {
+        for mut buf in new_buffers() {
+            buf.replace(0..0, b"the quick fox");
+            buf.replace(4..9, b"slow");
+            assert_eq!(contents(&buf), b"the slow fox");
+            buf.replace(9..12, b"tortoise");
+            assert_eq!(contents(&buf), b"the slow tortoise");
+        }
+    }
+
+    #[test]
+    fn test_reads_across_gap() {
+        for mut buf in new_buffers() {
+            buf.replace(0..0, b"abcdefgh");
+            // Move the gap into the middle.
+            buf.replace(4..4, b"XY");
+            assert_eq!(contents(&buf), b"abcdXYefgh");
+
+            // Reading forward stops at the gap and continues behind it.
+            assert_eq!(buf.read_forward(0), b"abcdXY");
+            assert_eq!(buf.read_forward(3), b"dXY");
+            assert_eq!(buf.read_forward(6), b"efgh");
+            assert_eq!(buf.read_forward(8), b"gh");
+            assert_eq!(buf.read_forward(100), b"");
+
+            // Reading backward stops at the gap as well.
+            assert_eq!(buf.read_backward(10), b"efgh");
+            assert_eq!(buf.read_backward(7), b"e");
+            assert_eq!(buf.read_backward(6), b"abcdXY");
+            assert_eq!(buf.read_backward(2), b"ab");
+            assert_eq!(buf.read_backward(100), b"efgh");
+        }
+    }
+
+    #[test]
+    fn test_edits_spanning_gap() {
+        for mut buf in new_buffers() {
+            buf.replace(0..0, b"abcdefgh");
+            buf.replace(4..4, b"--");
+            assert_eq!(contents(&buf), b"abcd--efgh");
+
+            // Delete a range that starts before the gap and ends behind it.
+            buf.replace(2..8, b"");
+            assert_eq!(contents(&buf), b"abgh");
+
+            buf.replace(4..4, b"ij");
+            buf.replace(1..5, b"1234");
+            assert_eq!(contents(&buf), b"a1234j");
+            assert_eq!(contents_backward(&buf), b"a1234j");
+        }
+    }
+
+    #[test]
+    fn test_enlarge_gap() {
+        for mut buf in new_buffers() {
+            // Insert in the middle repeatedly, so that the text after the gap has to be moved.
+            let mut expected = Vec::new();
+            for i in 0..1000 {
+                let ch = b'a' + (i % 26) as u8;
+                let off = expected.len() / 2;
+                expected.insert(off, ch);
+                buf.replace(off..off, &[ch]);
+            }
+            buf.replace(0..0, &[b'x'; 4000]);
+            expected.splice(0..0, [b'x'; 4000]);
+            assert_eq!(contents(&buf), expected);
+            assert_eq!(contents_backward(&buf), expected);
+        }
+    }
+
+    #[test]
+    fn test_small_capacity() {
+        let mut buf = GapBuffer::new(true).unwrap();
+        let data = vec![b'a'; SMALL_CAPACITY * 2];
+        buf.replace(0..0, &data);
+        assert!(buf.len() <= SMALL_CAPACITY);
+        assert!(buf.len() > 0);
+    }
+
+    #[test]
+    fn test_generation() {
+        let mut buf = GapBuffer::new(false).unwrap();
+        let generation = buf.generation();
+        buf.replace(0..0, b"abc");
+        assert_ne!(buf.generation(), generation);
+        let generation = buf.generation();
+        buf.replace(0..3, b"");
+        assert_ne!(buf.generation(), generation);
+        assert_eq!(buf.len(), 0);
+    }
+}
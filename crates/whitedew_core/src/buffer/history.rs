@@ -0,0 +1,387 @@
+
+//! The undo/redo history of a [`TextBuffer`].
+
+use super::TextBuffer;
+use std::ops::Range;
+use stdext::ReplaceRange as _;
+
+/// What kind of edit a change is. Consecutive edits of the same kind
+/// (e.g. typing a word character by character) get merged into one undo step.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub(super) enum HistoryType {
+    /// Never merged with anything.
+    #[default]
+    Other,
+    /// Typing.
+    Write,
+    /// Backspace/Delete.
+    Delete,
+}
+
+/// The cursor and selection at the time of an edit,
+/// so that undo/redo can put them back where they were.
+#[derive(Clone, Copy)]
+struct CursorState {
+    cursor: usize,
+    selection_anchor: Option<usize>,
+}
+
+/// A single replacement of `deleted` with `added` at `offset`.
+struct HistoryChange {
+    offset: usize,
+    deleted: Vec<u8>,
+    added: Vec<u8>,
+}
+
+/// A single undo step. It consists of one or more changes, which are applied in order.
+struct HistoryEntry {
+    changes: Vec<HistoryChange>,
+    cursor_before: CursorState,
+    cursor_after: CursorState,
+    generation_before: u32,
+    generation_after: u32,
+}
+
+#[derive(Default)]
+pub(super) struct History {
+    undo_stack: Vec<HistoryEntry>,
+    redo_stack: Vec<HistoryEntry>,
+    /// The type of the last change. Used to decide whether the next one can be merged into it.
+    last_type: HistoryType,
+    /// Nesting depth of [`TextBuffer::begin_edit_group`] calls.
+    group_depth: usize,
+    /// `true` if the current edit group has already pushed its entry onto the undo stack.
+    group_started: bool,
+}
+
+impl TextBuffer {
+    /// Returns `true` if there's something to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.history.undo_stack.is_empty()
+    }
+
+    /// Returns `true` if there's something to redo.
+    pub fn can_redo(&self) -> bool {
+        !self.history.redo_stack.is_empty()
+    }
+
+    /// Starts a group of edits that get undone and redone as a whole.
+    /// Compound operations like paste, replace-all or indenting a block use this.
+    ///
+    /// Groups may be nested. Only the outermost group counts.
+    /// Every call must be paired with a call to [`TextBuffer::end_edit_group`].
+    pub fn begin_edit_group(&mut self) {
+        let history = &mut self.history;
+        if history.group_depth == 0 {
+            history.group_started = false;
+            history.last_type = HistoryType::Other;
+        }
+        history.group_depth += 1;
+    }
+
+    /// Ends a group of edits started with [`TextBuffer::begin_edit_group`].
+    pub fn end_edit_group(&mut self) {
+        let history = &mut self.history;
+        debug_assert!(history.group_depth > 0);
+        history.group_depth = history.group_depth.saturating_sub(1);
+        if history.group_depth == 0 {
+            // Typing right after a paste shouldn't be merged into the paste.
+            history.last_type = HistoryType::Other;
+        }
+    }
+
+    /// Undoes the last undo step, if any.
+    pub fn undo(&mut self) {
+        let Some(entry) = self.history.undo_stack.pop() else {
+            return;
+        };
+
+        for change in entry.changes.iter().rev() {
+            let end = change.offset + change.added.len();
+            self.replace_raw(change.offset..end, &change.deleted);
+        }
+
+        // Going back to the generation from before the edit is what makes the
+        // document clean again, if we undo all the way back to the saved state.
+        self.buffer.set_generation(entry.generation_before);
+        self.restore_cursor_state(entry.cursor_before);
+        self.history.redo_stack.push(entry);
+        self.history.last_type = HistoryType::Other;
+    }
+
+    /// Redoes the last undone step, if any.
+    pub fn redo(&mut self) {
+        let Some(entry) = self.history.redo_stack.pop() else {
+            return;
+        };
+
+        for change in &entry.changes {
+            let end = change.offset + change.deleted.len();
+            self.replace_raw(change.offset..end, &change.added);
+        }
+
+        self.buffer.set_generation(entry.generation_after);
+        self.restore_cursor_state(entry.cursor_after);
+        self.history.undo_stack.push(entry);
+        self.history.last_type = HistoryType::Other;
+    }
+
+    /// Drops the entire history, e.g. after loading a file.
+    pub(super) fn history_clear(&mut self) {
+        let group_depth = self.history.group_depth;
+        self.history = Default::default();
+        self.history.group_depth = group_depth;
+    }
+
+    /// Prevents the next change from being merged into the previous one.
+    /// Called whenever the cursor is moved.
+    pub(super) fn history_break_merge(&mut self) {
+        if self.history.group_depth == 0 {
+            self.history.last_type = HistoryType::Other;
+        }
+    }
+
+    /// Records that `range` is about to be replaced with `added`.
+    /// Must be called right before the buffer is modified.
+    pub(super) fn history_push_change(
+        &mut self,
+        history_type: HistoryType,
+        range: Range<usize>,
+        added: &[u8],
+    ) {
+        let mut deleted = Vec::new();
+        self.buffer.extract_raw(range.clone(), &mut deleted);
+
+        let cursor_state = self.cursor_state();
+        let generation = self.buffer.generation();
+        let history = &mut self.history;
+        history.redo_stack.clear();
+
+        if history.group_depth > 0
+            && history.group_started
+            && let Some(entry) = history.undo_stack.last_mut()
+        {
+            entry.changes.push(HistoryChange { offset: range.start, deleted, added: added.to_vec() });
+            return;
+        }
+
+        if history.group_depth == 0
+            && history_type != HistoryType::Other
+            && history_type == history.last_type
+            && let Some(entry) = history.undo_stack.last_mut()
+            && let [change] = &mut entry.changes[..]
+            && Self::history_merge(change, history_type, &range, &deleted, added)
+        {
+            return;
+        }
+
+        history.undo_stack.push(HistoryEntry {
+            changes: vec![HistoryChange { offset: range.start, deleted, added: added.to_vec() }],
+            cursor_before: cursor_state,
+            cursor_after: cursor_state,
+            generation_before: generation,
+            generation_after: generation,
+        });
+        history.last_type = history_type;
+        history.group_started = history.group_depth > 0;
+    }
+
+    /// Stores the cursor and generation after the change in the undo step.
+    /// Must be called right after the buffer was modified.
+    pub(super) fn history_finish_change(&mut self) {
+        let cursor_state = self.cursor_state();
+        let generation = self.buffer.generation();
+        if let Some(entry) = self.history.undo_stack.last_mut() {
+            entry.cursor_after = cursor_state;
+            entry.generation_after = generation;
+        }
+    }
+
+    /// Tries to merge a new change into the previous `change`. Returns `false` if it can't.
+    fn history_merge(
+        change: &mut HistoryChange,
+        history_type: HistoryType,
+        range: &Range<usize>,
+        deleted: &[u8],
+        added: &[u8],
+    ) -> bool {
+        match history_type {
+            HistoryType::Write => {
+                // Only merge text that is typed right after the previous text. A newline
+                // ends the undo step, so that undoing doesn't remove multiple lines at once.
+                if !deleted.is_empty()
+                    || range.start != change.offset + change.added.len()
+                    || change.added.ends_with(b"\n")
+                {
+                    return false;
+                }
+                change.added.extend_from_slice(added);
+                true
+            }
+            HistoryType::Delete => {
+                if !added.is_empty() || !change.added.is_empty() {
+                    return false;
+                }
+                if range.end == change.offset {
+                    // Backspace: The deleted text precedes the previously deleted text.
+                    change.offset = range.start;
+                    change.deleted.replace_range(0..0, deleted);
+                    true
+                } else if range.start == change.offset {
+                    // Delete: The deleted text follows the previously deleted text.
+                    change.deleted.extend_from_slice(deleted);
+                    true
+                } else {
+                    false
+                }
+            }
+            HistoryType::Other => false,
+        }
+    }
+
+    fn cursor_state(&self) -> CursorState {
+        CursorState { cursor: self.cursor, selection_anchor: self.selection_anchor }
+    }
+
+    fn restore_cursor_state(&mut self, state: CursorState) {
+        let len = self.buffer.len();
+        self.cursor = state.cursor.min(len);
+        self.selection_anchor = state.selection_anchor.map(|a| a.min(len));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_buffer(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb
+    }
+
+    fn contents(tb: &TextBuffer) -> String {
+        let mut out = Vec::new();
+        tb.extract_raw(0..tb.text_length(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_typing() {
+        let mut tb = new_buffer("");
+        for text in ["a", "b", "\n", "c", "d"] {
+            tb.write(text.as_bytes());
+        }
+        // The newline ends the first undo step.
+        tb.undo();
+        assert_eq!(contents(&tb), "ab\n");
+        tb.undo();
+        assert_eq!(contents(&tb), "");
+        assert!(!tb.can_undo());
+
+        tb.redo();
+        tb.redo();
+        assert_eq!(contents(&tb), "ab\ncd");
+        assert_eq!(tb.cursor_offset(), 5);
+
+        // Moving the cursor in between breaks the merge.
+        tb.set_cursor_offset(0);
+        tb.write(b"x");
+        tb.set_cursor_offset(6);
+        tb.write(b"y");
+        tb.undo();
+        assert_eq!(contents(&tb), "xab\ncd");
+    }
+
+    #[test]
+    fn test_deleting() {
+        let mut tb = new_buffer("hello world");
+        tb.set_cursor_offset(5);
+        tb.delete_backward();
+        tb.delete_backward();
+        tb.delete_forward();
+        tb.delete_forward();
+        assert_eq!(contents(&tb), "helorld");
+
+        tb.undo();
+        assert_eq!(contents(&tb), "hello world");
+        assert_eq!(tb.cursor_offset(), 5);
+
+        // Typing isn't merged into deleting and vice versa.
+        tb.delete_backward();
+        tb.write(b"O");
+        tb.undo();
+        assert_eq!(contents(&tb), "hell world");
+    }
+
+    #[test]
+    fn test_edit_group() {
+        let mut tb = new_buffer("a b c");
+        tb.begin_edit_group();
+        tb.replace(0..1, b"x");
+        tb.begin_edit_group();
+        tb.replace(2..3, b"yy");
+        tb.end_edit_group();
+        tb.insert(6, b"z");
+        tb.end_edit_group();
+        assert_eq!(contents(&tb), "x yy cz");
+
+        tb.undo();
+        assert_eq!(contents(&tb), "a b c");
+        tb.redo();
+        assert_eq!(contents(&tb), "x yy cz");
+        tb.undo();
+        tb.undo();
+        assert_eq!(contents(&tb), "");
+    }
+
+    #[test]
+    fn test_undo_to_clean() {
+        let mut tb = new_buffer("saved");
+        tb.mark_as_clean();
+        tb.set_cursor_offset(5);
+        tb.write(b"!");
+        assert!(tb.is_dirty());
+
+        tb.undo();
+        assert!(!tb.is_dirty());
+        tb.redo();
+        assert!(tb.is_dirty());
+        tb.undo();
+        tb.undo();
+        assert!(tb.is_dirty());
+    }
+
+    #[test]
+    fn test_undo_to_save_while_typing() {
+        let mut tb = new_buffer("saved");
+        tb.set_cursor_offset(5);
+        tb.write(b"a");
+        tb.mark_as_clean();
+        tb.write(b"b");
+        assert!(tb.is_dirty());
+
+        tb.undo();
+        assert_eq!(contents(&tb), "saveda");
+        assert!(!tb.is_dirty());
+    }
+
+    #[test]
+    fn test_undo_after_mark_as_dirty() {
+        let mut tb = new_buffer("saved");
+        tb.mark_as_clean();
+        tb.set_cursor_offset(5);
+        tb.write(b"!");
+        tb.undo();
+        assert!(!tb.is_dirty());
+
+        // Changing the encoding changes the file, which undo can't take back.
+        tb.set_encoding("UTF-16LE", true);
+        assert!(tb.is_dirty());
+        tb.undo();
+        assert!(tb.is_dirty());
+        tb.undo();
+        assert_eq!(contents(&tb), "");
+        assert!(tb.is_dirty());
+    }
+}
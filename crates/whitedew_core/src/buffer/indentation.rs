@@ -0,0 +1,278 @@
+
+//! Indentation of a [`TextBuffer`]: detecting it, indenting and unindenting blocks
+//! of lines, and converting between tabs and spaces.
+
+use super::TextBuffer;
+use crate::helpers::{CoordType, KIBI};
+use std::ops::Range;
+
+/// How much of a file [`TextBuffer::detect_indentation`] looks at.
+const SAMPLE_SIZE: usize = 64 * KIBI;
+
+impl TextBuffer {
+    /// Returns `true` if indenting inserts tabs, and `false` if it inserts spaces.
+    /// Either way, the width of an indentation level is [`TextBuffer::tab_size`].
+    pub fn indent_with_tabs(&self) -> bool {
+        self.indent_with_tabs
+    }
+
+    /// Sets whether indenting inserts tabs or spaces. The text stays the same.
+    /// See [`TextBuffer::reindent`] for changing it.
+    pub fn set_indent_with_tabs(&mut self, indent_with_tabs: bool) {
+        self.indent_with_tabs = indent_with_tabs;
+    }
+
+    /// Indents, as done by the Tab key: if the selection spans multiple lines,
+    /// all of them are indented by one level. Otherwise, this inserts a tab,
+    /// or as many spaces as it takes to get to the next tab stop.
+    pub fn indent(&mut self) {
+        let Some(lines) = self.selected_lines() else {
+            let column = self.offset_to_logical(self.cursor).x;
+            let indent = self.indent_string(column);
+            self.write(&indent);
+            return;
+        };
+
+        let indent = self.indent_string(0);
+        self.begin_edit_group();
+        for line in lines.clone().rev() {
+            let start = self.line_start_offset(line);
+            // Indenting empty lines would only leave trailing whitespace behind.
+            if self.line_end_offset(line) > start {
+                self.insert(start, &indent);
+            }
+        }
+        self.end_edit_group();
+        self.select_lines(lines);
+    }
+
+    /// Unindents, as done by Shift+Tab: removes one level of indentation from
+    /// each line of the selection, or from the cursor's line if there's none.
+    pub fn unindent(&mut self) {
+        let lines = self.selected_lines();
+        let (lines, select) = match lines {
+            Some(lines) => (lines, true),
+            None => {
+                let line = self.offset_to_logical(self.cursor).y;
+                (line..line + 1, false)
+            }
+        };
+
+        let cursor = self.cursor;
+        let mut removed_before_cursor = 0;
+
+        self.begin_edit_group();
+        for line in lines.clone().rev() {
+            let start = self.line_start_offset(line);
+            let len = self.indent_level_len(start);
+            if len > 0 {
+                self.delete(start..start + len);
+            }
+            if !select && len > 0 {
+                removed_before_cursor = (cursor - start).min(len);
+            }
+        }
+        self.end_edit_group();
+
+        if select {
+            self.select_lines(lines);
+        } else {
+            self.set_cursor_offset(cursor - removed_before_cursor);
+        }
+    }
+
+    /// Converts the indentation of every line to the current style: tabs or spaces.
+    /// Leading whitespace keeps its width, so that alignment is preserved.
+    /// This is a single undo step. The cursor stays on the same line and column.
+    pub fn reindent(&mut self) {
+        let mut text = Vec::new();
+        self.extract_raw(0..self.text_length(), &mut text);
+
+        let tab_size = self.tab_size as usize;
+        let mut converted = Vec::with_capacity(text.len());
+        for line in text.split_inclusive(|&b| b == b'\n') {
+            let ws = line.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+            let width = line[..ws]
+                .iter()
+                .fold(0, |col, &b| if b == b'\t' { col + tab_size - col % tab_size } else { col + 1 });
+
+            if self.indent_with_tabs {
+                converted.extend(std::iter::repeat_n(b'\t', width / tab_size));
+                converted.extend(std::iter::repeat_n(b' ', width % tab_size));
+            } else {
+                converted.extend(std::iter::repeat_n(b' ', width));
+            }
+            converted.extend_from_slice(&line[ws..]);
+        }
+
+        if converted != text {
+            let pos = self.offset_to_logical(self.cursor);
+            self.replace(0..self.text_length(), &converted);
+            let off = self.logical_to_offset(pos);
+            self.set_cursor_offset(off);
+        }
+    }
+
+    /// Guesses the indentation style and width from the beginning of the text.
+    /// If there's no indentation at all, the current settings are kept.
+    pub(super) fn detect_indentation(&mut self) {
+        let mut text = Vec::new();
+        self.extract_raw(0..self.text_length().min(SAMPLE_SIZE), &mut text);
+
+        let mut lines: Vec<&[u8]> = text.split(|&b| b == b'\n').collect();
+        if self.text_length() > SAMPLE_SIZE {
+            // The last line was cut off.
+            lines.pop();
+        }
+
+        let mut tab_lines = 0;
+        let mut space_lines = 0;
+        // How often the indentation changed by 1 to 8 spaces from one line to the next.
+        let mut deltas = [0; 9];
+        let mut prev = 0;
+
+        for line in lines {
+            let ws = line.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+            if ws == line.len() || line[ws] == b'\r' {
+                // Blank lines say nothing about indentation.
+                continue;
+            }
+
+            if line[0] == b'\t' {
+                tab_lines += 1;
+                continue;
+            }
+
+            let spaces = line.iter().take_while(|&&b| b == b' ').count();
+            // A single space is usually alignment, like the ` * ` in block comments.
+            if spaces > 1 {
+                space_lines += 1;
+            }
+            let delta = spaces.abs_diff(prev);
+            if delta <= 8 {
+                deltas[delta] += 1;
+            }
+            prev = spaces;
+        }
+
+        if tab_lines == 0 && space_lines == 0 {
+            return;
+        }
+
+        self.indent_with_tabs = tab_lines > space_lines;
+        if !self.indent_with_tabs {
+            // For the same reason, a change by 1 only counts if there's nothing else.
+            // Ties go to the larger width, since `max_by_key` returns the last maximum.
+            let width = (2..=8).max_by_key(|&w| deltas[w]).filter(|&w| deltas[w] > 0);
+            let width = width.or((deltas[1] > 0).then_some(1));
+            if let Some(width) = width {
+                self.set_tab_size(width as CoordType);
+            }
+        }
+    }
+
+    /// One level of indentation, starting at `column`.
+    fn indent_string(&self, column: CoordType) -> Vec<u8> {
+        if self.indent_with_tabs {
+            b"\t".to_vec()
+        } else {
+            vec![b' '; (self.tab_size - column % self.tab_size) as usize]
+        }
+    }
+
+    /// The number of bytes that make up one level of indentation at `start`:
+    /// either a tab, or up to [`TextBuffer::tab_size`] spaces.
+    fn indent_level_len(&self, start: usize) -> usize {
+        let mut text = Vec::new();
+        self.extract_raw(start..start + self.tab_size as usize, &mut text);
+        if text.first() == Some(&b'\t') {
+            return 1;
+        }
+        let spaces = text.iter().take_while(|&&b| b == b' ').count();
+        // A tab right after the spaces finishes the same level.
+        if text.get(spaces) == Some(&b'\t') { spaces + 1 } else { spaces }
+    }
+
+    /// Returns the lines that the selection spans, if it spans more than one.
+    /// A line on which the selection merely ends at column 0 doesn't count.
+    fn selected_lines(&self) -> Option<Range<CoordType>> {
+        let range = self.selection_range()?;
+        let first = self.offset_to_logical(range.start).y;
+        let end = self.offset_to_logical(range.end);
+        let last = if end.x == 0 && end.y > first { end.y - 1 } else { end.y };
+        (last > first).then_some(first..last + 1)
+    }
+
+    /// Selects the given lines in full, from the start of the first to the end of the last.
+    fn select_lines(&mut self, lines: Range<CoordType>) {
+        let start = self.line_start_offset(lines.start);
+        let end = self.line_end_offset(lines.end - 1);
+        self.set_selection(start, end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_buffer(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb
+    }
+
+    fn contents(tb: &TextBuffer) -> String {
+        let mut out = Vec::new();
+        tb.extract_raw(0..tb.text_length(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_detect_indentation() {
+        let mut tb = new_buffer("fn main() {\n  if x {\n    y();\n  }\n}\n");
+        tb.detect_indentation();
+        assert!(!tb.indent_with_tabs());
+        assert_eq!(tb.tab_size(), 2);
+
+        // One step of 2 and one of 4 spaces.
+        let mut tb = new_buffer("a\n  b\n      c\n");
+        tb.detect_indentation();
+        assert_eq!(tb.tab_size(), 4);
+
+        let mut tb = new_buffer("a\n\tb\n\t\tc\n/*\n * d\n */\n");
+        tb.detect_indentation();
+        assert!(tb.indent_with_tabs());
+    }
+
+    #[test]
+    fn test_indent_unindent_block() {
+        let mut tb = new_buffer("a\n\nb\nc");
+        tb.set_indent_with_tabs(false);
+        tb.set_selection(0, 5);
+        tb.indent();
+        assert_eq!(contents(&tb), "    a\n\n    b\nc");
+        assert_eq!(tb.selection_range(), Some(0..12));
+
+        tb.unindent();
+        assert_eq!(contents(&tb), "a\n\nb\nc");
+        tb.undo();
+        tb.undo();
+        assert_eq!(contents(&tb), "a\n\nb\nc");
+
+        tb.set_cursor_offset(1);
+        tb.indent();
+        assert_eq!(contents(&tb), "a   \n\nb\nc");
+    }
+
+    #[test]
+    fn test_reindent() {
+        let mut tb = new_buffer("\tx\n      y\n");
+        tb.set_tab_size(4);
+        tb.set_indent_with_tabs(true);
+        tb.reindent();
+        assert_eq!(contents(&tb), "\tx\n\t  y\n");
+        tb.set_indent_with_tabs(false);
+        tb.reindent();
+        assert_eq!(contents(&tb), "    x\n      y\n");
+    }
+}
@@ -0,0 +1,187 @@
+
+//! Line endings of a [`TextBuffer`]: which ones a file uses, and converting between them.
+//!
+//! The text is stored with its line endings as they are, which is what preserves them on save.
+//! To know which kind is used, the buffer keeps count of the `\r\n` pairs in it. Together with
+//! the number of `\n` that the line index keeps anyway, that tells us how many plain `\n` there are.
+
+use super::TextBuffer;
+use crate::{document::ReadableDocument as _, simd::memchr2};
+use std::{cmp::Ordering, ops::Range};
+
+/// The kind of line endings in a document.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineEndings {
+    /// `\n`, as on Unix.
+    Lf,
+    /// `\r\n`, as on Windows.
+    Crlf,
+    /// Both of them.
+    Mixed,
+}
+
+impl TextBuffer {
+    /// Returns the kind of line endings in the text.
+    /// A text without any line breaks has the kind that new line breaks will get.
+    pub fn line_endings(&self) -> LineEndings {
+        match (self.crlf_count, self.lf_count()) {
+            (0, 0) if self.newlines_are_crlf => LineEndings::Crlf,
+            (0, _) => LineEndings::Lf,
+            (_, 0) => LineEndings::Crlf,
+            _ => LineEndings::Mixed,
+        }
+    }
+
+    /// Returns the line break that Enter inserts: whichever kind is in the majority.
+    pub fn newline(&self) -> &'static [u8] {
+        newline_for(match self.crlf_count.cmp(&self.lf_count()) {
+            Ordering::Less => false,
+            Ordering::Equal => self.newlines_are_crlf,
+            Ordering::Greater => true,
+        })
+    }
+
+    /// Converts all line breaks to `\r\n` or `\n` in a single undo step.
+    /// The cursor stays on the same line and column.
+    pub fn set_line_endings(&mut self, crlf: bool) {
+        self.newlines_are_crlf = crlf;
+
+        let done = if crlf { self.lf_count() == 0 } else { self.crlf_count == 0 };
+        if done {
+            return;
+        }
+
+        let mut text = Vec::new();
+        self.extract_raw(0..self.text_length(), &mut text);
+        let converted = convert_newlines(&text, newline_for(crlf));
+
+        let pos = self.offset_to_logical(self.cursor);
+        self.replace(0..self.text_length(), &converted);
+        let off = self.logical_to_offset(pos);
+        self.set_cursor_offset(off);
+    }
+
+    /// Counts all `\r\n` after the text was loaded and picks up the kind of line endings
+    /// that new line breaks get. Text without line breaks keeps the previous kind.
+    pub(super) fn line_endings_rebuild(&mut self) {
+        self.crlf_count = self.count_crlf(0..self.text_length());
+        match self.crlf_count.cmp(&self.lf_count()) {
+            Ordering::Less => self.newlines_are_crlf = false,
+            Ordering::Equal => {}
+            Ordering::Greater => self.newlines_are_crlf = true,
+        }
+    }
+
+    /// Counts the `\r\n` pairs that lie entirely within `range`.
+    pub(super) fn count_crlf(&self, range: Range<usize>) -> usize {
+        let mut count = 0;
+        let mut off = range.start;
+        let mut prev_cr = false;
+
+        while off < range.end {
+            let chunk = self.buffer.read_forward(off);
+            let chunk = &chunk[..chunk.len().min(range.end - off)];
+
+            // A pair may be split between two chunks.
+            if prev_cr && chunk[0] == b'\n' {
+                count += 1;
+            }
+
+            let mut i = 0;
+            loop {
+                i = memchr2(b'\r', b'\r', chunk, i);
+                if i + 1 >= chunk.len() {
+                    break;
+                }
+                if chunk[i + 1] == b'\n' {
+                    count += 1;
+                }
+                i += 1;
+            }
+
+            prev_cr = chunk.last() == Some(&b'\r');
+            off += chunk.len();
+        }
+
+        count
+    }
+
+    /// The number of `\n` that aren't part of a `\r\n`.
+    fn lf_count(&self) -> usize {
+        self.line_index.newlines() as usize - self.crlf_count
+    }
+}
+
+fn newline_for(crlf: bool) -> &'static [u8] {
+    if crlf { b"\r\n" } else { b"\n" }
+}
+
+/// Replaces every `\n` and `\r\n` in `text` with `newline`.
+pub(super) fn convert_newlines(text: &[u8], newline: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(text.len() + text.len() / 16);
+    for line in text.split_inclusive(|&b| b == b'\n') {
+        match line.strip_suffix(b"\n") {
+            Some(line) => {
+                converted.extend_from_slice(line.strip_suffix(b"\r").unwrap_or(line));
+                converted.extend_from_slice(newline);
+            }
+            None => converted.extend_from_slice(line),
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_buffer(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb
+    }
+
+    fn contents(tb: &TextBuffer) -> String {
+        let mut out = Vec::new();
+        tb.extract_raw(0..tb.text_length(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_counts_follow_edits() {
+        let mut tb = new_buffer("a\r\nb\nc\r\n");
+        assert_eq!(tb.line_endings(), LineEndings::Mixed);
+        assert_eq!(tb.newline(), b"\r\n");
+
+        // Completing and splitting pairs at the edges of an edit.
+        tb.insert(4, b"\r");
+        assert_eq!(tb.line_endings(), LineEndings::Crlf);
+        tb.delete(1..2);
+        assert_eq!(tb.line_endings(), LineEndings::Mixed);
+        tb.undo();
+        tb.undo();
+        assert_eq!(contents(&tb), "a\r\nb\nc\r\n");
+        assert_eq!(tb.line_endings(), LineEndings::Mixed);
+    }
+
+    #[test]
+    fn test_set_line_endings() {
+        let mut tb = new_buffer("one\r\ntwo\nthree");
+        tb.set_cursor_offset(8);
+        tb.set_line_endings(true);
+        assert_eq!(contents(&tb), "one\r\ntwo\r\nthree");
+        assert_eq!(tb.cursor_offset(), 8);
+
+        tb.set_line_endings(false);
+        assert_eq!(contents(&tb), "one\ntwo\nthree");
+        assert_eq!(tb.line_endings(), LineEndings::Lf);
+        assert_eq!(tb.cursor_offset(), 7);
+
+        tb.undo();
+        assert_eq!(contents(&tb), "one\r\ntwo\r\nthree");
+        tb.delete(0..tb.text_length());
+        assert_eq!(tb.line_endings(), LineEndings::Lf);
+        tb.set_line_endings(true);
+        assert_eq!(tb.line_endings(), LineEndings::Crlf);
+    }
+}
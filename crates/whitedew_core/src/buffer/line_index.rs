@@ -0,0 +1,287 @@
+
+//! Conversion between byte offsets and (line, column) positions.
+//!
+//! Counting lines from the start of the document for every lookup would be
+//! far too slow for files with millions of lines. Instead, [`LineIndex`] keeps
+//! a sparse list of checkpoints, roughly every [`CHECKPOINT_STRIDE`] lines,
+//! and lookups only count the lines between the nearest checkpoint and the target.
+//! Edits shift the checkpoints after them, and only rescan the text around the edit.
+
+use super::TextBuffer;
+use crate::{
+    document::ReadableDocument,
+    helpers::{CoordType, Point},
+    simd::lines_fwd,
+    unicode::{Cursor, MeasurementConfig},
+};
+use std::ops::Range;
+
+/// The number of lines between two checkpoints.
+const CHECKPOINT_STRIDE: CoordType = 1024;
+
+/// The start of a line and its line number.
+#[derive(Clone, Copy)]
+struct Checkpoint {
+    offset: usize,
+    line: CoordType,
+}
+
+#[derive(Default)]
+pub(super) struct LineIndex {
+    /// Sorted by offset. The start of the document at (0, 0) is implied.
+    checkpoints: Vec<Checkpoint>,
+    /// The number of `\n` in the document.
+    newlines: CoordType,
+}
+
+impl LineIndex {
+    /// Indexes the entire document from scratch.
+    pub(super) fn rebuild(&mut self, doc: &dyn ReadableDocument, len: usize) {
+        self.checkpoints.clear();
+        self.newlines = Self::fill(&mut self.checkpoints, doc, Checkpoint { offset: 0, line: 0 }, len);
+    }
+
+    /// Counts the `\n` within the given `range`.
+    /// Must be called before the range is replaced, to pass the result to [`LineIndex::replace`].
+    pub(super) fn count_newlines(doc: &dyn ReadableDocument, range: Range<usize>) -> CoordType {
+        Self::seek(doc, Checkpoint { offset: range.start, line: 0 }, range.end, CoordType::MAX).line
+    }
+
+    /// Updates the index after `range` got replaced with `text`.
+    /// `removed` is the number of `\n` that were in `range`.
+    pub(super) fn replace(
+        &mut self,
+        doc: &dyn ReadableDocument,
+        len: usize,
+        range: Range<usize>,
+        text: &[u8],
+        removed: CoordType,
+    ) {
+        let added = lines_fwd(text, 0, 0, CoordType::MAX).1;
+        let delta_lines = added - removed;
+        self.newlines += delta_lines;
+
+        // Checkpoints right after a deleted `\n` are gone. The one at `range.start`
+        // is still valid, because the `\n` in front of it wasn't touched.
+        let beg = self.checkpoints.partition_point(|c| c.offset <= range.start);
+        let end = self.checkpoints.partition_point(|c| c.offset <= range.end);
+        self.checkpoints.drain(beg..end);
+
+        for c in &mut self.checkpoints[beg..] {
+            c.offset = c.offset - range.len() + text.len();
+            c.line += delta_lines;
+        }
+
+        // If the edit left a large gap between two checkpoints (e.g. after pasting a lot of lines),
+        // fill it up again. Small edits don't need this, which keeps typing cheap.
+        let prev = if beg > 0 { self.checkpoints[beg - 1] } else { Checkpoint { offset: 0, line: 0 } };
+        let next = self
+            .checkpoints
+            .get(beg)
+            .copied()
+            .unwrap_or(Checkpoint { offset: len, line: self.newlines });
+        if next.line - prev.line > 2 * CHECKPOINT_STRIDE {
+            let mut filled = Vec::new();
+            Self::fill(&mut filled, doc, prev, next.offset);
+            self.checkpoints.splice(beg..beg, filled);
+        }
+    }
+
+    /// Returns the number of `\n` in the document.
+    pub(super) fn newlines(&self) -> CoordType {
+        self.newlines
+    }
+
+    /// Returns the offset at which the given `line` starts.
+    /// `line` must be within `0..=newlines`.
+    pub(super) fn line_start(&self, doc: &dyn ReadableDocument, len: usize, line: CoordType) -> usize {
+        let i = self.checkpoints.partition_point(|c| c.line <= line);
+        let from = if i > 0 { self.checkpoints[i - 1] } else { Checkpoint { offset: 0, line: 0 } };
+        Self::seek(doc, from, len, line).offset
+    }
+
+    /// Returns the line that contains `offset` and the offset at which that line starts.
+    pub(super) fn line_of(&self, doc: &dyn ReadableDocument, offset: usize) -> (CoordType, usize) {
+        let i = self.checkpoints.partition_point(|c| c.offset <= offset);
+        let from = if i > 0 { self.checkpoints[i - 1] } else { Checkpoint { offset: 0, line: 0 } };
+        let line = Self::seek(doc, from, offset, CoordType::MAX).line;
+        let start = Self::seek(doc, from, offset, line).offset;
+        (line, start)
+    }
+
+    /// Appends a checkpoint every [`CHECKPOINT_STRIDE`] lines between `from` and `end`.
+    /// Returns the line number at `end`.
+    fn fill(
+        checkpoints: &mut Vec<Checkpoint>,
+        doc: &dyn ReadableDocument,
+        from: Checkpoint,
+        end: usize,
+    ) -> CoordType {
+        let mut pos = from;
+        loop {
+            let stop = pos.line + CHECKPOINT_STRIDE;
+            pos = Self::seek(doc, pos, end, stop);
+            if pos.line < stop || pos.offset >= end {
+                return pos.line;
+            }
+            checkpoints.push(pos);
+        }
+    }
+
+    /// Counts lines from `from` until `line_stop` is reached or until `end`.
+    fn seek(
+        doc: &dyn ReadableDocument,
+        from: Checkpoint,
+        end: usize,
+        line_stop: CoordType,
+    ) -> Checkpoint {
+        let mut offset = from.offset;
+        let mut line = from.line;
+
+        while offset < end && line < line_stop {
+            let chunk = doc.read_forward(offset);
+            if chunk.is_empty() {
+                break;
+            }
+            let chunk = &chunk[..chunk.len().min(end - offset)];
+            let (off, l) = lines_fwd(chunk, 0, line, line_stop);
+            offset += off;
+            line = l;
+        }
+
+        Checkpoint { offset, line }
+    }
+}
+
+impl TextBuffer {
+    /// Returns the number of lines in the document. An empty document has 1 line.
+    pub fn line_count(&self) -> CoordType {
+        self.line_index.newlines() + 1
+    }
+
+    /// Returns the offset at which the given `line` starts.
+    ///
+    /// `line` is clamped to the valid range of lines.
+    pub fn line_start_offset(&self, line: CoordType) -> usize {
+        let line = line.clamp(0, self.line_index.newlines());
+        self.line_index.line_start(&self.buffer, self.text_length(), line)
+    }
+
+    /// Returns the offset at which the given `line` ends, excluding the line break.
+    ///
+    /// `line` is clamped to the valid range of lines.
+    pub fn line_end_offset(&self, line: CoordType) -> usize {
+        let line = line.clamp(0, self.line_index.newlines());
+        let start = self.line_index.line_start(&self.buffer, self.text_length(), line);
+        self.line_end_from(start, line)
+    }
+
+    /// Returns the logical position of `offset`: `y` is the line and
+    /// `x` is the visual column, with wide characters and tabs expanded.
+    /// Offsets inside a grapheme cluster map to the start of it.
+    pub fn offset_to_logical(&self, offset: usize) -> Point {
+        let offset = offset.min(self.text_length());
+        let (line, start) = self.line_index.line_of(&self.buffer, offset);
+        self.measurement_at_line(start, line).goto_offset(offset).logical_pos
+    }
+
+    /// Returns the offset at the logical position `pos`, which is the inverse of
+    /// [`TextBuffer::offset_to_logical`]. Positions past the end of a line map
+    /// to the end of that line. Positions inside a wide grapheme cluster or tab map
+    /// to the start of it.
+    pub fn logical_to_offset(&self, pos: Point) -> usize {
+        let line = pos.y.clamp(0, self.line_index.newlines());
+        let start = self.line_index.line_start(&self.buffer, self.text_length(), line);
+        self.measurement_at_line(start, line).goto_logical(Point { x: pos.x, y: line }).offset
+    }
+
+    /// Returns a [`MeasurementConfig`] for this buffer, starting at the start of `line`.
+    pub(super) fn measurement_at_line(&self, start: usize, line: CoordType) -> MeasurementConfig<'_> {
+        MeasurementConfig::new(&self.buffer)
+            .with_tab_size(self.tab_size)
+            .with_cursor(Cursor { offset: start, logical_pos: Point { x: 0, y: line } })
+    }
+
+    fn line_end_from(&self, start: usize, line: CoordType) -> usize {
+        let len = self.text_length();
+        let next = LineIndex::seek(&self.buffer, Checkpoint { offset: start, line }, len, line + 1);
+        if next.line == line {
+            // The last line has no line break.
+            return len;
+        }
+
+        let mut end = next.offset - 1;
+        if end > start && self.buffer.read_backward(end).last() == Some(&b'\r') {
+            end -= 1;
+        }
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks every lookup against a naive scan of `text`.
+    fn check(tb: &TextBuffer, text: &[u8]) {
+        let newlines = text.iter().enumerate().filter(|(_, b)| **b == b'\n');
+        let starts: Vec<usize> = std::iter::once(0).chain(newlines.map(|(i, _)| i + 1)).collect();
+        assert_eq!(tb.line_count() as usize, starts.len());
+
+        for (line, &start) in starts.iter().enumerate() {
+            let y = line as CoordType;
+            let end = starts.get(line + 1).map_or(text.len(), |&next| next - 1);
+            assert_eq!(tb.line_start_offset(y), start, "line {line}");
+            assert_eq!(tb.line_end_offset(y), end, "line {line}");
+            assert_eq!(tb.offset_to_logical(end), Point { x: (end - start) as CoordType, y }, "line {line}");
+            assert_eq!(tb.logical_to_offset(Point { x: 1, y }), (start + 1).min(end), "line {line}");
+            assert_eq!(tb.logical_to_offset(Point { x: CoordType::MAX, y }), end, "line {line}");
+        }
+    }
+
+    fn new_buffer(text: &[u8]) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text);
+        tb
+    }
+
+    #[test]
+    fn test_small() {
+        let text = b"ab\n\ncde\n";
+        let tb = new_buffer(text);
+        check(&tb, text);
+        assert_eq!(tb.offset_to_logical(1), Point { x: 1, y: 0 });
+        assert_eq!(tb.offset_to_logical(5), Point { x: 1, y: 2 });
+        assert_eq!(tb.logical_to_offset(Point { x: 2, y: 99 }), 8);
+    }
+
+    #[test]
+    fn test_edits_across_checkpoints() {
+        // Lines of varying length, enough for a few checkpoints.
+        let mut text = Vec::new();
+        for i in 0..5 * CHECKPOINT_STRIDE as usize {
+            text.extend(std::iter::repeat_n(b'x', i % 7));
+            text.push(b'\n');
+        }
+        let mut tb = new_buffer(&text);
+        check(&tb, &text);
+
+        // (offset, deleted bytes, inserted text)
+        let pasted: Vec<u8> = b"p\n".repeat(3 * CHECKPOINT_STRIDE as usize);
+        let edits: [(usize, usize, &[u8]); 5] = [
+            // Typing within a line and splitting a line.
+            (5000, 0, b"abc"),
+            (7000, 0, b"\n"),
+            // Deleting a range that spans more than one checkpoint.
+            (2000, 9000, b""),
+            // Pasting enough lines that the gap gets filled with new checkpoints.
+            (100, 0, &pasted),
+            (text.len() - 9000 + pasted.len() + 3, 0, b"end\nx"),
+        ];
+        for (offset, deleted, added) in edits {
+            tb.replace(offset..offset + deleted, added);
+            text.splice(offset..offset + deleted, added.iter().copied());
+            check(&tb, &text);
+        }
+    }
+}
@@ -1,13 +1,91 @@
 
-use crate::{apperr, cell::SemiRefCell};
-use std::{fs::File, rc::Rc};
+mod clipboard;
+mod gap_buffer;
+mod history;
+mod indentation;
+mod line_endings;
+mod line_index;
+mod navigation;
+mod search;
+mod word_wrap;
+
+use crate::{
+    apperr,
+    cell::SemiRefCell,
+    document::ReadableDocument,
+    encoding::{self, Decoder, Encoder},
+    helpers::{CoordType, KIBI},
+    sys,
+};
+use gap_buffer::GapBuffer;
+use history::{History, HistoryType};
+pub use line_endings::LineEndings;
+use line_index::LineIndex;
+use search::ActiveSearch;
+pub use search::{SearchCount, SearchOptions};
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+};
+use word_wrap::WordWrap;
+pub use word_wrap::{VisualLineStart, VisualRow};
+
+/// How much we read from a file at a time. Each chunk is followed by a progress report.
+const READ_CHUNK_SIZE: usize = 256 * KIBI;
+/// How much of a file we look at to detect its encoding.
+const DETECT_SIZE: usize = 4 * KIBI;
 
 pub type TextBufferCell = SemiRefCell<TextBuffer>;
 
 pub type RcTextBuffer = Rc<TextBufferCell>;
 
+/// Passed to the progress callback of [`TextBuffer::read_file_with_progress`].
+#[derive(Clone, Copy)]
+pub struct ReadProgress {
+    /// Number of bytes read so far.
+    pub read: usize,
+    /// The size of the file, if known. Pipes, for instance, don't have one.
+    pub total: Option<usize>,
+}
+
+/// The text of a document and everything that's needed to edit it.
+///
+/// The text is stored as raw bytes in a [`GapBuffer`]. It's usually UTF-8,
+/// but the buffer doesn't enforce that. Offsets are byte offsets.
 pub struct TextBuffer {
+    buffer: GapBuffer,
+    /// The [`GapBuffer::generation`] at the time the buffer was last saved.
+    /// The buffer is dirty as long as the two differ.
+    last_save_generation: u32,
+    /// The most recent generation handed out by [`TextBuffer::bump_generation`].
+    newest_generation: u32,
+    history: History,
+    line_index: LineIndex,
+    /// The number of `\r\n` in the text. See [`TextBuffer::line_endings`].
+    crlf_count: usize,
+    /// Whether new line breaks are `\r\n`, if the text doesn't tell by itself.
+    newlines_are_crlf: bool,
+    /// The number of columns between tab stops. It's also the width of an indentation level.
+    tab_size: CoordType,
+    /// Whether the Tab key indents with tabs or with spaces.
+    indent_with_tabs: bool,
+    /// The encoding of the file. The text in the buffer is converted from and to it.
+    encoding: &'static str,
+    /// Whether the file starts with a byte order mark.
+    bom: bool,
+
+    /// Byte offset of the cursor.
+    cursor: usize,
+    /// If there's a selection, this is where it started. It ends at the cursor.
+    selection_anchor: Option<usize>,
 
+    search: Option<ActiveSearch>,
+    word_wrap: WordWrap,
 }
 
 impl TextBuffer {
@@ -16,17 +94,748 @@ impl TextBuffer {
         Ok(Rc::new(SemiRefCell::new(buffer)))
     }
 
+    /// Creates a new, empty buffer.
+    ///
+    /// Pass `small = true` for buffers that will only ever hold a little bit
+    /// of text, like single-line text inputs. Those use the regular heap instead
+    /// of reserving a large chunk of virtual memory up front.
     pub fn new(small: bool) -> apperr::Result<Self> {
         Ok(Self {
-            
+            buffer: GapBuffer::new(small)?,
+            last_save_generation: 0,
+            newest_generation: 0,
+            history: Default::default(),
+            line_index: Default::default(),
+            crlf_count: 0,
+            newlines_are_crlf: cfg!(windows),
+            tab_size: 4,
+            indent_with_tabs: false,
+            encoding: encoding::UTF8,
+            bom: false,
+
+            cursor: 0,
+            selection_anchor: None,
+
+            search: None,
+            word_wrap: Default::default(),
         })
     }
 
+    /// Length of the text in bytes.
+    pub fn text_length(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.len() == 0
+    }
+
+    /// The number of columns between tab stops.
+    pub fn tab_size(&self) -> CoordType {
+        self.tab_size
+    }
+
+    /// Sets the number of columns between tab stops. Clamped to 1..=8.
+    pub fn set_tab_size(&mut self, tab_size: CoordType) {
+        let tab_size = tab_size.clamp(1, 8);
+        if self.tab_size != tab_size {
+            self.tab_size = tab_size;
+            self.reflow();
+        }
+    }
+
+    /// The encoding that the file was read in and will be saved in.
+    pub fn encoding(&self) -> &'static str {
+        self.encoding
+    }
+
+    /// Whether the file starts with a byte order mark.
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    /// Sets the encoding that the file will be saved in. The text itself stays the same.
+    /// Since the file will change nonetheless, the buffer is marked as dirty.
+    ///
+    /// `bom` is ignored for encodings that don't have a byte order mark.
+    pub fn set_encoding(&mut self, encoding: &'static str, bom: bool) {
+        let bom = bom && !encoding::bom(encoding).is_empty();
+        if self.encoding != encoding || self.bom != bom {
+            self.encoding = encoding;
+            self.bom = bom;
+            self.mark_as_dirty();
+        }
+    }
+
+    /// A counter that changes whenever the text is modified.
+    pub fn generation(&self) -> u32 {
+        self.buffer.generation()
+    }
+
+    /// Replaces the contents of the buffer with the contents of the given file.
+    ///
+    /// See [`TextBuffer::read_file_with_progress`].
     pub fn read_file(&mut self, file: &mut File, encoding: Option<&'static str>) -> apperr::Result<()> {
+        self.read_file_with_progress(file, encoding, &mut |_| true)
+    }
+
+    /// Replaces the contents of the buffer with the contents of the given file.
+    ///
+    /// The file is converted from `encoding` to UTF-8. If it's `None`, the encoding is detected
+    /// from the byte order mark, or else it's UTF-8 (or UTF-16 if it looks like that).
+    /// If the encoding can't be used, e.g. because ICU is missing, the buffer is left untouched.
+    ///
+    /// UTF-8 is read chunk by chunk straight into the gap of the buffer, so no matter how
+    /// large the file is, it's never held in memory twice. `progress` is called after every
+    /// chunk. If it returns `false`, reading stops, the buffer is left empty and
+    /// [`apperr::APP_CANCELLED`] is returned.
+    ///
+    /// Afterwards the buffer is considered clean.
+    pub fn read_file_with_progress(
+        &mut self,
+        file: &mut File,
+        encoding: Option<&'static str>,
+        progress: &mut dyn FnMut(ReadProgress) -> bool,
+    ) -> apperr::Result<()> {
+        let decoder = match encoding {
+            Some(encoding) if encoding != encoding::UTF8 => Some(Decoder::new(encoding)?),
+            _ => None,
+        };
+
+        let total = file.metadata().ok().filter(|m| m.is_file()).map(|m| m.len() as usize);
+
+        self.buffer.replace(0..self.buffer.len(), b"");
+
+        let res = self.read_file_detect(file, total, encoding, decoder, progress);
+        (self.encoding, self.bom) = match res {
+            Ok(detected) => detected,
+            Err(_) => {
+                self.buffer.replace(0..self.buffer.len(), b"");
+                (encoding::UTF8, false)
+            }
+        };
+
+        self.line_index.rebuild(&self.buffer, self.buffer.len());
+        self.line_endings_rebuild();
+        self.detect_indentation();
+        self.reflow();
+
+        // Loading a file can't be undone.
+        self.history_clear();
+        self.cursor = 0;
+        self.selection_anchor = None;
+        self.bump_generation();
+        self.mark_as_clean();
+        res.map(|_| ())
+    }
+
+    /// Reads the file into the empty buffer and returns its encoding and whether it had a BOM.
+    fn read_file_detect(
+        &mut self,
+        file: &mut File,
+        total: Option<usize>,
+        encoding: Option<&'static str>,
+        decoder: Option<Decoder>,
+        progress: &mut dyn FnMut(ReadProgress) -> bool,
+    ) -> apperr::Result<(&'static str, bool)> {
+        let mut head = vec![0; DETECT_SIZE];
+        let mut head_len = 0;
+        while head_len < head.len() {
+            match file.read(&mut head[head_len..]) {
+                Ok(0) => break,
+                Ok(read) => head_len += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        head.truncate(head_len);
+
+        let (encoding, bom) = match encoding {
+            Some(encoding) => (encoding, head.starts_with(encoding::bom(encoding))),
+            None => encoding::detect(&head),
+        };
+        let bom = bom && !encoding::bom(encoding).is_empty();
+        let text = &head[if bom { encoding::bom(encoding).len() } else { 0 }..];
+
+        if encoding == encoding::UTF8 {
+            // Regular files tell us their size up front. Growing the gap to that size in one go
+            // avoids moving the text every time the gap runs full. The read loop below will still
+            // pick up any bytes that got appended to the file in the meantime.
+            if let Some(total) = total {
+                self.buffer.allocate_gap(0, total, 0);
+                self.buffer.commit_gap(0);
+            }
+            self.append_raw(text)?;
+            self.read_file_chunks(file, total, progress)?;
+        } else {
+            // Only encodings that need ICU can fail here, and those are never detected.
+            let decoder = match decoder {
+                Some(decoder) => decoder,
+                None => Decoder::new(encoding)?,
+            };
+            self.read_file_decoded(file, total, head_len, text, decoder, progress)?;
+        }
+
+        Ok((encoding, bom))
+    }
+
+    fn read_file_chunks(
+        &mut self,
+        file: &mut File,
+        total: Option<usize>,
+        progress: &mut dyn FnMut(ReadProgress) -> bool,
+    ) -> apperr::Result<()> {
+        loop {
+            let off = self.buffer.len();
+            let gap = self.buffer.allocate_gap(off, READ_CHUNK_SIZE, 0);
+            if gap.is_empty() {
+                // We hit the capacity of the buffer (or the system ran out of memory).
+                return Err(apperr::Error::new_sys(libc::ENOMEM as u32));
+            }
+
+            // Don't hand the entire gap to `read()`, or else we'd only
+            // report progress once for files that fit into the gap.
+            let len = gap.len().min(READ_CHUNK_SIZE);
+            let read = match file.read(&mut gap[..len]) {
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if read == 0 {
+                return Ok(());
+            }
+
+            self.buffer.commit_gap(read);
+
+            if !progress(ReadProgress { read: self.buffer.len(), total }) {
+                return Err(apperr::APP_CANCELLED);
+            }
+        }
+    }
+
+    /// Like [`TextBuffer::read_file_chunks`], but for files that need to be converted to UTF-8.
+    /// `head` is the beginning of the file, which was already read, and `read` is its length.
+    fn read_file_decoded(
+        &mut self,
+        file: &mut File,
+        total: Option<usize>,
+        mut read: usize,
+        head: &[u8],
+        mut decoder: Decoder,
+        progress: &mut dyn FnMut(ReadProgress) -> bool,
+    ) -> apperr::Result<()> {
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        let mut decoded = Vec::new();
+
+        decoder.decode(head, false, &mut decoded)?;
+        self.append_raw(&decoded)?;
+
+        loop {
+            let len = match file.read(&mut chunk) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            decoded.clear();
+            decoder.decode(&chunk[..len], len == 0, &mut decoded)?;
+            self.append_raw(&decoded)?;
+
+            if len == 0 {
+                return Ok(());
+            }
+
+            read += len;
+            if !progress(ReadProgress { read, total }) {
+                return Err(apperr::APP_CANCELLED);
+            }
+        }
+    }
+
+    /// Appends `text` while a file is being read.
+    fn append_raw(&mut self, text: &[u8]) -> apperr::Result<()> {
+        let len = self.buffer.len();
+        self.buffer.replace(len..len, text);
+        if self.buffer.len() != len + text.len() {
+            return Err(apperr::Error::new_sys(libc::ENOMEM as u32));
+        }
         Ok(())
     }
 
+    /// Saves the text to the file at `path`.
+    ///
+    /// To not lose any data if we crash or the disk runs full halfway through, the text is
+    /// first written to a temporary file next to the original, which is then fsync'ed and
+    /// renamed over the original. The permissions and (if we're allowed to) the ownership
+    /// of the original are carried over. If `path` is a symlink, the file it points to
+    /// gets replaced and the symlink is left as is.
+    ///
+    /// The text is converted to [`TextBuffer::encoding`].
+    /// The buffer is marked as clean only if the save succeeded.
+    pub fn write_file(&mut self, path: &Path) -> apperr::Result<()> {
+        // Fail before touching the file if the encoding can't be used, e.g. because ICU is missing.
+        let mut encoder = match self.encoding {
+            encoding::UTF8 => None,
+            encoding => Some(Encoder::new(encoding)?),
+        };
+
+        // Resolve symlinks so that we replace the file they point to and not the link itself.
+        let target = match fs::canonicalize(path) {
+            Ok(target) => target,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+            Err(err) => return Err(err.into()),
+        };
+        let metadata = match fs::metadata(&target) {
+            Ok(metadata) => Some(metadata),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        match Self::create_temp_file(&target) {
+            Ok((mut file, temp_path)) => {
+                let res =
+                    self.write_file_atomic(&mut file, &temp_path, &target, metadata.as_ref(), encoder.as_mut());
+                if res.is_err() {
+                    _ = fs::remove_file(&temp_path);
+                }
+                res?;
+            }
+            // We may be allowed to modify the file, but not the directory it's in (for instance
+            // a group-writable file in /etc). The best we can do then is to overwrite it in place.
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied && metadata.is_some() => {
+                let mut file = File::options().write(true).truncate(true).open(&target)?;
+                self.write_contents(&mut file, encoder.as_mut())?;
+                file.sync_all()?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        self.mark_as_clean();
+        Ok(())
+    }
+
+    /// Creates a new, uniquely named, hidden file in the same directory as `target`.
+    /// It needs to be on the same filesystem, because `rename()` can't cross filesystems.
+    fn create_temp_file(target: &Path) -> io::Result<(File, PathBuf)> {
+        let dir = target.parent().unwrap_or(Path::new(""));
+        let name = target.file_name().unwrap_or_default();
+        let pid = process::id();
+
+        for attempt in 0..100 {
+            let mut temp_name = OsString::with_capacity(name.len() + 32);
+            temp_name.push(".");
+            temp_name.push(name);
+            temp_name.push(format!(".{pid}-{attempt}.wdtmp"));
+
+            let temp_path = dir.join(temp_name);
+            match File::options().write(true).create_new(true).open(&temp_path) {
+                Ok(file) => return Ok((file, temp_path)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(io::ErrorKind::AlreadyExists.into())
+    }
+
+    fn write_file_atomic(
+        &self,
+        file: &mut File,
+        temp_path: &Path,
+        target: &Path,
+        metadata: Option<&fs::Metadata>,
+        encoder: Option<&mut Encoder>,
+    ) -> apperr::Result<()> {
+        self.write_contents(file, encoder)?;
+        if let Some(metadata) = metadata {
+            sys::copy_file_metadata(file, metadata)?;
+        }
+        file.sync_all()?;
+
+        fs::rename(temp_path, target)?;
+
+        // The rename() is only durable once the directory entry is flushed as well.
+        // Not every filesystem supports fsync() on directories, so this is best effort.
+        if let Some(dir) = target.parent()
+            && let Ok(dir) = File::open(dir)
+        {
+            _ = dir.sync_all();
+        }
+
+        Ok(())
+    }
+
+    fn write_contents(&self, file: &mut File, mut encoder: Option<&mut Encoder>) -> apperr::Result<()> {
+        if self.bom {
+            file.write_all(encoding::bom(self.encoding))?;
+        }
+
+        let mut encoded = Vec::new();
+        let mut off = 0;
+        loop {
+            let chunk = self.read_forward(off);
+            off += chunk.len();
+
+            match encoder.as_deref_mut() {
+                None => file.write_all(chunk)?,
+                Some(encoder) => {
+                    encoded.clear();
+                    encoder.encode(chunk, chunk.is_empty(), &mut encoded)?;
+                    file.write_all(&encoded)?;
+                }
+            }
+
+            if chunk.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns `true` if the text was modified since the last save.
+    pub fn is_dirty(&self) -> bool {
+        self.last_save_generation != self.buffer.generation()
+    }
+
+    /// Forces the buffer to be considered modified, e.g. after reading from stdin,
+    /// because such a document has never been saved anywhere.
     pub fn mark_as_dirty(&mut self) {
+        // A generation that no text has had or will have, so that not even undo can make it clean.
+        self.newest_generation = self.newest_generation.wrapping_add(1);
+        self.last_save_generation = self.newest_generation;
+    }
+
+    fn mark_as_clean(&mut self) {
+        self.last_save_generation = self.buffer.generation();
+        // Otherwise typing after a save would merge into the undo step from before it,
+        // and undo would skip right past the saved text.
+        self.history_break_merge();
+    }
+
+    /// Returns the byte offset of the cursor.
+    pub fn cursor_offset(&self) -> usize {
+        self.cursor
+    }
+
+    /// Moves the cursor to the byte offset `off` and clears the selection.
+    pub fn set_cursor_offset(&mut self, off: usize) {
+        self.cursor = off.min(self.text_length());
+        self.selection_anchor = None;
+        self.history_break_merge();
+    }
+
+    /// Returns the selected byte range, if there's a non-empty selection.
+    pub fn selection_range(&self) -> Option<Range<usize>> {
+        let anchor = self.selection_anchor?;
+        match anchor.cmp(&self.cursor) {
+            std::cmp::Ordering::Less => Some(anchor..self.cursor),
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater => Some(self.cursor..anchor),
+        }
+    }
+
+    /// Returns where the selection started. The cursor is at its other end.
+    pub fn selection_anchor(&self) -> Option<usize> {
+        self.selection_anchor
+    }
+
+    /// Selects the text between `anchor` and `cursor` and moves the cursor to the latter.
+    pub fn set_selection(&mut self, anchor: usize, cursor: usize) {
+        let len = self.text_length();
+        self.selection_anchor = Some(anchor.min(len));
+        self.cursor = cursor.min(len);
+        self.history_break_merge();
+    }
+
+    /// Selects the entire text.
+    pub fn select_all(&mut self) {
+        self.set_selection(0, self.text_length());
+    }
+
+    /// Inserts `text` at the byte offset `off`.
+    ///
+    /// `off` is clamped to the length of the text.
+    pub fn insert(&mut self, off: usize, text: &[u8]) {
+        self.replace(off..off, text);
+    }
+
+    /// Deletes the given byte `range`.
+    ///
+    /// The range is clamped to the length of the text.
+    pub fn delete(&mut self, range: Range<usize>) {
+        self.replace(range, b"");
+    }
+
+    /// Replaces the given byte `range` with `text`.
+    ///
+    /// This is recorded as an undo step of its own.
+    /// Afterwards the cursor is placed at the end of `text`.
+    pub fn replace(&mut self, range: Range<usize>, text: &[u8]) {
+        self.edit(HistoryType::Other, range, text);
+    }
+
+    /// Types `text` at the cursor, replacing the selection if there's one.
+    ///
+    /// Text typed in a row is undone in one go.
+    pub fn write(&mut self, text: &[u8]) {
+        let range = self.selection_range().unwrap_or(self.cursor..self.cursor);
+        self.edit(HistoryType::Write, range, text);
+    }
+
+    /// Deletes the selection, or if there's none, the character before the cursor.
+    ///
+    /// Characters deleted in a row are restored in one go.
+    pub fn delete_backward(&mut self) {
+        let range = match self.selection_range() {
+            Some(range) => range,
+            None => self.prev_char_offset(self.cursor)..self.cursor,
+        };
+        self.edit(HistoryType::Delete, range, b"");
+    }
+
+    /// Deletes the selection, or if there's none, the character after the cursor.
+    ///
+    /// Characters deleted in a row are restored in one go.
+    pub fn delete_forward(&mut self) {
+        let range = match self.selection_range() {
+            Some(range) => range,
+            None => self.cursor..self.next_char_offset(self.cursor),
+        };
+        self.edit(HistoryType::Delete, range, b"");
+    }
+
+    fn edit(&mut self, history_type: HistoryType, range: Range<usize>, text: &[u8]) {
+        let len = self.text_length();
+        let beg = range.start.min(len);
+        let end = range.end.clamp(beg, len);
+        if beg == end && text.is_empty() {
+            return;
+        }
+
+        self.history_push_change(history_type, beg..end, text);
+        self.replace_raw(beg..end, text);
+        self.cursor = beg + text.len();
+        self.selection_anchor = None;
+        self.bump_generation();
+        self.history_finish_change();
+    }
+
+    /// Replaces `range` in the underlying buffer with `text` and updates the line index,
+    /// the count of line endings and the cached visual lines.
+    /// All edits after loading a file must go through here.
+    fn replace_raw(&mut self, range: Range<usize>, text: &[u8]) {
+        // An edit can also complete or split a `\r\n` right at its edges.
+        let edges = |end: usize, len: usize| range.start.saturating_sub(1)..(end + 1).min(len);
+        let crlf_removed = self.count_crlf(edges(range.end, self.buffer.len()));
+
+        let removed = LineIndex::count_newlines(&self.buffer, range.clone());
+        let first_line = self.word_wrap_has_cache().then(|| self.offset_to_logical(range.start).y);
+        let newlines_before = self.line_index.newlines();
+        self.buffer.replace(range.clone(), text);
+        self.line_index.replace(&self.buffer, self.buffer.len(), range.clone(), text, removed);
+
+        if let Some(first_line) = first_line {
+            let added = self.line_index.newlines() - newlines_before + removed;
+            self.word_wrap_invalidate(first_line, removed, added);
+        }
+
+        let crlf_added = self.count_crlf(edges(range.start + text.len(), self.buffer.len()));
+        self.crlf_count = self.crlf_count + crlf_added - crlf_removed;
+    }
+
+    /// Gives the current text a generation that has never been used before.
+    ///
+    /// Undo and redo reset the generation to that of an earlier text. If a subsequent edit
+    /// simply incremented it, it could produce the generation of the saved text again,
+    /// which would make a modified document look clean.
+    fn bump_generation(&mut self) {
+        self.newest_generation = self.newest_generation.wrapping_add(1);
+        self.buffer.set_generation(self.newest_generation);
+    }
+
+    /// Returns the offset of the UTF-8 character that precedes `off`.
+    fn prev_char_offset(&self, off: usize) -> usize {
+        let mut off = off.min(self.text_length());
+        // Step back over at most 3 continuation bytes to the lead byte.
+        for _ in 0..4 {
+            if off == 0 {
+                break;
+            }
+            off -= 1;
+            if self.read_forward(off)[0] & 0b1100_0000 != 0b1000_0000 {
+                break;
+            }
+        }
+        off
+    }
+
+    /// Returns the offset of the UTF-8 character that follows the one at `off`.
+    fn next_char_offset(&self, off: usize) -> usize {
+        let len = self.text_length();
+        let mut off = off;
+        for i in 0..4 {
+            if off >= len || (i != 0 && self.read_forward(off)[0] & 0b1100_0000 != 0b1000_0000) {
+                break;
+            }
+            off += 1;
+        }
+        off.min(len)
+    }
+
+    /// Appends the contents of the given byte `range` to `out`.
+    pub fn extract_raw(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        self.buffer.extract_raw(range, out);
+    }
+}
+
+impl ReadableDocument for TextBuffer {
+    fn read_forward(&self, off: usize) -> &[u8] {
+        self.buffer.read_forward(off)
+    }
+
+    fn read_backward(&self, off: usize) -> &[u8] {
+        self.buffer.read_backward(off)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_buffer(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb
+    }
+
+    /// Returns a fresh, empty directory for the test called `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wd-test-{}-{name}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn dir_entries(dir: &Path) -> Vec<OsString> {
+        let mut names: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_read_file() {
+        let dir = test_dir("read");
+        let path = dir.join("a.txt");
+        // Spans several chunks and doesn't end on a chunk boundary.
+        let text: Vec<u8> = (0..3 * READ_CHUNK_SIZE + 5).map(|i| b"abc\n"[i % 4]).collect();
+        fs::write(&path, &text).unwrap();
+
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.read_file(&mut File::open(&path).unwrap(), None).unwrap();
+        let mut out = Vec::new();
+        tb.extract_raw(0..tb.text_length(), &mut out);
+        assert!(out == text);
+        assert_eq!(tb.line_count(), (3 * READ_CHUNK_SIZE / 4 + 2) as CoordType);
+        assert!(!tb.is_dirty());
+
+        fs::write(&path, b"\xff\xfea\x00\n\x00").unwrap();
+        tb.read_file(&mut File::open(&path).unwrap(), None).unwrap();
+        out.clear();
+        tb.extract_raw(0..tb.text_length(), &mut out);
+        assert_eq!(out, b"a\n");
+        assert_eq!(tb.encoding(), encoding::UTF16LE);
+
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_file_progress() {
+        let dir = test_dir("progress");
+        let path = dir.join("a.txt");
+        fs::write(&path, vec![b'x'; 2 * READ_CHUNK_SIZE + 1]).unwrap();
+        let mut tb = TextBuffer::new(false).unwrap();
+
+        // Every chunk is reported, even though the whole file fits into the gap.
+        let mut reports = Vec::new();
+        tb.read_file_with_progress(&mut File::open(&path).unwrap(), None, &mut |p| {
+            reports.push((p.read, p.total));
+            true
+        })
+        .unwrap();
+        let total = Some(2 * READ_CHUNK_SIZE + 1);
+        assert_eq!(reports, [(DETECT_SIZE + READ_CHUNK_SIZE, total), (2 * READ_CHUNK_SIZE + 1, total)]);
+
+        let res = tb.read_file_with_progress(&mut File::open(&path).unwrap(), None, &mut |_| false);
+        assert_eq!(res, Err(apperr::APP_CANCELLED));
+        assert_eq!(tb.text_length(), 0);
+        assert!(!tb.is_dirty());
+
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_file() {
+        let dir = test_dir("write");
+        let path = dir.join("a.txt");
+        let mut tb = new_buffer("hello\n");
+        assert!(tb.is_dirty());
+
+        tb.write_file(&path).unwrap();
+        assert!(!tb.is_dirty());
+        assert_eq!(fs::read(&path).unwrap(), b"hello\n");
+        assert_eq!(dir_entries(&dir), ["a.txt"]);
+
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_file_failure() {
+        let dir = test_dir("failure");
+        // rename() can't replace a directory with a file, so the write fails after the temp file was written.
+        let path = dir.join("sub");
+        fs::create_dir(&path).unwrap();
+        let mut tb = new_buffer("hello\n");
+
+        assert!(tb.write_file(&path).is_err());
+        assert!(tb.is_dirty());
+        assert_eq!(dir_entries(&dir), ["sub"]);
+
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_symlink() {
+        let dir = test_dir("symlink");
+        let real = dir.join("real.txt");
+        let link = dir.join("link.txt");
+        fs::write(&real, "old\n").unwrap();
+        std::os::unix::fs::symlink("real.txt", &link).unwrap();
+        let mut tb = new_buffer("new\n");
+
+        tb.write_file(&link).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("real.txt"));
+        assert_eq!(fs::read(&real).unwrap(), b"new\n");
+        assert_eq!(dir_entries(&dir), ["link.txt", "real.txt"]);
+
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_permissions() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = test_dir("permissions");
+        let path = dir.join("script.sh");
+        fs::write(&path, "old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        let mut tb = new_buffer("new\n");
+
+        tb.write_file(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o750);
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
 
+        _ = fs::remove_dir_all(&dir);
     }
 }
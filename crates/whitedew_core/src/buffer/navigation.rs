@@ -0,0 +1,509 @@
+
+//! Moving the cursor of a [`TextBuffer`] by grapheme clusters, words and lines,
+//! and finding the words and lines that double and triple clicks select.
+//! Moving it up and down by visual lines lives in [`super::word_wrap`].
+
+use super::TextBuffer;
+use crate::{
+    document::ReadableDocument,
+    helpers::{CoordType, Point},
+    unicode::{Cursor, MeasurementConfig, WordBreak, word_break},
+};
+use std::ops::Range;
+
+/// How many bytes [`TextBuffer::clusters_rev`] segments at a time.
+const BACKWARD_WINDOW: usize = 256;
+
+/// What kind of character a grapheme cluster starts with, for finding word boundaries.
+///
+/// Mostly the Word_Break property of UAX #29, whose rules [`CharClass::joins`] implements.
+/// Its Other is split up further, because unlike UAX #29 we treat runs of whitespace and
+/// punctuation as words, too.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Whitespace,
+    /// Letters of most scripts, including hangul.
+    Letter,
+    HebrewLetter,
+    Numeric,
+    Katakana,
+    /// `_` and other connector punctuation, which joins letters, digits and katakana.
+    ExtendNumLet,
+    /// `:` and the like, which joins letters.
+    MidLetter,
+    /// `,`, `;` and the like, which joins digits.
+    MidNum,
+    /// `.` and the like, which joins both.
+    MidNumLet,
+    /// `'`, which joins both, like [`CharClass::MidNumLet`], and follows Hebrew letters.
+    SingleQuote,
+    /// `"`, which joins Hebrew letters.
+    DoubleQuote,
+    /// Han ideographs, hiragana and the letters of scripts that would need a dictionary
+    /// to find words, like Thai. UAX #29 doesn't join them, so each is a word of its own.
+    Standalone,
+    Punctuation,
+}
+
+impl CharClass {
+    fn of(c: char) -> Self {
+        match word_break(c) {
+            WordBreak::ALetter => Self::Letter,
+            WordBreak::HebrewLetter => Self::HebrewLetter,
+            WordBreak::Numeric => Self::Numeric,
+            WordBreak::Katakana => Self::Katakana,
+            WordBreak::ExtendNumLet => Self::ExtendNumLet,
+            WordBreak::MidLetter => Self::MidLetter,
+            WordBreak::MidNum => Self::MidNum,
+            WordBreak::MidNumLet => Self::MidNumLet,
+            WordBreak::SingleQuote => Self::SingleQuote,
+            WordBreak::DoubleQuote => Self::DoubleQuote,
+            WordBreak::WSegSpace => Self::Whitespace,
+            _ if c.is_whitespace() => Self::Whitespace,
+            _ if c.is_alphanumeric() => Self::Standalone,
+            _ => Self::Punctuation,
+        }
+    }
+
+    fn is_ahletter(self) -> bool {
+        matches!(self, Self::Letter | Self::HebrewLetter)
+    }
+
+    fn is_mid_letter(self) -> bool {
+        matches!(self, Self::MidLetter | Self::MidNumLet | Self::SingleQuote)
+    }
+
+    fn is_mid_num(self) -> bool {
+        matches!(self, Self::MidNum | Self::MidNumLet | Self::SingleQuote)
+    }
+
+    fn is_punctuation(self) -> bool {
+        matches!(self, Self::Punctuation | Self::DoubleQuote) || self.is_mid_letter() || self.is_mid_num()
+    }
+
+    /// Returns `true` if there's no word boundary between `b` and `c`, where `a` is the class
+    /// of the cluster that precedes `b` and `d` that of the one that follows `c`, if any.
+    /// The rule numbers are those of UAX #29.
+    fn joins(a: Option<Self>, b: Self, c: Self, d: Option<Self>) -> bool {
+        let a = a.unwrap_or(Self::Whitespace);
+        let d = d.unwrap_or(Self::Whitespace);
+        match (b, c) {
+            // WB3d, extended to all whitespace.
+            (Self::Whitespace, Self::Whitespace) => true,
+            // WB5, WB8, WB9, WB10
+            (b, c) if (b.is_ahletter() || b == Self::Numeric) && (c.is_ahletter() || c == Self::Numeric) => true,
+            // WB6, WB7
+            (b, c) if b.is_ahletter() && c.is_mid_letter() && d.is_ahletter() => true,
+            (b, c) if a.is_ahletter() && b.is_mid_letter() && c.is_ahletter() => true,
+            // WB7a, WB7b, WB7c
+            (Self::HebrewLetter, Self::SingleQuote) => true,
+            (Self::HebrewLetter, Self::DoubleQuote) if d == Self::HebrewLetter => true,
+            (Self::DoubleQuote, Self::HebrewLetter) if a == Self::HebrewLetter => true,
+            // WB11, WB12
+            (Self::Numeric, c) if c.is_mid_num() && d == Self::Numeric => true,
+            (b, Self::Numeric) if b.is_mid_num() && a == Self::Numeric => true,
+            // WB13, WB13a, WB13b
+            (Self::Katakana, Self::Katakana) => true,
+            (x, Self::ExtendNumLet) | (Self::ExtendNumLet, x)
+                if x.is_ahletter() || matches!(x, Self::Numeric | Self::Katakana | Self::ExtendNumLet) =>
+            {
+                true
+            }
+            // Not in UAX #29: runs of punctuation are words.
+            (b, c) => b.is_punctuation() && c.is_punctuation(),
+        }
+    }
+}
+
+impl TextBuffer {
+    /// Moves the cursor by `delta` grapheme clusters, backwards if it's negative.
+    /// A line break counts as one, even if it's a `\r\n`.
+    pub fn move_cursor_horizontal(&mut self, delta: CoordType) {
+        let mut off = self.cursor;
+        for _ in 0..delta.unsigned_abs() {
+            off = if delta < 0 { self.prev_grapheme_offset(off) } else { self.next_grapheme_offset(off) };
+        }
+        self.set_cursor_offset(off);
+    }
+
+    /// Moves the cursor to the next or previous word boundary, as done by Ctrl+Right and Ctrl+Left:
+    /// forward to the end of the next word, or backward to the start of the previous one.
+    pub fn move_cursor_word(&mut self, forward: bool) {
+        let off = self.word_boundary(self.cursor, forward);
+        self.set_cursor_offset(off);
+    }
+
+    /// Moves the cursor to the first non-blank character of its line, or to the start
+    /// of the line if it's already there, as done by Home.
+    pub fn move_cursor_home(&mut self) {
+        let line = self.offset_to_logical(self.cursor).y;
+        let start = self.line_start_offset(line);
+        let end = self.line_end_offset(line);
+
+        let mut indent = start;
+        while indent < end {
+            let chunk = self.buffer.read_forward(indent);
+            let chunk = &chunk[..chunk.len().min(end - indent)];
+            let blanks = chunk.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+            indent += blanks;
+            if blanks < chunk.len() || chunk.is_empty() {
+                break;
+            }
+        }
+
+        self.set_cursor_offset(if self.cursor == indent { start } else { indent });
+    }
+
+    /// Moves the cursor to the end of its line, in front of the line break, as done by End.
+    pub fn move_cursor_end(&mut self) {
+        let line = self.offset_to_logical(self.cursor).y;
+        let end = self.line_end_offset(line);
+        self.set_cursor_offset(end);
+    }
+
+    /// Returns the offset of the grapheme cluster that follows the one at `off`.
+    /// `off` must be at the start of a cluster, as the cursor always is.
+    pub fn next_grapheme_offset(&self, off: usize) -> usize {
+        let off = off.min(self.text_length());
+        self.measurement_at(off).next_grapheme().map_or(off, |g| g.range.end)
+    }
+
+    /// Returns the offset of the grapheme cluster that precedes `off`.
+    pub fn prev_grapheme_offset(&self, off: usize) -> usize {
+        let off = off.min(self.text_length());
+        if off == 0 {
+            return 0;
+        }
+
+        if self.byte_at(off - 1) == b'\n' {
+            let crlf = off >= 2 && self.byte_at(off - 2) == b'\r';
+            return off - 1 - crlf as usize;
+        }
+
+        let (_, start) = self.line_index.line_of(&self.buffer, off);
+        self.clusters_rev(start, off).next().map_or(off - 1, |(r, _)| r.start)
+    }
+
+    /// Returns the range of the word at `off`, as selected by a double click.
+    /// Runs of whitespace and punctuation count as words, too.
+    pub fn word_range_at(&self, off: usize) -> Range<usize> {
+        let off = off.min(self.text_length());
+        let (_, start) = self.line_index.line_of(&self.buffer, off);
+        // At the end of the line, the last word is meant.
+        let Some((range, class)) = self.clusters_fwd(off).next().or_else(|| self.clusters_rev(start, off).next())
+        else {
+            return off..off;
+        };
+
+        let before = self.clusters_rev(start, range.start).next().map(|(_, c)| c);
+        let after = self.clusters_fwd(range.end).next().map(|(_, c)| c);
+        let end = Self::word_end(range.clone(), class, before, self.clusters_fwd(range.end), true);
+        let beg = Self::word_end(range.clone(), class, after, self.clusters_rev(start, range.start), false);
+        beg.start..end.end
+    }
+
+    /// Returns the range of the line at `off`, including its line break, as selected by a triple click.
+    pub fn line_range_at(&self, off: usize) -> Range<usize> {
+        let line = self.offset_to_logical(off).y;
+        let start = self.line_start_offset(line);
+        let last = line + 1 >= self.line_count();
+        let end = if last { self.text_length() } else { self.line_start_offset(line + 1) };
+        start..end
+    }
+
+    /// Finds the word boundary that [`TextBuffer::move_cursor_word`] moves to.
+    /// Line breaks are boundaries of their own, so that it stops at the start and end of each line.
+    fn word_boundary(&self, off: usize, forward: bool) -> usize {
+        let off = off.min(self.text_length());
+        let (_, start) = self.line_index.line_of(&self.buffer, off);
+        if forward {
+            let behind = self.clusters_rev(start, off).next().map(|(_, c)| c);
+            let skipped = Self::skip_word(self.clusters_fwd(off), behind, true);
+            skipped.map_or_else(|| self.next_grapheme_offset(off), |r| r.end)
+        } else {
+            let behind = self.clusters_fwd(off).next().map(|(_, c)| c);
+            let skipped = Self::skip_word(self.clusters_rev(start, off), behind, false);
+            skipped.map_or_else(|| self.prev_grapheme_offset(off), |r| r.start)
+        }
+    }
+
+    /// Skips any whitespace and then one word. Returns the last cluster that was skipped,
+    /// or `None` if `clusters` is empty. See [`TextBuffer::word_end`] for the parameters.
+    fn skip_word(
+        mut clusters: impl Iterator<Item = (Range<usize>, CharClass)>,
+        mut behind: Option<CharClass>,
+        forward: bool,
+    ) -> Option<Range<usize>> {
+        let mut last = None;
+        while let Some((range, class)) = clusters.next() {
+            if class != CharClass::Whitespace {
+                return Some(Self::word_end(range, class, behind, clusters, forward));
+            }
+            behind = Some(class);
+            last = Some(range);
+        }
+        last
+    }
+
+    /// Returns the last cluster of the word that `first` is part of, going in the direction of
+    /// `clusters`, which continue where `first` ends. `behind` is the class of the cluster on the
+    /// other side of `first`, since some of the rules in [`CharClass::joins`] look that far.
+    fn word_end(
+        first: Range<usize>,
+        class: CharClass,
+        behind: Option<CharClass>,
+        mut clusters: impl Iterator<Item = (Range<usize>, CharClass)>,
+        forward: bool,
+    ) -> Range<usize> {
+        let (mut last, mut prev, mut prev_prev) = (first, class, behind);
+        let mut next = clusters.next();
+        while let Some((range, class)) = next.take() {
+            next = clusters.next();
+            let ahead = next.as_ref().map(|&(_, c)| c);
+            let joins = if forward {
+                CharClass::joins(prev_prev, prev, class, ahead)
+            } else {
+                CharClass::joins(ahead, class, prev, prev_prev)
+            };
+            if !joins {
+                break;
+            }
+            (last, prev, prev_prev) = (range, class, Some(prev));
+        }
+        last
+    }
+
+    /// Returns a [`MeasurementConfig`] that starts at `off`, for when only the grapheme clusters
+    /// matter and not their columns. `off` must be at the start of a cluster.
+    fn measurement_at(&self, off: usize) -> MeasurementConfig<'_> {
+        MeasurementConfig::new(&self.buffer).with_cursor(Cursor { offset: off, logical_pos: Point::default() })
+    }
+
+    /// Iterates over the grapheme clusters from `off` to the end of its line, excluding the line break.
+    fn clusters_fwd(&self, off: usize) -> impl Iterator<Item = (Range<usize>, CharClass)> + '_ {
+        let mut cfg = self.measurement_at(off);
+        let mut next = cfg.next_grapheme();
+        std::iter::from_fn(move || {
+            let g = next.take().filter(|g| !g.newline)?;
+            let mut range = g.range;
+            // WB4: Format characters like the soft hyphen don't break words. They're clusters
+            // of their own, so they get attached to the preceding one.
+            next = cfg.next_grapheme();
+            while let Some(f) = next.as_ref().filter(|f| !f.newline && word_break(f.base) == WordBreak::Format) {
+                range.end = f.range.end;
+                next = cfg.next_grapheme();
+            }
+            Some((range, CharClass::of(g.base)))
+        })
+    }
+
+    /// Iterates backwards over the grapheme clusters from `off` to `line_start`.
+    ///
+    /// Grapheme clusters can only be found going forward. So this segments the text in windows of
+    /// about [`BACKWARD_WINDOW`] bytes, going back from `off`. That keeps the work proportional to
+    /// the distance moved, instead of to the length of the line, which matters for minified files.
+    fn clusters_rev(
+        &self,
+        line_start: usize,
+        off: usize,
+    ) -> impl Iterator<Item = (Range<usize>, CharClass)> + '_ {
+        let mut end = off;
+        let mut window = Vec::new();
+        std::iter::from_fn(move || {
+            if window.is_empty() && end > line_start {
+                let pos = end.saturating_sub(BACKWARD_WINDOW).max(line_start);
+                let beg = self.cluster_start_before(line_start, pos);
+                let clusters = self.clusters_fwd(beg).take_while(|(r, _)| r.start < end);
+                // The last one may have format characters past `end` attached to it.
+                window.extend(clusters.map(|(r, c)| (r.start..r.end.min(end), c)));
+                end = beg;
+            }
+            window.pop()
+        })
+    }
+
+    /// Returns a position in `line_start..=pos` where a grapheme cluster starts, preferably close to `pos`.
+    ///
+    /// A cluster starts at every ASCII character other than a line break, except for the rare
+    /// prepended concatenation marks. Text without any ASCII nearby falls back to a character start,
+    /// which may split a cluster, but only affects the first one in a window of [`TextBuffer::clusters_rev`].
+    fn cluster_start_before(&self, line_start: usize, pos: usize) -> usize {
+        let limit = pos.saturating_sub(BACKWARD_WINDOW).max(line_start);
+        let mut char_start = None;
+        for p in (limit..=pos).rev() {
+            if p == line_start {
+                return line_start;
+            }
+            let b = self.byte_at(p);
+            if b < 0x80 && b != b'\r' && b != b'\n' {
+                return p;
+            }
+            if char_start.is_none() && (b & 0xC0) != 0x80 {
+                char_start = Some(p);
+            }
+        }
+        char_start.unwrap_or(limit)
+    }
+
+    /// Returns the byte at `off`, which must be in bounds.
+    fn byte_at(&self, off: usize) -> u8 {
+        self.buffer.read_forward(off)[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_buffer(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let mut tb = new_buffer("e\u{301}🇩🇪\r\nx");
+        tb.set_cursor_offset(0);
+        let mut offsets = Vec::new();
+        for _ in 0..5 {
+            tb.move_cursor_horizontal(1);
+            offsets.push(tb.cursor_offset());
+        }
+        assert_eq!(offsets, [3, 11, 13, 14, 14]);
+
+        tb.move_cursor_horizontal(-3);
+        assert_eq!(tb.cursor_offset(), 3);
+    }
+
+    #[test]
+    fn test_words() {
+        let mut tb = new_buffer("  größe(42) +=  straße_2\nnext");
+        tb.set_cursor_offset(0);
+        let mut offsets = Vec::new();
+        for _ in 0..7 {
+            tb.move_cursor_word(true);
+            offsets.push(tb.cursor_offset());
+        }
+        assert_eq!(offsets, [9, 10, 12, 13, 16, 27, 28]);
+
+        let mut offsets = Vec::new();
+        for _ in 0..4 {
+            tb.move_cursor_word(false);
+            offsets.push(tb.cursor_offset());
+        }
+        assert_eq!(offsets, [27, 18, 14, 12]);
+
+        // Ideographs are words of their own, katakana are joined.
+        let mut tb = new_buffer("漢字かなカタカナ");
+        tb.set_cursor_offset(0);
+        let mut offsets = Vec::new();
+        for _ in 0..6 {
+            tb.move_cursor_word(true);
+            offsets.push(tb.cursor_offset());
+        }
+        assert_eq!(offsets, [3, 6, 9, 12, 24, 24]);
+        tb.move_cursor_word(false);
+        assert_eq!(tb.cursor_offset(), 12);
+        assert_eq!(tb.word_range_at(3), 3..6);
+        assert_eq!(tb.word_range_at(15), 12..24);
+    }
+
+    #[test]
+    fn test_long_line() {
+        // Longer than the window that backward movement segments at a time,
+        // with clusters that straddle the window boundaries.
+        let line = "ab e\u{301}🇩🇪 漢".repeat(100);
+        let mut tb = new_buffer(&format!("x\r\n{line}"));
+
+        let mut forward = Vec::new();
+        tb.set_cursor_offset(0);
+        while tb.cursor_offset() < tb.text_length() {
+            tb.move_cursor_horizontal(1);
+            forward.push(tb.cursor_offset());
+        }
+        let mut backward = Vec::new();
+        while tb.cursor_offset() > 0 {
+            backward.push(tb.cursor_offset());
+            tb.move_cursor_horizontal(-1);
+        }
+        backward.reverse();
+        assert_eq!(forward, backward);
+        assert_eq!(&forward[..3], [1, 3, 4]);
+
+        let len = tb.text_length();
+        assert_eq!(tb.word_range_at(len - 3), len - 3..len);
+        assert_eq!(tb.word_range_at(len - 4), len - 4..len - 3);
+    }
+
+    #[test]
+    fn test_word_and_line_ranges() {
+        let tb = new_buffer("foo/bar  baz\r\nx");
+        assert_eq!(tb.word_range_at(1), 0..3);
+        assert_eq!(tb.word_range_at(3), 3..4);
+        assert_eq!(tb.word_range_at(8), 7..9);
+        // At the end of the line, the last word is meant.
+        assert_eq!(tb.word_range_at(12), 9..12);
+        assert_eq!(tb.line_range_at(5), 0..14);
+        assert_eq!(tb.line_range_at(14), 14..15);
+    }
+
+    #[test]
+    fn test_word_break_rules() {
+        fn words(text: &str) -> Vec<&str> {
+            let tb = new_buffer(text);
+            let mut words = Vec::new();
+            let mut off = 0;
+            while off < text.len() {
+                let range = tb.word_range_at(off);
+                off = range.end;
+                words.push(&text[range]);
+            }
+            words
+        }
+
+        // Apostrophes and periods join letters, and periods and commas join digits.
+        assert_eq!(words("can't 3.14 1,000"), ["can't", " ", "3.14", " ", "1,000"]);
+        assert_eq!(words("e.g. x:y"), ["e.g", ".", " ", "x:y"]);
+        // But only if they're in between. Otherwise they're punctuation.
+        assert_eq!(words("'tis 3.. a'."), ["'", "tis", " ", "3", "..", " ", "a", "'."]);
+        // Letters, digits and underscores join each other.
+        assert_eq!(words("x2_y3 +__"), ["x2_y3", " ", "+", "__"]);
+        // A soft hyphen stays in its word, even though it's a cluster of its own.
+        assert_eq!(words("co\u{AD}op"), ["co\u{AD}op"]);
+        // Hebrew letters and the quotation marks of their abbreviations.
+        assert_eq!(words("צה\"ל"), ["צה\"ל"]);
+
+        let mut tb = new_buffer("can't 3.14");
+        tb.set_cursor_offset(3);
+        tb.move_cursor_word(true);
+        assert_eq!(tb.cursor_offset(), 5);
+        tb.move_cursor_word(true);
+        assert_eq!(tb.cursor_offset(), 10);
+        tb.set_cursor_offset(9);
+        tb.move_cursor_word(false);
+        assert_eq!(tb.cursor_offset(), 6);
+        tb.move_cursor_word(false);
+        assert_eq!(tb.cursor_offset(), 0);
+    }
+
+    #[test]
+    fn test_home_end() {
+        let mut tb = new_buffer("a\n\t  bc\n");
+        tb.set_cursor_offset(6);
+        tb.move_cursor_home();
+        assert_eq!(tb.cursor_offset(), 5);
+        tb.move_cursor_home();
+        assert_eq!(tb.cursor_offset(), 2);
+        tb.move_cursor_home();
+        assert_eq!(tb.cursor_offset(), 5);
+        tb.move_cursor_end();
+        assert_eq!(tb.cursor_offset(), 7);
+
+        // With the gap of the buffer in the middle of the indentation.
+        tb.insert(3, b" ");
+        tb.set_cursor_offset(8);
+        tb.move_cursor_home();
+        assert_eq!(tb.cursor_offset(), 6);
+    }
+}
@@ -0,0 +1,451 @@
+//! Find and replace within a [`TextBuffer`].
+//!
+//! The active search is part of the buffer, so that the textarea can highlight
+//! the matches. The regex runs right on the chunks of the gap buffer, and only as
+//! far as needed: over the visible text for highlighting, and up to the next match
+//! for finding. Counting all matches happens in steps, so that huge files don't block the UI.
+
+use super::{TextBuffer, gap_buffer::GapBuffer};
+use crate::{
+    apperr,
+    document::ReadableDocument,
+    helpers::KIBI,
+    regex::{self, Captures, Matcher, Regex},
+};
+use std::ops::Range;
+
+/// How much text [`TextBuffer::search_count`] searches per call. About 10ms worth.
+const COUNT_STEP: usize = 128 * KIBI;
+
+/// How much text [`TextBuffer::find_next`] searches at first, when going backwards.
+const REVERSE_WINDOW: usize = 64 * KIBI;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub match_case: bool,
+    pub whole_word: bool,
+    pub use_regex: bool,
+}
+
+/// The result of [`TextBuffer::search_count`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SearchCount {
+    /// The index of the selected match, once counting got that far.
+    pub current: Option<usize>,
+    /// The number of matches found so far.
+    pub total: usize,
+    /// Whether all matches have been counted.
+    pub complete: bool,
+}
+
+pub(super) struct ActiveSearch {
+    regex: Regex,
+    options: SearchOptions,
+    /// The starts of the matches counted so far, as of `generation`.
+    starts: Vec<usize>,
+    /// Where counting continues.
+    counted: usize,
+    complete: bool,
+    generation: Option<u32>,
+}
+
+impl TextBuffer {
+    /// Starts searching for `needle`, or stops searching if it's empty.
+    /// Returns [`apperr::APP_REGEX_INVALID`] if `needle` isn't a valid regex.
+    ///
+    /// The first match at or after the start of the selection gets selected.
+    /// Calling this while the needle is typed makes the search incremental.
+    pub fn set_search(&mut self, needle: &str, options: SearchOptions) -> apperr::Result<()> {
+        if needle.is_empty() {
+            self.search = None;
+            return Ok(());
+        }
+
+        let pattern = if options.use_regex { needle.to_string() } else { Regex::escape(needle) };
+        let regex = Regex::new(&pattern, !options.match_case)?;
+        self.search = Some(ActiveSearch {
+            regex,
+            options,
+            starts: Vec::new(),
+            counted: 0,
+            complete: false,
+            generation: None,
+        });
+
+        let from = self.selection_range().map_or(self.cursor, |r| r.start);
+        if let Some(range) = self.find_match(from..usize::MAX).or_else(|| self.find_match(0..from)) {
+            self.set_selection(range.start, range.end);
+        }
+        Ok(())
+    }
+
+    /// Stops searching. The matches are no longer highlighted.
+    pub fn clear_search(&mut self) {
+        self.search = None;
+    }
+
+    /// Returns `true` if a search is active.
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Returns the matches of the active search that start within `range`, e.g. the visible text.
+    pub fn search_matches_in(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let Some(search) = &self.search else {
+            return Vec::new();
+        };
+
+        let mut matcher = search.regex.matcher();
+        let mut res = Vec::new();
+        let mut pos = range.start.min(self.text_length());
+
+        while let Some(caps) = find_match(&self.buffer, &search.options, &mut matcher, pos..range.end) {
+            let m = caps.range();
+            let next = after_match(&self.buffer, &m);
+            res.push(m);
+            let Some(next) = next else {
+                break;
+            };
+            pos = next;
+        }
+
+        res
+    }
+
+    /// Returns `true` if the selection is a match of the active search.
+    pub fn is_match_selected(&self) -> bool {
+        self.selected_match().is_some()
+    }
+
+    /// Counts the matches of the active search, [`COUNT_STEP`] bytes of text per call.
+    /// Call it again (e.g. on the next frame) until [`SearchCount::complete`] is `true`.
+    /// Counting starts over whenever the text changes.
+    pub fn search_count(&mut self) -> SearchCount {
+        let selected = self.selected_match().map(|caps| caps.range().start);
+        let generation = self.buffer.generation();
+        let len = self.text_length();
+        let Some(search) = &mut self.search else {
+            return SearchCount::default();
+        };
+
+        if search.generation != Some(generation) {
+            search.starts.clear();
+            search.counted = 0;
+            search.complete = false;
+            search.generation = Some(generation);
+        }
+
+        if !search.complete {
+            let end = search.counted.saturating_add(COUNT_STEP);
+            // The end of the text is a position, too, where e.g. `$` can match.
+            let end = if end > len { usize::MAX } else { char_boundary(&self.buffer, end) };
+            let mut matcher = search.regex.matcher();
+
+            loop {
+                let range = search.counted..end;
+                let Some(caps) = find_match(&self.buffer, &search.options, &mut matcher, range) else {
+                    search.counted = search.counted.max(end);
+                    search.complete = end == usize::MAX;
+                    break;
+                };
+
+                let m = caps.range();
+                search.starts.push(m.start);
+                match after_match(&self.buffer, &m) {
+                    Some(next) => search.counted = next,
+                    None => {
+                        search.complete = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let current = selected.and_then(|start| {
+            let i = search.starts.partition_point(|&s| s < start);
+            (search.starts.get(i) == Some(&start)).then_some(i)
+        });
+        SearchCount { current, total: search.starts.len(), complete: search.complete }
+    }
+
+    /// Selects the next match after the current one, or after the cursor
+    /// if no match is selected. Wraps around at the end of the text.
+    /// With `reverse`, it's the previous match instead.
+    pub fn find_next(&mut self, reverse: bool) {
+        let cursor = self.selection_range().map_or(self.cursor, |r| r.start);
+        let found = if reverse {
+            self.find_prev(cursor).or_else(|| self.find_prev(self.text_length() + 1))
+        } else {
+            let from = match self.selected_match() {
+                Some(caps) => after_match(&self.buffer, &caps.range()),
+                None => Some(cursor),
+            };
+            from.and_then(|from| self.find_match(from..usize::MAX)).or_else(|| self.find_match(0..usize::MAX))
+        };
+
+        if let Some(range) = found {
+            self.set_selection(range.start, range.end);
+        }
+    }
+
+    /// Replaces the selected match with `replacement` and selects the next one.
+    /// If no match is selected, this only selects the next one.
+    ///
+    /// With regex searches, `$1` etc. in `replacement` refer to capture groups.
+    pub fn replace_next(&mut self, replacement: &str) {
+        if let Some(caps) = self.selected_match() {
+            let mut replaced = Vec::new();
+            self.expand_replacement(&caps, replacement, &mut replaced);
+            self.replace(caps.range(), &replaced);
+        }
+        self.find_next(false);
+    }
+
+    /// Replaces all matches with `replacement` in a single undo step.
+    /// Returns the number of replacements.
+    pub fn replace_all(&mut self, replacement: &str) -> usize {
+        let Some(search) = &self.search else {
+            return 0;
+        };
+
+        let mut matcher = search.regex.matcher();
+        let mut captures = Vec::new();
+        let mut pos = 0;
+        while let Some(caps) = find_match(&self.buffer, &search.options, &mut matcher, pos..usize::MAX) {
+            let next = after_match(&self.buffer, &caps.range());
+            captures.push(caps);
+            let Some(next) = next else {
+                break;
+            };
+            pos = next;
+        }
+        if captures.is_empty() {
+            return 0;
+        }
+
+        // Going backwards keeps the offsets of the remaining matches intact.
+        self.begin_edit_group();
+        let mut replaced = Vec::new();
+        for caps in captures.iter().rev() {
+            replaced.clear();
+            self.expand_replacement(caps, replacement, &mut replaced);
+            self.replace(caps.range(), &replaced);
+        }
+        self.end_edit_group();
+
+        captures.len()
+    }
+
+    /// Finds the first match that starts within `range`.
+    fn find_match(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let search = self.search.as_ref()?;
+        let mut matcher = search.regex.matcher();
+        find_match(&self.buffer, &search.options, &mut matcher, range).map(|caps| caps.range())
+    }
+
+    /// Finds the last match that starts before `before`. It searches backwards in windows
+    /// that double in size each time, so the work is proportional to the distance to the match.
+    fn find_prev(&self, before: usize) -> Option<Range<usize>> {
+        let search = self.search.as_ref()?;
+        let mut matcher = search.regex.matcher();
+        let mut end = before;
+        let mut window = REVERSE_WINDOW;
+
+        while end > 0 {
+            let beg = char_boundary(&self.buffer, end.saturating_sub(window));
+            let mut last = None;
+            let mut pos = beg;
+
+            while let Some(caps) = find_match(&self.buffer, &search.options, &mut matcher, pos..end) {
+                let m = caps.range();
+                let next = after_match(&self.buffer, &m);
+                last = Some(m);
+                let Some(next) = next else {
+                    break;
+                };
+                pos = next;
+            }
+            if last.is_some() {
+                return last;
+            }
+
+            end = beg;
+            window *= 2;
+        }
+
+        None
+    }
+
+    /// Returns the match that is currently selected, if any.
+    fn selected_match(&self) -> Option<Captures> {
+        let search = self.search.as_ref()?;
+        let selection = self.selection_range().unwrap_or(self.cursor..self.cursor);
+        let mut matcher = search.regex.matcher();
+        // Only a match that starts right at the selection can be it.
+        let range = selection.start..selection.start + 1;
+        let caps = find_match(&self.buffer, &search.options, &mut matcher, range)?;
+        (caps.range() == selection).then_some(caps)
+    }
+
+    /// `caps` must refer to the current text.
+    fn expand_replacement(&self, caps: &Captures, replacement: &str, out: &mut Vec<u8>) {
+        let Some(search) = &self.search else {
+            return;
+        };
+
+        if search.options.use_regex {
+            Regex::expand(caps, &self.buffer, replacement, out);
+        } else {
+            out.extend_from_slice(replacement.as_bytes());
+        }
+    }
+}
+
+/// Finds the first match that starts within `range`, taking [`SearchOptions::whole_word`] into account.
+fn find_match(
+    text: &GapBuffer,
+    options: &SearchOptions,
+    matcher: &mut Matcher,
+    range: Range<usize>,
+) -> Option<Captures> {
+    if !options.whole_word {
+        return matcher.find(text, range);
+    }
+
+    // Matches that are glued to other word characters don't count,
+    // but there may be a shorter or later match that does.
+    let glued = |c: Option<char>| c.is_some_and(regex::is_word_char);
+    let mut pos = range.start;
+
+    while let Some(caps) = matcher.find(text, pos..range.end) {
+        let m = caps.range();
+        let before = regex::char_before(text, m.start);
+        let after = regex::char_at(text, m.end).map(|(c, _)| c);
+        if !m.is_empty() && !glued(before) && !glued(after) {
+            return Some(caps);
+        }
+
+        let (_, len) = regex::char_at(text, m.start)?;
+        pos = m.start + len;
+    }
+
+    None
+}
+
+/// Returns where to continue searching after the match `m`, stepping over empty matches,
+/// or we'd find them again. Returns `None` if there's no text left.
+fn after_match(text: &GapBuffer, m: &Range<usize>) -> Option<usize> {
+    if !m.is_empty() {
+        Some(m.end)
+    } else {
+        regex::char_at(text, m.end).map(|(_, len)| m.end + len)
+    }
+}
+
+/// Moves `pos` forward to the next character boundary, if it's in the middle of one.
+fn char_boundary(text: &GapBuffer, mut pos: usize) -> usize {
+    for _ in 0..3 {
+        match text.read_forward(pos).first() {
+            Some(b) if b & 0b1100_0000 == 0b1000_0000 => pos += 1,
+            _ => break,
+        }
+    }
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_buffer(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb.set_cursor_offset(0);
+        tb
+    }
+
+    fn contents(tb: &TextBuffer) -> String {
+        let mut out = Vec::new();
+        tb.extract_raw(0..tb.text_length(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_find_next_wraps_around() {
+        let mut tb = new_buffer("foo Foo foobar foo");
+        let options = SearchOptions { whole_word: true, ..Default::default() };
+        tb.set_search("foo", options).unwrap();
+        assert_eq!(tb.search_matches_in(0..usize::MAX), [0..3, 4..7, 15..18]);
+        assert_eq!(tb.search_matches_in(1..16), [4..7, 15..18]);
+        assert!(tb.search_matches_in(5..15).is_empty());
+        assert_eq!(tb.search_count(), SearchCount { current: Some(0), total: 3, complete: true });
+
+        tb.find_next(false);
+        tb.find_next(false);
+        assert_eq!(tb.selection_range(), Some(15..18));
+        tb.find_next(false);
+        assert_eq!(tb.selection_range(), Some(0..3));
+        tb.find_next(true);
+        assert_eq!(tb.search_count().current, Some(2));
+        tb.find_next(true);
+        assert_eq!(tb.selection_range(), Some(4..7));
+    }
+
+    #[test]
+    fn test_count_in_steps() {
+        let mut text = "x".repeat(COUNT_STEP - 1);
+        text.push_str("ab ab");
+        text.push_str(&"x".repeat(3 * REVERSE_WINDOW));
+        text.push_str("ab");
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb.set_cursor_offset(0);
+
+        tb.set_search("ab", SearchOptions { match_case: true, ..Default::default() }).unwrap();
+        let mut count = tb.search_count();
+        assert_eq!(count, SearchCount { current: Some(0), total: 1, complete: false });
+        while !count.complete {
+            count = tb.search_count();
+        }
+        assert_eq!(count, SearchCount { current: Some(0), total: 3, complete: true });
+
+        // Going backwards from the start wraps around to the last match, far away.
+        tb.find_next(true);
+        assert_eq!(tb.selection_range(), Some(text.len() - 2..text.len()));
+        tb.find_next(true);
+        assert_eq!(tb.selection_range(), Some(COUNT_STEP + 2..COUNT_STEP + 4));
+
+        // Edits start the count over.
+        tb.replace_next("abab");
+        let mut count = tb.search_count();
+        assert_eq!(count, SearchCount { current: None, total: 1, complete: false });
+        while !count.complete {
+            count = tb.search_count();
+        }
+        assert_eq!(count, SearchCount { current: Some(3), total: 4, complete: true });
+    }
+
+    #[test]
+    fn test_replace_all_is_one_undo_step() {
+        let mut tb = new_buffer("a=1, b=22");
+        let options = SearchOptions { use_regex: true, ..Default::default() };
+        tb.set_search(r"(\w)=(\d+)", options).unwrap();
+        assert_eq!(tb.replace_all("$2:$1"), 2);
+        assert_eq!(contents(&tb), "1:a, 22:b");
+        assert_eq!(tb.search_count().total, 0);
+
+        tb.undo();
+        assert_eq!(contents(&tb), "a=1, b=22");
+        assert_eq!(tb.search_count().total, 2);
+    }
+
+    #[test]
+    fn test_replace_next() {
+        let mut tb = new_buffer("x.x.x");
+        tb.set_search(".", SearchOptions::default()).unwrap();
+        tb.replace_next("$1");
+        assert_eq!(contents(&tb), "x$1x.x");
+        assert_eq!(tb.selection_range(), Some(4..5));
+        assert!(tb.set_search("(", SearchOptions { use_regex: true, ..Default::default() }).is_err());
+    }
+}
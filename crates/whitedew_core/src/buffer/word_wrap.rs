@@ -0,0 +1,315 @@
+
+//! Soft word wrap: splitting long lines into several visual lines.
+//!
+//! Where the visual lines of a line start is computed on demand and cached per line.
+//! Edits only drop the entries of the lines they touched. The entries of the other lines
+//! stay valid, because they're relative to the start of their line. Anything that changes
+//! the width of the text, like resizing the textarea, drops the entire cache.
+
+use super::TextBuffer;
+use crate::helpers::{CoordType, Point};
+use std::{iter, ops::Range};
+
+/// Where a visual line starts, relative to the start of its logical line.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct VisualLineStart {
+    /// The byte offset from the start of the line.
+    pub offset: usize,
+    /// The logical column, as in [`TextBuffer::offset_to_logical`].
+    pub column: CoordType,
+}
+
+/// A visual line: the `row`-th visual line of the logical `line`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct VisualRow {
+    pub line: CoordType,
+    pub row: CoordType,
+}
+
+#[derive(Default)]
+pub(super) struct WordWrap {
+    enabled: bool,
+    /// The number of columns to wrap at.
+    width: CoordType,
+    /// The visual line starts of each line, if they were computed already.
+    /// Lines past the end of the list haven't been computed either.
+    lines: Vec<Option<Box<[VisualLineStart]>>>,
+    /// The column that Up and Down try to stay in, and the cursor offset it belongs to.
+    /// It's only valid as long as the cursor stays there.
+    goal: Option<(usize, CoordType)>,
+}
+
+/// The visual line starts of a line that isn't wrapped.
+const UNWRAPPED: &[VisualLineStart] = &[VisualLineStart { offset: 0, column: 0 }];
+
+impl TextBuffer {
+    pub fn is_word_wrap_enabled(&self) -> bool {
+        self.word_wrap.enabled
+    }
+
+    /// Turns word wrap on or off.
+    pub fn set_word_wrap(&mut self, enabled: bool) {
+        if self.word_wrap.enabled != enabled {
+            self.word_wrap.enabled = enabled;
+            self.reflow();
+        }
+    }
+
+    /// Sets the number of columns to wrap at. The textarea calls this with its own width.
+    pub fn set_word_wrap_width(&mut self, width: CoordType) {
+        let width = width.max(1);
+        if self.word_wrap.width != width {
+            self.word_wrap.width = width;
+            self.reflow();
+        }
+    }
+
+    /// Drops all cached visual lines. Must be called when the width of characters
+    /// changes, e.g. once it's known how wide the terminal draws ambiguous ones.
+    pub fn reflow(&mut self) {
+        self.word_wrap.lines.clear();
+    }
+
+    /// Returns where the visual lines of `line` start. There's always at least one.
+    pub fn visual_line_starts(&mut self, line: CoordType) -> &[VisualLineStart] {
+        if !self.word_wrap.enabled || !(0..self.line_count()).contains(&line) {
+            return UNWRAPPED;
+        }
+
+        let idx = line as usize;
+        if idx >= self.word_wrap.lines.len() {
+            self.word_wrap.lines.resize(idx + 1, None);
+        }
+        if self.word_wrap.lines[idx].is_none() {
+            self.word_wrap.lines[idx] = Some(self.compute_visual_line_starts(line));
+        }
+        self.word_wrap.lines[idx].as_deref().unwrap()
+    }
+
+    /// Returns the visual line that `offset` is shown on.
+    pub fn visual_row_of(&mut self, offset: usize) -> VisualRow {
+        let line = self.offset_to_logical(offset).y;
+        let rel = offset.min(self.text_length()) - self.line_start_offset(line);
+        let starts = self.visual_line_starts(line);
+        let row = starts.partition_point(|s| s.offset <= rel) - 1;
+        VisualRow { line, row: row as CoordType }
+    }
+
+    /// Moves `from` by `delta` visual lines, up if it's negative.
+    /// Stops at the first and last visual line of the text.
+    pub fn visual_row_move(&mut self, from: VisualRow, delta: CoordType) -> VisualRow {
+        let last_line = self.line_count() - 1;
+        let mut pos = VisualRow { line: from.line.clamp(0, last_line), row: from.row.max(0) };
+        pos.row = pos.row.min(self.visual_line_starts(pos.line).len() as CoordType - 1);
+
+        let mut delta = delta;
+        while delta < 0 {
+            if pos.row > 0 {
+                let step = pos.row.min(-delta);
+                pos.row -= step;
+                delta += step;
+            } else if pos.line > 0 {
+                pos.line -= 1;
+                pos.row = self.visual_line_starts(pos.line).len() as CoordType - 1;
+                delta += 1;
+            } else {
+                break;
+            }
+        }
+        while delta > 0 {
+            let rows = self.visual_line_starts(pos.line).len() as CoordType;
+            if pos.row + 1 < rows {
+                let step = (rows - 1 - pos.row).min(delta);
+                pos.row += step;
+                delta -= step;
+            } else if pos.line < last_line {
+                pos.line += 1;
+                pos.row = 0;
+                delta -= 1;
+            } else {
+                break;
+            }
+        }
+        pos
+    }
+
+    /// Returns the byte range of the visual line `pos`, excluding the line break,
+    /// and the logical column it starts at.
+    pub fn visual_row_range(&mut self, pos: VisualRow) -> (Range<usize>, CoordType) {
+        let beg = self.line_start_offset(pos.line);
+        let end = self.line_end_offset(pos.line);
+        let starts = self.visual_line_starts(pos.line);
+        let row = (pos.row.max(0) as usize).min(starts.len() - 1);
+        let start = starts[row];
+        let next = starts.get(row + 1).map_or(end, |s| beg + s.offset);
+        (beg + start.offset..next, start.column)
+    }
+
+    /// Returns the offset in the visual line `pos` that is closest to `x` columns
+    /// from its start, without going past it.
+    pub fn visual_row_to_offset(&mut self, pos: VisualRow, x: CoordType) -> usize {
+        let (range, column) = self.visual_row_range(pos);
+        let off = self.logical_to_offset(Point { x: column + x, y: pos.line });
+
+        // The end of a visual line that continues on the next one is shown
+        // at the start of the next one, so it's off limits.
+        let wrapped = range.end < self.line_end_offset(pos.line);
+        if wrapped && off >= range.end {
+            // Back to the start of the last grapheme cluster in it.
+            let last = self.offset_to_logical(range.end - 1);
+            self.logical_to_offset(last).max(range.start)
+        } else {
+            off
+        }
+    }
+
+    /// Moves the cursor up or down by `delta` visual lines, as done by the arrow keys.
+    /// Moving repeatedly keeps the column that the first move started from,
+    /// even across lines that are too short for it.
+    pub fn move_cursor_vertical(&mut self, delta: CoordType) {
+        let from = self.visual_row_of(self.cursor);
+        let x = match self.word_wrap.goal {
+            Some((off, x)) if off == self.cursor => x,
+            _ => {
+                let (range, column) = self.visual_row_range(from);
+                self.offset_to_logical(self.cursor.max(range.start)).x - column
+            }
+        };
+
+        let to = self.visual_row_move(from, delta);
+        let off = self.visual_row_to_offset(to, x);
+        self.set_cursor_offset(off);
+        self.word_wrap.goal = Some((off, x));
+    }
+
+    /// Drops the cached visual lines of the lines that an edit touches.
+    /// `first` is the line it starts in, `removed` and `added` are the number of `\n`
+    /// that it removes and adds.
+    pub(super) fn word_wrap_invalidate(&mut self, first: CoordType, removed: CoordType, added: CoordType) {
+        let lines = &mut self.word_wrap.lines;
+        let first = first as usize;
+        if first >= lines.len() {
+            return;
+        }
+        let end = (first + removed as usize + 1).min(lines.len());
+        lines.splice(first..end, iter::repeat_n(None, added as usize + 1));
+    }
+
+    /// Returns `true` if there are cached visual lines that edits need to take care of.
+    pub(super) fn word_wrap_has_cache(&self) -> bool {
+        !self.word_wrap.lines.is_empty()
+    }
+
+    /// Breaks `line` into visual lines of at most [`WordWrap::width`] columns.
+    /// Lines are broken after whitespace, if possible, and only between grapheme clusters otherwise.
+    /// Whitespace never starts a visual line. It hangs over the end of the previous one instead.
+    fn compute_visual_line_starts(&self, line: CoordType) -> Box<[VisualLineStart]> {
+        let beg = self.line_start_offset(line);
+        let mut cfg = self.measurement_at_line(beg, line);
+
+        let width = self.word_wrap.width.max(1);
+        let mut starts = vec![VisualLineStart::default()];
+        let mut row_column = 0;
+        // The last place after whitespace in the current visual line.
+        let mut opportunity: Option<VisualLineStart> = None;
+
+        while let Some(g) = cfg.next_grapheme() {
+            if g.newline {
+                break;
+            }
+
+            let is_space = g.base == ' ' || g.base == '\t';
+            let column = g.column;
+            // Zero-width clusters, like stray combining marks, stay with the preceding one.
+            let fits = |row_column: CoordType| {
+                g.width == 0 || is_space || column == row_column || column + g.width - row_column <= width
+            };
+
+            if !fits(row_column) {
+                if let Some(o) = opportunity.take() {
+                    starts.push(o);
+                    row_column = o.column;
+                }
+                if !fits(row_column) {
+                    starts.push(VisualLineStart { offset: g.range.start - beg, column });
+                    row_column = column;
+                }
+            }
+
+            if is_space {
+                opportunity = Some(VisualLineStart { offset: g.range.end - beg, column: column + g.width });
+            }
+        }
+
+        starts.into_boxed_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_buffer(text: &str, width: CoordType) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.insert(0, text.as_bytes());
+        tb.set_word_wrap(true);
+        tb.set_word_wrap_width(width);
+        tb
+    }
+
+    fn offsets(tb: &mut TextBuffer, line: CoordType) -> Vec<usize> {
+        tb.visual_line_starts(line).iter().map(|s| s.offset).collect()
+    }
+
+    #[test]
+    fn test_wrap_at_words() {
+        let mut tb = new_buffer("the quick brown fox\nabcdefghijkl", 10);
+        assert_eq!(offsets(&mut tb, 0), [0, 10]);
+        // A word that is longer than a line is broken anywhere.
+        assert_eq!(offsets(&mut tb, 1), [0, 10]);
+
+        // Whitespace hangs over the end of the line.
+        let mut tb = new_buffer("abcdefghij  x", 10);
+        assert_eq!(offsets(&mut tb, 0), [0, 12]);
+        // Wide characters aren't split.
+        let mut tb = new_buffer("漢字漢字漢字", 5);
+        assert_eq!(offsets(&mut tb, 0), [0, 6, 12]);
+    }
+
+    #[test]
+    fn test_edits_invalidate_touched_lines() {
+        let mut tb = new_buffer("aaa bbb\nccc ddd\neee fff", 4);
+        assert_eq!(offsets(&mut tb, 2), [0, 4]);
+
+        tb.insert(8, b"x\ny ");
+        assert_eq!(offsets(&mut tb, 1), [0]);
+        assert_eq!(offsets(&mut tb, 2), [0, 2, 6]);
+        assert_eq!(offsets(&mut tb, 3), [0, 4]);
+
+        tb.undo();
+        assert_eq!(offsets(&mut tb, 1), [0, 4]);
+        assert_eq!(offsets(&mut tb, 2), [0, 4]);
+    }
+
+    #[test]
+    fn test_move_cursor_vertical() {
+        let mut tb = new_buffer("one two three\nx\nfour five six", 8);
+        tb.set_cursor_offset(2);
+        tb.move_cursor_vertical(1);
+        // "three" at column 2.
+        assert_eq!(tb.cursor_offset(), 10);
+        tb.move_cursor_vertical(1);
+        assert_eq!(tb.cursor_offset(), 15);
+        tb.move_cursor_vertical(1);
+        assert_eq!(tb.cursor_offset(), 18);
+        tb.move_cursor_vertical(-3);
+        assert_eq!(tb.cursor_offset(), 2);
+
+        // The end of a wrapped visual line is the start of the next one.
+        tb.set_cursor_offset(8);
+        assert_eq!(tb.visual_row_of(8), VisualRow { line: 0, row: 1 });
+        tb.set_cursor_offset(7);
+        tb.move_cursor_vertical(0);
+        assert_eq!(tb.cursor_offset(), 7);
+    }
+}
@@ -15,4 +15,29 @@ impl Clipboard {
     pub fn read(&self) -> &[u8] {
         &self.data
     }
+
+    /// Replaces the contents of the clipboard and marks them for syncing to the host terminal.
+    ///
+    /// `line_copy` marks them as entire lines that were copied without a selection.
+    /// Pasting them inserts them above the cursor's line, instead of at the cursor.
+    pub fn write(&mut self, data: Vec<u8>, line_copy: bool) {
+        self.data = data;
+        self.line_copy = line_copy;
+        self.wants_host_sync = true;
+    }
+
+    /// Returns `true` if the contents are entire lines. See [`Clipboard::write`].
+    pub fn is_line_copy(&self) -> bool {
+        self.line_copy
+    }
+
+    /// Returns `true` if the contents changed since they were last sent to the host terminal.
+    pub fn wants_host_sync(&self) -> bool {
+        self.wants_host_sync
+    }
+
+    /// Marks the contents as sent to the host terminal.
+    pub fn mark_as_synced(&mut self) {
+        self.wants_host_sync = false;
+    }
 }
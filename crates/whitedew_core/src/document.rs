@@ -0,0 +1,42 @@
+
+//! Abstractions over reading from text containers.
+
+/// An abstraction over reading from text containers.
+///
+/// A container doesn't need to store its contents contiguously.
+/// Instead, it hands out the longest contiguous chunk it has at a given offset.
+pub trait ReadableDocument {
+    /// Read some bytes starting at (including) the given absolute offset.
+    ///
+    /// # Warning
+    ///
+    /// * Be lenient on inputs:
+    ///   * The given offset may be out of bounds and you MUST clamp it.
+    /// * Callers must be lenient on outputs:
+    ///   * A chunk may end in the middle of a UTF-8 sequence or a grapheme cluster.
+    ///   * An empty slice is only returned if the offset is at or beyond the end.
+    fn read_forward(&self, off: usize) -> &[u8];
+
+    /// Read some bytes before (but not including) the given absolute offset.
+    ///
+    /// # Warning
+    ///
+    /// * Be lenient on inputs:
+    ///   * The given offset may be out of bounds and you MUST clamp it.
+    /// * Callers must be lenient on outputs:
+    ///   * A chunk may start in the middle of a UTF-8 sequence or a grapheme cluster.
+    ///   * An empty slice is only returned if the offset is zero.
+    fn read_backward(&self, off: usize) -> &[u8];
+}
+
+impl ReadableDocument for &[u8] {
+    fn read_forward(&self, off: usize) -> &[u8] {
+        let s = *self;
+        &s[off.min(s.len())..]
+    }
+
+    fn read_backward(&self, off: usize) -> &[u8] {
+        let s = *self;
+        &s[..off.min(s.len())]
+    }
+}
@@ -0,0 +1,354 @@
+
+//! Conversion between UTF-8, which is what the editor works with, and the encoding of a file.
+//!
+//! The Unicode encodings, Latin-1 and Windows-1252 are built in. Everything else
+//! is converted by ICU and fails with [`apperr::APP_ICU_MISSING`] without it.
+
+use crate::{apperr, icu, unicode::Utf8Chars};
+use std::borrow::Cow;
+
+pub const UTF8: &str = "UTF-8";
+pub const UTF16LE: &str = "UTF-16LE";
+pub const UTF16BE: &str = "UTF-16BE";
+pub const UTF32LE: &str = "UTF-32LE";
+pub const UTF32BE: &str = "UTF-32BE";
+pub const LATIN1: &str = "ISO-8859-1";
+pub const WINDOWS1252: &str = "windows-1252";
+
+/// The encodings that work without ICU.
+pub const BUILTIN: [&str; 7] = [UTF8, UTF16LE, UTF16BE, UTF32LE, UTF32BE, LATIN1, WINDOWS1252];
+
+/// The characters that Windows-1252 has in place of the C1 control characters of Latin-1.
+/// The 5 unassigned ones map to the control characters, the same as in browsers.
+const WINDOWS1252_C1: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// Returns all encodings that can be used: the built-in ones first,
+/// followed by those of ICU, if it's available.
+pub fn all() -> Vec<&'static str> {
+    let mut res = BUILTIN.to_vec();
+    for name in icu::encodings().unwrap_or_default() {
+        if !res.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            res.push(name);
+        }
+    }
+    res
+}
+
+/// Returns the byte order mark of `encoding`, or an empty slice if it has none.
+pub fn bom(encoding: &str) -> &'static [u8] {
+    match encoding {
+        UTF8 => b"\xEF\xBB\xBF",
+        UTF16LE => b"\xFF\xFE",
+        UTF16BE => b"\xFE\xFF",
+        UTF32LE => b"\xFF\xFE\x00\x00",
+        UTF32BE => b"\x00\x00\xFE\xFF",
+        _ => b"",
+    }
+}
+
+/// Guesses the encoding of a file from its first few KiB.
+/// Returns the encoding and whether `head` starts with its byte order mark.
+///
+/// Without a BOM, the text is assumed to be UTF-8, unless it looks like UTF-16.
+pub fn detect(head: &[u8]) -> (&'static str, bool) {
+    // UTF-32LE must be checked before UTF-16LE, because the BOM of the latter is a prefix of the former.
+    for encoding in [UTF8, UTF32LE, UTF32BE, UTF16LE, UTF16BE] {
+        if head.starts_with(bom(encoding)) {
+            return (encoding, true);
+        }
+    }
+    (detect_utf16(head).unwrap_or(UTF8), false)
+}
+
+/// In UTF-16, ASCII characters have a zero byte on one side, which text in UTF-8 never has.
+/// Most text contains plenty of ASCII (spaces, newlines, digits) and so that zero shows up
+/// on the same side over and over, while the other side almost never is zero.
+fn detect_utf16(head: &[u8]) -> Option<&'static str> {
+    let units = head.len() / 2;
+    if units < 4 {
+        return None;
+    }
+
+    let mut zeros = [0; 2];
+    for unit in head.chunks_exact(2) {
+        zeros[0] += (unit[0] == 0) as usize;
+        zeros[1] += (unit[1] == 0) as usize;
+    }
+
+    let plausible = |ascii: usize, other: usize| ascii >= units / 4 && other * 16 <= ascii;
+    if plausible(zeros[1], zeros[0]) {
+        Some(UTF16LE)
+    } else if plausible(zeros[0], zeros[1]) {
+        Some(UTF16BE)
+    } else {
+        None
+    }
+}
+
+enum Codec {
+    Utf8,
+    Utf16 { big_endian: bool },
+    Utf32 { big_endian: bool },
+    Latin1,
+    Windows1252,
+    Icu(icu::Converter),
+}
+
+impl Codec {
+    fn new(encoding: &str, to_utf8: bool) -> apperr::Result<Self> {
+        Ok(match encoding {
+            UTF8 => Self::Utf8,
+            UTF16LE => Self::Utf16 { big_endian: false },
+            UTF16BE => Self::Utf16 { big_endian: true },
+            UTF32LE => Self::Utf32 { big_endian: false },
+            UTF32BE => Self::Utf32 { big_endian: true },
+            LATIN1 => Self::Latin1,
+            WINDOWS1252 => Self::Windows1252,
+            _ if to_utf8 => Self::Icu(icu::Converter::new(encoding, UTF8)?),
+            _ => Self::Icu(icu::Converter::new(UTF8, encoding)?),
+        })
+    }
+}
+
+/// Converts text from some encoding to UTF-8, chunk by chunk.
+/// Invalid sequences are replaced with U+FFFD, except in UTF-8 itself:
+/// that is passed through as is, so that invalid bytes survive a round trip.
+pub struct Decoder {
+    codec: Codec,
+    /// An incomplete character at the end of the previous chunk.
+    pending: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new(encoding: &str) -> apperr::Result<Self> {
+        Ok(Self { codec: Codec::new(encoding, true)?, pending: Vec::new() })
+    }
+
+    /// Decodes `input` and appends the UTF-8 to `out`.
+    /// Pass `flush = true` with the last chunk, so that nothing is held back.
+    pub fn decode(&mut self, input: &[u8], flush: bool, out: &mut Vec<u8>) -> apperr::Result<()> {
+        let unit_len = match &mut self.codec {
+            Codec::Utf8 => {
+                out.extend_from_slice(input);
+                return Ok(());
+            }
+            Codec::Icu(cnv) => return cnv.convert(input, flush, out),
+            Codec::Utf16 { .. } => 2,
+            Codec::Utf32 { .. } => 4,
+            Codec::Latin1 => {
+                input.iter().for_each(|&b| push_char(out, b as char));
+                return Ok(());
+            }
+            Codec::Windows1252 => {
+                input.iter().for_each(|&b| push_char(out, windows1252_to_char(b)));
+                return Ok(());
+            }
+        };
+
+        let data: Cow<[u8]> = if self.pending.is_empty() {
+            Cow::Borrowed(input)
+        } else {
+            let mut data = std::mem::take(&mut self.pending);
+            data.extend_from_slice(input);
+            Cow::Owned(data)
+        };
+
+        let units = data.chunks_exact(unit_len);
+        let mut rest = units.remainder();
+
+        match self.codec {
+            Codec::Utf16 { big_endian } => {
+                let units: Vec<u16> = units
+                    .map(|u| [u[0], u[1]])
+                    .map(|u| if big_endian { u16::from_be_bytes(u) } else { u16::from_le_bytes(u) })
+                    .collect();
+                // A leading surrogate at the very end may be completed by the next chunk.
+                let mut len = units.len();
+                if !flush && len > 0 && (0xD800..0xDC00).contains(&units[len - 1]) {
+                    len -= 1;
+                    rest = &data[len * 2..];
+                }
+                for c in char::decode_utf16(units[..len].iter().copied()) {
+                    push_char(out, c.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+            }
+            Codec::Utf32 { big_endian } => {
+                for u in units {
+                    let u = u.try_into().unwrap();
+                    let c = if big_endian { u32::from_be_bytes(u) } else { u32::from_le_bytes(u) };
+                    push_char(out, char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        if flush {
+            if !rest.is_empty() {
+                push_char(out, char::REPLACEMENT_CHARACTER);
+            }
+        } else {
+            self.pending.extend_from_slice(rest);
+        }
+        Ok(())
+    }
+}
+
+/// Converts UTF-8 to some encoding, chunk by chunk.
+///
+/// Invalid UTF-8 is treated as U+FFFD, unless the target is UTF-8 itself,
+/// which is passed through as is (see [`Decoder`]). Characters that the encoding can't represent
+/// are replaced with `?` (or whatever ICU considers appropriate for the encoding).
+pub struct Encoder {
+    codec: Codec,
+    /// An incomplete character at the end of the previous chunk.
+    pending: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new(encoding: &str) -> apperr::Result<Self> {
+        Ok(Self { codec: Codec::new(encoding, false)?, pending: Vec::new() })
+    }
+
+    /// Encodes the UTF-8 in `input` and appends the result to `out`.
+    /// Pass `flush = true` with the last chunk, so that nothing is held back.
+    pub fn encode(&mut self, input: &[u8], flush: bool, out: &mut Vec<u8>) -> apperr::Result<()> {
+        match &mut self.codec {
+            Codec::Utf8 => {
+                out.extend_from_slice(input);
+                return Ok(());
+            }
+            // ICU keeps track of incomplete characters by itself.
+            Codec::Icu(cnv) => return cnv.convert(input, flush, out),
+            _ => {}
+        }
+
+        let data: Cow<[u8]> = if self.pending.is_empty() {
+            Cow::Borrowed(input)
+        } else {
+            let mut data = std::mem::take(&mut self.pending);
+            data.extend_from_slice(input);
+            Cow::Owned(data)
+        };
+
+        let len = if flush { data.len() } else { complete_utf8_len(&data) };
+        self.pending.extend_from_slice(&data[len..]);
+
+        for c in Utf8Chars::new(&data[..len], 0) {
+            match self.codec {
+                Codec::Utf16 { big_endian } => {
+                    for u in c.encode_utf16(&mut [0; 2]) {
+                        out.extend_from_slice(&if big_endian { u.to_be_bytes() } else { u.to_le_bytes() });
+                    }
+                }
+                Codec::Utf32 { big_endian } => {
+                    let c = c as u32;
+                    out.extend_from_slice(&if big_endian { c.to_be_bytes() } else { c.to_le_bytes() });
+                }
+                Codec::Latin1 => out.push(if (c as u32) < 0x100 { c as u8 } else { b'?' }),
+                Codec::Windows1252 => out.push(char_to_windows1252(c).unwrap_or(b'?')),
+                Codec::Utf8 | Codec::Icu(_) => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn push_char(out: &mut Vec<u8>, c: char) {
+    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+fn windows1252_to_char(b: u8) -> char {
+    match b {
+        0x80..0xA0 => WINDOWS1252_C1[b as usize - 0x80],
+        _ => b as char,
+    }
+}
+
+fn char_to_windows1252(c: char) -> Option<u8> {
+    match c as u32 {
+        0..0x80 | 0xA0..0x100 => Some(c as u8),
+        _ => WINDOWS1252_C1.iter().position(|&w| w == c).map(|i| 0x80 + i as u8),
+    }
+}
+
+/// Returns the length of `data` without an incomplete UTF-8 sequence at its end.
+fn complete_utf8_len(data: &[u8]) -> usize {
+    // Find the start of the last character. It's at most 3 continuation bytes back.
+    let Some(start) = data.iter().rev().take(4).position(|&b| b & 0xC0 != 0x80) else {
+        return data.len();
+    };
+    let start = data.len() - 1 - start;
+    let len = match data[start] {
+        0xF0.. => 4,
+        0xE0.. => 3,
+        0xC0.. => 2,
+        _ => 1,
+    };
+    if start + len > data.len() { start } else { data.len() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_chunked(encoding: &str, input: &[u8]) -> String {
+        let mut decoder = Decoder::new(encoding).unwrap();
+        let mut out = Vec::new();
+        for b in input {
+            decoder.decode(&[*b], false, &mut out).unwrap();
+        }
+        decoder.decode(&[], true, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn encode_chunked(encoding: &str, input: &str) -> Vec<u8> {
+        let mut encoder = Encoder::new(encoding).unwrap();
+        let mut out = Vec::new();
+        for b in input.as_bytes() {
+            encoder.encode(&[*b], false, &mut out).unwrap();
+        }
+        encoder.encode(&[], true, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"\xEF\xBB\xBFabc"), (UTF8, true));
+        assert_eq!(detect(b"\xFF\xFE\x00\x00a\x00\x00\x00"), (UTF32LE, true));
+        assert_eq!(detect(b"\xFF\xFEa\x00"), (UTF16LE, true));
+        assert_eq!(detect(b"h\x00e\x00l\x00l\x00o\x00"), (UTF16LE, false));
+        assert_eq!(detect(b"\x00h\x00e\x00l\x00l\x00o"), (UTF16BE, false));
+        assert_eq!(detect("héllo wörld".as_bytes()), (UTF8, false));
+        assert_eq!(detect(b"\x00\x00\x00\x00\x00\x00\x00\x00"), (UTF8, false));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "a€ä😀\n";
+        for encoding in [UTF16LE, UTF16BE, UTF32LE, UTF32BE] {
+            let encoded = encode_chunked(encoding, text);
+            assert_eq!(decode_chunked(encoding, &encoded), text);
+        }
+
+        assert_eq!(encode_chunked(WINDOWS1252, text), b"a\x80\xE4?\n");
+        assert_eq!(encode_chunked(LATIN1, text), b"a?\xE4?\n");
+        assert_eq!(decode_chunked(WINDOWS1252, b"\x80\x81\xE4"), "€\u{81}ä");
+        assert_eq!(decode_chunked(LATIN1, b"\x80\xE4"), "\u{80}ä");
+    }
+
+    #[test]
+    fn test_invalid() {
+        // A lone surrogate, and a truncated unit at the end.
+        assert_eq!(decode_chunked(UTF16LE, b"\x00\xD8a\x00b"), "\u{FFFD}a\u{FFFD}");
+        assert_eq!(decode_chunked(UTF32LE, b"\x00\x00\x11\x00"), "\u{FFFD}");
+
+        let mut out = Vec::new();
+        Encoder::new(UTF16LE).unwrap().encode(b"a\xFF", true, &mut out).unwrap();
+        assert_eq!(out, b"a\x00\xFD\xFF");
+    }
+}
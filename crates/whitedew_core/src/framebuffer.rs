@@ -1,6 +1,11 @@
 
-use crate::oklab::StraightRgba;
-use std::cell::Cell;
+use crate::{
+    helpers::{CoordType, Point, Rect, Size},
+    oklab::StraightRgba,
+    unicode::MeasurementConfig,
+};
+use std::{cell::Cell, fmt::Write as _, mem};
+use stdext::arena::ArenaString;
 
 const HASH_MULTIPLIER: usize = 6364136223846793005; // Knuth's MMIX multiplier
 /// The size of our cache table. 1<<8 = 256.
@@ -11,6 +16,11 @@ const CACHE_TABLE_SIZE: usize = 1 << CACHE_TABLE_LOG2_SIZE;
 /// 8 bits out, but rather shift 56 bits down to get the best bits from the top.
 const CACHE_TABLE_SHIFT: usize = usize::BITS as usize - CACHE_TABLE_LOG2_SIZE;
 
+/// The longest grapheme (in bytes) a single cell can hold.
+/// That's enough for most emoji ZWJ sequences, like families of four.
+/// Anything longer is displayed as U+FFFD.
+const CELL_TEXT_CAPACITY: usize = 31;
+
 /// Number of indices used by [`IndexedColor`].
 pub const INDEXED_COLORS_COUNT: usize = 18;
 
@@ -61,6 +71,46 @@ pub enum IndexedColor {
     Foreground,
 }
 
+/// Text attributes of a cell, besides its colors.
+#[repr(transparent)]
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+pub struct Attributes(u8);
+
+impl Attributes {
+    pub const NONE: Self = Self(0);
+    pub const BOLD: Self = Self(1 << 0);
+    pub const ITALIC: Self = Self(1 << 1);
+    pub const UNDERLINED: Self = Self(1 << 2);
+    pub const STRIKETHROUGH: Self = Self(1 << 3);
+    pub const ALL: Self = Self(0b1111);
+
+    pub const fn contains(&self, attr: Self) -> bool {
+        (self.0 & attr.0) != 0
+    }
+}
+
+impl std::ops::BitOr for Attributes {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Attributes {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// SGR codes to turn on/off each of the [`Attributes`].
+const ATTRIBUTE_SGR: [(Attributes, &str, &str); 4] = [
+    (Attributes::BOLD, "1", "22"),
+    (Attributes::ITALIC, "3", "23"),
+    (Attributes::UNDERLINED, "4", "24"),
+    (Attributes::STRIKETHROUGH, "9", "29"),
+];
+
 /// A shoddy framebuffer for terminal applications.
 ///
 /// The idea is that you create a [`Framebuffer`], draw a bunch of text and
@@ -72,6 +122,11 @@ pub enum IndexedColor {
 /// of `vim` for instance, you'll notice that it redraws unrelated parts of
 /// the screen all the time.
 pub struct Framebuffer {
+    /// The cell grid that is drawn into.
+    back: Buffer,
+    /// The cell grid that was rendered last, i.e. what the terminal currently shows.
+    /// Empty if the terminal contents are unknown and everything must be redrawn.
+    front: Buffer,
     /// Store the color palette.
     indexed_colors: [StraightRgba; INDEXED_COLORS_COUNT],
     background_fill: StraightRgba,
@@ -83,12 +138,16 @@ pub struct Framebuffer {
     /// A cache table for previously contrasted colors.
     /// See: <https://fgiesen.wordpress.com/2019/02/11/cache-tables/>
     contrast_colors: [Cell<(StraightRgba, StraightRgba)>; CACHE_TABLE_SIZE],
+    /// Whether the terminal currently shows its cursor. Terminals start out with a visible cursor.
+    cursor_visible: bool,
 }
 
 impl Framebuffer {
     /// Creates a new framebuffer.
     pub fn new() -> Self {
         Self {
+            back: Buffer::default(),
+            front: Buffer::default(),
             indexed_colors: DEFAULT_THEME,
             background_fill: DEFAULT_THEME[IndexedColor::Background as usize],
             foreground_fill: DEFAULT_THEME[IndexedColor::Foreground as usize],
@@ -97,6 +156,7 @@ impl Framebuffer {
                 DEFAULT_THEME[IndexedColor::BrightWhite as usize],
             ],
             contrast_colors: [const { Cell::new((StraightRgba::zero(), StraightRgba::zero())) }; CACHE_TABLE_SIZE],
+            cursor_visible: true,
         }
     }
 
@@ -116,6 +176,314 @@ impl Framebuffer {
         if !Self::is_dark(self.auto_colors[0]) {
             self.auto_colors.swap(0, 1);
         }
+
+        // Translucent colors are blended with the palette, so they may look different now.
+        self.invalidate();
+    }
+
+    /// Forces the next [`Framebuffer::render`] to redraw the entire screen.
+    /// Use this if something else wrote to the terminal.
+    pub fn invalidate(&mut self) {
+        self.front.cells.clear();
+    }
+
+    /// Begins a new frame with the given `size`.
+    ///
+    /// Clears the cell grid to the default colors and hides the cursor.
+    /// If the size changed since the last frame, the next render redraws everything.
+    pub fn flip(&mut self, size: Size) {
+        let size = Size { width: size.width.max(0), height: size.height.max(0) };
+        let blank = GridCell::blank(self.background_fill, self.foreground_fill);
+        self.back.size = size;
+        self.back.cells.clear();
+        self.back.cells.resize((size.width * size.height) as usize, blank);
+        self.back.cursor = None;
+    }
+
+    /// Returns the size of the current frame.
+    pub fn size(&self) -> Size {
+        self.back.size
+    }
+
+    /// Shows the cursor at `pos` once the frame is rendered.
+    pub fn set_cursor(&mut self, pos: Point) {
+        if self.back.size.as_rect().contains(pos) {
+            self.back.cursor = Some(pos);
+        }
+    }
+
+    /// Replaces the text in row `y` starting at column `x`, but only within the `clip` rectangle.
+    /// Tabs and control characters are displayed as spaces.
+    ///
+    /// Returns the column after the last written character.
+    pub fn replace_text(&mut self, y: CoordType, x: CoordType, clip: Rect, text: &str) -> CoordType {
+        let clip = clip.intersect(self.back.size.as_rect());
+        let mut x = x;
+
+        if y < clip.top || y >= clip.bottom {
+            return x;
+        }
+
+        let row = (y * self.back.size.width) as usize;
+        let bytes = text.as_bytes();
+        let mut cfg = MeasurementConfig::new(&bytes);
+
+        while x < clip.right {
+            let Some(g) = cfg.next_grapheme() else {
+                break;
+            };
+            // Control characters, including tabs and line breaks, are shown as a single space.
+            let control = g.base < ' ' || g.base == '\x7f';
+            let width = if control { 1 } else { g.width };
+            let cluster = &bytes[g.range];
+
+            if width == 0 {
+                // Combining marks without a base and the like get appended to the preceding cell.
+                if x > clip.left && x <= clip.right {
+                    let mut prev = x - 1;
+                    while prev > clip.left && self.back.cells[row + prev as usize].is_continuation() {
+                        prev -= 1;
+                    }
+                    self.back.cells[row + prev as usize].append_text(cluster);
+                }
+                continue;
+            }
+
+            let next = x + width;
+            if x >= clip.left && next <= clip.right {
+                self.split_wide_chars(row, x, next);
+                self.back.cells[row + x as usize].set_text(if control { b" " } else { cluster });
+                for i in x + 1..next {
+                    self.back.cells[row + i as usize].set_text(b"");
+                }
+            } else {
+                // A wide grapheme that is cut off by the clip rect is replaced with spaces.
+                for i in x.max(clip.left)..next.min(clip.right) {
+                    self.back.cells[row + i as usize].set_text(b" ");
+                }
+            }
+            x = next;
+        }
+
+        x
+    }
+
+    /// Replaces the wide characters that `beg..end` in the row starting at
+    /// index `row` partially overlaps with spaces, since they're about to be
+    /// cut in half. Otherwise, stale continuation cells would linger around.
+    fn split_wide_chars(&mut self, row: usize, beg: CoordType, end: CoordType) {
+        let cells = &mut self.back.cells[row..row + self.back.size.width as usize];
+        let (beg, end) = (beg as usize, end as usize);
+
+        let mut i = beg;
+        while i > 0 && cells[i].is_continuation() {
+            i -= 1;
+            cells[i].set_text(b" ");
+        }
+
+        let mut i = end;
+        while i < cells.len() && cells[i].is_continuation() {
+            cells[i].set_text(b" ");
+            i += 1;
+        }
+    }
+
+    /// Blends the given background `color` over the cells in `target`.
+    pub fn blend_bg(&mut self, target: Rect, color: StraightRgba) {
+        self.for_each_cell(target, |cell| cell.bg = Self::blend(cell.bg, color));
+    }
+
+    /// Blends the given foreground `color` over the cells in `target`.
+    pub fn blend_fg(&mut self, target: Rect, color: StraightRgba) {
+        self.for_each_cell(target, |cell| cell.fg = Self::blend(cell.fg, color));
+    }
+
+    /// Replaces the attributes in `mask` with those in `attr` for the cells in `target`.
+    pub fn replace_attr(&mut self, target: Rect, mask: Attributes, attr: Attributes) {
+        let attr = Attributes(attr.0 & mask.0);
+        self.for_each_cell(target, |cell| cell.attr = Attributes(cell.attr.0 & !mask.0 | attr.0));
+    }
+
+    /// Draws a single-line box around the edges of `rect`, clipped to `clip`.
+    pub fn draw_border(&mut self, rect: Rect, clip: Rect) {
+        if rect.right - rect.left < 2 || rect.bottom - rect.top < 2 {
+            return;
+        }
+
+        let inner = rect.right - rect.left - 2;
+        let mut line = String::with_capacity((inner as usize + 2) * 3);
+
+        for (y, [l, m, r]) in [
+            (rect.top, ["┌", "─", "┐"]),
+            (rect.bottom - 1, ["└", "─", "┘"]),
+        ] {
+            line.clear();
+            line.push_str(l);
+            for _ in 0..inner {
+                line.push_str(m);
+            }
+            line.push_str(r);
+            self.replace_text(y, rect.left, clip, &line);
+        }
+
+        for y in rect.top + 1..rect.bottom - 1 {
+            self.replace_text(y, rect.left, clip, "│");
+            self.replace_text(y, rect.right - 1, clip, "│");
+        }
+    }
+
+    /// Writes the VT sequences that turn the previously rendered frame into the current one
+    /// to `out`. Cells that didn't change are skipped, as are redundant SGR sequences.
+    pub fn render(&mut self, out: &mut ArenaString) {
+        let back = &self.back;
+        let front = &self.front;
+        let full = front.size != back.size || front.cells.len() != back.cells.len();
+        let width = back.size.width;
+
+        let mut vt = VtState::default();
+        let mut cursor_hidden = !self.cursor_visible;
+
+        for y in 0..back.size.height {
+            let row = (y * width) as usize;
+            let mut x = 0;
+
+            while x < width {
+                let idx = row + x as usize;
+                let cell = &back.cells[idx];
+
+                // A wide character must be redrawn as a whole, if any of its cells changed.
+                let mut w = 1;
+                while x + w < width && back.cells[idx + w as usize].is_continuation() {
+                    w += 1;
+                }
+
+                if full || back.cells[idx..idx + w as usize] != front.cells[idx..idx + w as usize] {
+                    if !cursor_hidden {
+                        // Hide the cursor while drawing to avoid flicker.
+                        out.push_str("\x1b[?25l");
+                        cursor_hidden = true;
+                    }
+
+                    Self::move_cursor(out, &mut vt.pos, Point { x, y });
+                    self.format_cell_style(out, &mut vt, cell);
+                    // Continuation cells without a leading cell shouldn't exist, but who knows.
+                    out.push_str(if cell.is_continuation() { " " } else { cell.text() });
+                    vt.pos.x += w;
+                }
+
+                x += w;
+            }
+        }
+
+        if let Some(pos) = back.cursor {
+            // Unless nothing was drawn and the cursor stayed where it was, move it into place.
+            if vt.pos != Point::MIN || front.cursor != Some(pos) {
+                Self::move_cursor(out, &mut vt.pos, pos);
+            }
+            if cursor_hidden {
+                out.push_str("\x1b[?25h");
+            }
+        } else if !cursor_hidden {
+            out.push_str("\x1b[?25l");
+        }
+
+        self.cursor_visible = back.cursor.is_some();
+        mem::swap(&mut self.front, &mut self.back);
+    }
+
+    /// Moves the cursor from `pos` to `target`, using the shortest sequence
+    /// we know of. `pos` is [`Point::MIN`] if the position is unknown.
+    fn move_cursor(out: &mut ArenaString, pos: &mut Point, target: Point) {
+        if *pos == target {
+            return;
+        }
+
+        if pos.y == target.y && pos.x < target.x && pos.x != CoordType::MIN {
+            // Cursor Forward (CUF).
+            _ = write!(out, "\x1b[{}C", target.x - pos.x);
+        } else if target.x == 0 {
+            _ = write!(out, "\x1b[{}H", target.y + 1);
+        } else {
+            _ = write!(out, "\x1b[{};{}H", target.y + 1, target.x + 1);
+        }
+
+        *pos = target;
+    }
+
+    /// Writes the SGR sequences needed to switch from the `vt` state to the style of `cell`.
+    fn format_cell_style(&self, out: &mut ArenaString, vt: &mut VtState, cell: &GridCell) {
+        let attr = match vt.attr {
+            Some(attr) => attr,
+            None => {
+                // We don't know what the terminal's state is, so reset it once.
+                out.push_str("\x1b[0m");
+                Attributes::NONE
+            }
+        };
+
+        if attr != cell.attr {
+            out.push_str("\x1b[");
+            let mut first = true;
+            for (a, on, off) in ATTRIBUTE_SGR {
+                if attr.contains(a) != cell.attr.contains(a) {
+                    if !first {
+                        out.push_str(";");
+                    }
+                    out.push_str(if cell.attr.contains(a) { on } else { off });
+                    first = false;
+                }
+            }
+            out.push_str("m");
+        }
+        vt.attr = Some(cell.attr);
+
+        if vt.bg != Some(cell.bg) {
+            vt.bg = Some(cell.bg);
+            self.format_color(out, false, cell.bg);
+        }
+        if vt.fg != Some(cell.fg) {
+            vt.fg = Some(cell.fg);
+            self.format_color(out, true, cell.fg);
+        }
+    }
+
+    fn format_color(&self, out: &mut ArenaString, fg: bool, mut color: StraightRgba) {
+        let typ = if fg { '3' } else { '4' };
+
+        // Transparent means "default color". If the palette was detected, the
+        // default fill is transparent, which lets terminals with transparent
+        // backgrounds show through. It also keeps the output a little shorter.
+        if color.to_ne() == 0 {
+            _ = write!(out, "\x1b[{typ}9m");
+            return;
+        }
+
+        if color.alpha() != 0xff {
+            let idx = if fg { IndexedColor::Foreground } else { IndexedColor::Background };
+            color = self.indexed(idx).oklab_blend(color);
+        }
+
+        _ = write!(out, "\x1b[{typ}8;2;{};{};{}m", color.red(), color.green(), color.blue());
+    }
+
+    fn blend(bottom: StraightRgba, top: StraightRgba) -> StraightRgba {
+        match top.alpha() {
+            0 => bottom,
+            0xff => top,
+            _ if bottom.to_ne() == 0 => top,
+            _ => bottom.oklab_blend(top),
+        }
+    }
+
+    fn for_each_cell(&mut self, target: Rect, mut f: impl FnMut(&mut GridCell)) {
+        let target = target.intersect(self.back.size.as_rect());
+        let width = self.back.size.width;
+        for y in target.top..target.bottom {
+            let row = (y * width) as usize;
+            for cell in &mut self.back.cells[row + target.left as usize..row + target.right as usize] {
+                f(cell);
+            }
+        }
     }
 
     fn is_dark(color: StraightRgba) -> bool {
@@ -165,3 +533,153 @@ impl Framebuffer {
         contrast
     }
 }
+
+/// A grid of cells.
+#[derive(Default)]
+struct Buffer {
+    cells: Vec<GridCell>,
+    size: Size,
+    /// The position of the cursor, if it's visible.
+    cursor: Option<Point>,
+}
+
+/// What we know about the terminal's state while rendering.
+/// `None` means unknown, since other output may have changed it.
+struct VtState {
+    pos: Point,
+    bg: Option<StraightRgba>,
+    fg: Option<StraightRgba>,
+    attr: Option<Attributes>,
+}
+
+impl Default for VtState {
+    fn default() -> Self {
+        Self { pos: Point::MIN, bg: None, fg: None, attr: None }
+    }
+}
+
+/// A single cell of the terminal.
+///
+/// The text of a wide character is stored in its first cell.
+/// The cells it covers beyond that have empty text.
+#[derive(Clone, Copy)]
+struct GridCell {
+    text: [u8; CELL_TEXT_CAPACITY],
+    text_len: u8,
+    bg: StraightRgba,
+    fg: StraightRgba,
+    attr: Attributes,
+}
+
+impl PartialEq for GridCell {
+    fn eq(&self, other: &Self) -> bool {
+        // Only the used part of `text` is compared. The rest may contain leftovers.
+        self.text() == other.text()
+            && self.text_len == other.text_len
+            && self.bg == other.bg
+            && self.fg == other.fg
+            && self.attr == other.attr
+    }
+}
+
+impl GridCell {
+    fn blank(bg: StraightRgba, fg: StraightRgba) -> Self {
+        let mut text = [0; CELL_TEXT_CAPACITY];
+        text[0] = b' ';
+        Self { text, text_len: 1, bg, fg, attr: Attributes::NONE }
+    }
+
+    fn text(&self) -> &str {
+        // SAFETY: `set_text` and `append_text` only ever store complete UTF-8 sequences.
+        unsafe { str::from_utf8_unchecked(&self.text[..self.text_len as usize]) }
+    }
+
+    fn is_continuation(&self) -> bool {
+        self.text_len == 0
+    }
+
+    fn set_text(&mut self, text: &[u8]) {
+        self.text_len = 0;
+        self.append_text(text);
+    }
+
+    fn append_text(&mut self, text: &[u8]) {
+        let len = self.text_len as usize;
+        if len + text.len() <= CELL_TEXT_CAPACITY {
+            self.text[len..len + text.len()].copy_from_slice(text);
+            self.text_len += text.len() as u8;
+        } else if len == 0 {
+            self.set_text("\u{FFFD}".as_bytes());
+        }
+        // Otherwise, the grapheme is too long. Drop the rest of it.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stdext::arena::Arena;
+
+    /// Draws `rows` into a new frame and returns the rendered output.
+    fn render(fb: &mut Framebuffer, rows: &[&str], cursor: Option<Point>) -> String {
+        let size = Size { width: 4, height: rows.len() as CoordType };
+        fb.flip(size);
+        for (y, row) in rows.iter().enumerate() {
+            fb.replace_text(y as CoordType, 0, size.as_rect(), row);
+        }
+        if let Some(pos) = cursor {
+            fb.set_cursor(pos);
+        }
+
+        let arena = Arena::new(64 * 1024).unwrap();
+        let mut out = ArenaString::new_in(&arena);
+        fb.render(&mut out);
+        out.as_str().to_string()
+    }
+
+    #[test]
+    fn test_render_diff() {
+        let mut fb = Framebuffer::new();
+
+        // The terminal starts out with a visible cursor, which has to be hidden right away.
+        let out = render(&mut fb, &["abcd", "efgh"], None);
+        assert!(out.starts_with("\x1b[?25l\x1b[1H\x1b[0m"), "{out:?}");
+        // All cells share the same colors, so they're only set once.
+        assert_eq!(out.matches("\x1b[38;2;").count(), 1, "{out:?}");
+        assert_eq!(out.matches("\x1b[48;2;").count(), 1, "{out:?}");
+        assert!(out.ends_with("abcd\x1b[2Hefgh"), "{out:?}");
+
+        // Nothing changed, nothing to do.
+        assert_eq!(render(&mut fb, &["abcd", "efgh"], None), "");
+
+        // Only the changed cell is drawn, and then the cursor is shown.
+        let cursor = Some(Point { x: 0, y: 1 });
+        let out = render(&mut fb, &["abXd", "efgh"], cursor);
+        assert!(out.starts_with("\x1b[1;3H\x1b[0m"), "{out:?}");
+        assert!(out.ends_with("X\x1b[2H\x1b[?25h"), "{out:?}");
+        assert_eq!(render(&mut fb, &["abXd", "efgh"], cursor), "");
+
+        // The cursor is hidden while drawing, to avoid flicker, and shown again afterwards.
+        let out = render(&mut fb, &["abXd", "efgY"], cursor);
+        assert!(out.starts_with("\x1b[?25l\x1b[2;4H"), "{out:?}");
+        assert!(out.ends_with("Y\x1b[2H\x1b[?25h"), "{out:?}");
+
+        // Hiding the cursor without drawing anything.
+        assert_eq!(render(&mut fb, &["abXd", "efgY"], None), "\x1b[?25l");
+    }
+
+    #[test]
+    fn test_render_attributes() {
+        let mut fb = Framebuffer::new();
+        let size = Size { width: 4, height: 1 };
+        fb.flip(size);
+        fb.replace_text(0, 0, size.as_rect(), "abcd");
+        fb.replace_attr(Rect { left: 1, top: 0, right: 3, bottom: 1 }, Attributes::ALL, Attributes::BOLD);
+
+        let arena = Arena::new(64 * 1024).unwrap();
+        let mut out = ArenaString::new_in(&arena);
+        fb.render(&mut out);
+        // Bold is switched on once for both cells and off again for the last one.
+        assert!(out.as_str().ends_with("a\x1b[1mbc\x1b[22md"), "{:?}", out.as_str());
+    }
+}
@@ -0,0 +1,44 @@
+
+//! A tiny, fast, non-cryptographic hash. Used to derive stable IDs for UI nodes.
+
+/// The seed to start hashing with, if there's no parent hash to build on.
+pub const INITIAL_HASH: u64 = 0x811C_9DC5_2DF7_8E53;
+
+/// Hashes `data` and mixes it into `seed`.
+#[inline]
+pub const fn hash(seed: u64, data: &[u8]) -> u64 {
+    // Byte-wise 64-bit FNV-1a, followed by the SplitMix64 finalizer, because FNV alone
+    // mixes the last few bytes poorly. Good enough for the short strings we're hashing.
+    let mut h = seed;
+    let mut i = 0;
+
+    while i < data.len() {
+        h ^= data[i] as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01B3);
+        i += 1;
+    }
+
+    mix(h)
+}
+
+/// Hashes `s` and mixes it into `seed`.
+#[inline]
+pub const fn hash_str(seed: u64, s: &str) -> u64 {
+    hash(seed, s.as_bytes())
+}
+
+/// Mixes `value` into `seed`.
+#[inline]
+pub const fn hash_u64(seed: u64, value: u64) -> u64 {
+    mix(seed ^ value.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+/// The finalizer of SplitMix64. Spreads the entropy across all bits.
+#[inline]
+const fn mix(mut h: u64) -> u64 {
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^ (h >> 31)
+}
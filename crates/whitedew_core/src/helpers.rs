@@ -1,4 +1,6 @@
 
+use std::cmp::Ordering;
+
 pub const KILO: usize = 1000;
 pub const MEGA: usize = 1000 * 1000;
 pub const GIGA: usize = 1000 * 1000 * 1000;
@@ -25,6 +27,43 @@ impl AsciiStringHelpers for str {
     }
 }
 
+/// Compares two strings the way a human would sort them: case-insensitively,
+/// and with runs of digits compared by their value, so that "file2" comes before "file10".
+///
+/// Strings that only differ in case or leading zeros are ordered by [`str::cmp`],
+/// which makes the order total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn take_digits<'a>(s: &mut &'a str) -> &'a str {
+        let len = s.bytes().take_while(u8::is_ascii_digit).count();
+        let (digits, rest) = s.split_at(len);
+        *s = rest;
+        digits.trim_start_matches('0')
+    }
+
+    let mut rest_a = a;
+    let mut rest_b = b;
+
+    while let (Some(ca), Some(cb)) = (rest_a.chars().next(), rest_b.chars().next()) {
+        let ord = if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let da = take_digits(&mut rest_a);
+            let db = take_digits(&mut rest_b);
+            // Without leading zeros, the longer number is the larger one.
+            da.len().cmp(&db.len()).then_with(|| da.cmp(db))
+        } else {
+            rest_a = &rest_a[ca.len_utf8()..];
+            rest_b = &rest_b[cb.len_utf8()..];
+            ca.to_lowercase().cmp(cb.to_lowercase())
+        };
+
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+
+    // The shorter string comes first, if one is a prefix of the other.
+    rest_a.is_empty().cmp(&rest_b.is_empty()).reverse().then_with(|| a.cmp(b))
+}
+
 /// A viewport coordinate type used throughout the application.
 pub type CoordType = isize;
 
@@ -56,6 +95,43 @@ pub struct Rect {
     pub bottom: CoordType,
 }
 
+impl Rect {
+    /// Creates a rectangle with all sides set to `value`. Useful for padding.
+    pub const fn one(value: CoordType) -> Self {
+        Self { left: value, top: value, right: value, bottom: value }
+    }
+
+    /// Creates a rectangle with `left`/`right` set to `lr` and `top`/`bottom` set to `tb`.
+    pub const fn two(tb: CoordType, lr: CoordType) -> Self {
+        Self { left: lr, top: tb, right: lr, bottom: tb }
+    }
+
+    pub const fn width(&self) -> CoordType {
+        self.right - self.left
+    }
+
+    pub const fn height(&self) -> CoordType {
+        self.bottom - self.top
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.left >= self.right || self.top >= self.bottom
+    }
+
+    pub const fn contains(&self, point: Point) -> bool {
+        point.x >= self.left && point.x < self.right && point.y >= self.top && point.y < self.bottom
+    }
+
+    /// Returns the overlap of the two rectangles. Empty if they don't overlap.
+    pub fn intersect(&self, rhs: Self) -> Self {
+        let left = self.left.max(rhs.left);
+        let top = self.top.max(rhs.top);
+        let right = self.right.min(rhs.right).max(left);
+        let bottom = self.bottom.min(rhs.bottom).max(top);
+        Self { left, top, right, bottom }
+    }
+}
+
 // A 2D size. Uses [`CoordType`].
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Size {
@@ -68,3 +144,43 @@ impl Size {
         Rect { left: 0, top: 0, right: self.width, bottom: self.height }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_cmp() {
+        // Each entry must sort before the next one.
+        let sorted = [
+            "",
+            "01",
+            "1",
+            "2",
+            "10",
+            "a",
+            "A1",
+            "a2",
+            "a10",
+            "a10b",
+            "a010c",
+            "B",
+            "b",
+            "file 2.txt",
+            "file.txt",
+            "file1.txt",
+            "file9.txt",
+            "file10.txt",
+            "file100.txt",
+            "über",
+        ];
+
+        for pair in sorted.windows(2) {
+            assert_eq!(natural_cmp(pair[0], pair[1]), Ordering::Less, "{:?} < {:?}", pair[0], pair[1]);
+            assert_eq!(natural_cmp(pair[1], pair[0]), Ordering::Greater, "{:?} > {:?}", pair[1], pair[0]);
+        }
+        for s in sorted {
+            assert_eq!(natural_cmp(s, s), Ordering::Equal, "{s:?}");
+        }
+    }
+}
@@ -1,4 +1,417 @@
 
-pub fn apperr_format(f: &mut std::fmt::Formatter<'_>, code: u32) -> std::fmt::Result {
+//! Bindings to ICU, which is loaded at runtime with `dlopen`.
+//!
+//! ICU is large and not installed everywhere, so it's an optional dependency.
+//! Everything in here returns [`apperr::APP_ICU_MISSING`] if it's unavailable
+//! and callers are expected to fall back to something simpler.
+
+use crate::{apperr, sys};
+use std::{
+    cmp::Ordering,
+    ffi::{CStr, CString, c_char, c_void},
+    fmt,
+    ptr::NonNull,
+    sync::OnceLock,
+};
+
+/// Where to find ICU and how its functions are named.
+/// The application knows this from its build configuration.
+#[derive(Clone, Copy)]
+pub struct IcuConfig {
+    /// The library with the common functions, e.g. `libicuuc.so`.
+    pub icuuc_soname: &'static str,
+    /// The library with the internationalization functions, e.g. `libicui18n.so`.
+    pub icui18n_soname: &'static str,
+    /// Prepended to each function name. `_` if ICU exports C++ names.
+    pub export_prefix: &'static str,
+    /// Appended to each function name, e.g. `_74` if ICU was built with symbol renaming.
+    pub export_suffix: &'static str,
+    /// Ignore `export_suffix` and find out the version of the installed ICU instead.
+    /// Linux distributions all ship different versions, each with its own suffix.
+    pub renaming_auto_detect: bool,
+}
+
+impl Default for IcuConfig {
+    fn default() -> Self {
+        let (icuuc_soname, icui18n_soname) = if cfg!(target_os = "macos") {
+            ("libicucore.dylib", "libicucore.dylib")
+        } else {
+            ("libicuuc.so", "libicui18n.so")
+        };
+        Self {
+            icuuc_soname,
+            icui18n_soname,
+            export_prefix: "",
+            export_suffix: "",
+            renaming_auto_detect: cfg!(unix) && !cfg!(target_os = "macos"),
+        }
+    }
+}
+
+type UErrorCode = i32;
+
+const U_ZERO_ERROR: UErrorCode = 0;
+const U_ILLEGAL_ARGUMENT_ERROR: UErrorCode = 1;
+const U_BUFFER_OVERFLOW_ERROR: UErrorCode = 15;
+const UCOL_NUMERIC_COLLATION: i32 = 7;
+const UCOL_ON: i32 = 17;
+
+/// The versions that [`IcuConfig::renaming_auto_detect`] looks for, newest first.
+const AUTO_DETECT_VERSIONS: std::ops::RangeInclusive<u32> = 50..=99;
+
+struct Library {
+    u_error_name: unsafe extern "C" fn(code: UErrorCode) -> *const c_char,
+    ucol_strcoll_utf8: unsafe extern "C" fn(
+        coll: *mut c_void,
+        source: *const c_char,
+        source_length: i32,
+        target: *const c_char,
+        target_length: i32,
+        status: *mut UErrorCode,
+    ) -> i32,
+    /// The root collator with numeric ordering, used by [`compare_strings`].
+    collator: Option<NonNull<c_void>>,
+
+    ucnv_open: unsafe extern "C" fn(name: *const c_char, status: *mut UErrorCode) -> *mut c_void,
+    ucnv_close: unsafe extern "C" fn(cnv: *mut c_void),
+    ucnv_convert_ex: unsafe extern "C" fn(
+        target_cnv: *mut c_void,
+        source_cnv: *mut c_void,
+        target: *mut *mut c_char,
+        target_limit: *const c_char,
+        source: *mut *const c_char,
+        source_limit: *const c_char,
+        pivot_start: *mut u16,
+        pivot_source: *mut *mut u16,
+        pivot_target: *mut *mut u16,
+        pivot_limit: *const u16,
+        reset: i8,
+        flush: i8,
+        status: *mut UErrorCode,
+    ),
+    ucnv_count_available: unsafe extern "C" fn() -> i32,
+    ucnv_get_available_name: unsafe extern "C" fn(n: i32) -> *const c_char,
+    ucnv_get_standard_name: unsafe extern "C" fn(
+        name: *const c_char,
+        standard: *const c_char,
+        status: *mut UErrorCode,
+    ) -> *const c_char,
+}
+
+// The collator is only used for comparisons, which ICU allows from any thread.
+// Everything else is plain function pointers.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+static CONFIG: OnceLock<IcuConfig> = OnceLock::new();
+static LIBRARY: OnceLock<apperr::Result<Library>> = OnceLock::new();
+
+/// Sets where to find ICU. Without a call to this, [`IcuConfig::default`] is used.
+///
+/// ICU itself is only loaded once it's needed. Must be called before that.
+pub fn configure(config: IcuConfig) {
+    _ = CONFIG.set(config);
+}
+
+/// Loads ICU if that didn't happen yet. Returns [`apperr::APP_ICU_MISSING`] if it isn't installed.
+pub fn init() -> apperr::Result<()> {
+    library().map(|_| ())
+}
+
+fn library() -> apperr::Result<&'static Library> {
+    LIBRARY
+        .get_or_init(|| {
+            let config = CONFIG.get_or_init(IcuConfig::default);
+            unsafe { load(config) }.ok_or(apperr::APP_ICU_MISSING)
+        })
+        .as_ref()
+        .map_err(|&err| err)
+}
+
+unsafe fn load(config: &IcuConfig) -> Option<Library> {
+    let (icuuc, version) = load_library(config.icuuc_soname, config.renaming_auto_detect)?;
+    let icui18n = if config.icui18n_soname == config.icuuc_soname {
+        icuuc
+    } else {
+        load_library(config.icui18n_soname, config.renaming_auto_detect)?.0
+    };
+
+    let suffix = if !config.renaming_auto_detect {
+        config.export_suffix.to_string()
+    } else {
+        detect_suffix(icuuc, config.export_prefix, version)?
+    };
+    let symbol = |name: &str| CString::new(format!("{}{name}{suffix}", config.export_prefix)).unwrap();
+
+    unsafe {
+        let u_error_name = sys::get_proc_address(icuuc, &symbol("u_errorName"))?;
+        let ucol_strcoll_utf8 = sys::get_proc_address(icui18n, &symbol("ucol_strcollUTF8"))?;
+        let ucol_open: unsafe extern "C" fn(*const c_char, *mut UErrorCode) -> *mut c_void =
+            sys::get_proc_address(icui18n, &symbol("ucol_open"))?;
+        let ucol_set_attribute: unsafe extern "C" fn(*mut c_void, i32, i32, *mut UErrorCode) =
+            sys::get_proc_address(icui18n, &symbol("ucol_setAttribute"))?;
+
+        // The empty locale is the root locale, which sorts reasonably for every language.
+        let mut status = U_ZERO_ERROR;
+        let collator = NonNull::new(ucol_open(c"".as_ptr(), &mut status));
+        if let Some(coll) = collator {
+            ucol_set_attribute(coll.as_ptr(), UCOL_NUMERIC_COLLATION, UCOL_ON, &mut status);
+        }
+        let collator = if status > U_ZERO_ERROR { None } else { collator };
+
+        Some(Library {
+            u_error_name,
+            ucol_strcoll_utf8,
+            collator,
+            ucnv_open: sys::get_proc_address(icuuc, &symbol("ucnv_open"))?,
+            ucnv_close: sys::get_proc_address(icuuc, &symbol("ucnv_close"))?,
+            ucnv_convert_ex: sys::get_proc_address(icuuc, &symbol("ucnv_convertEx"))?,
+            ucnv_count_available: sys::get_proc_address(icuuc, &symbol("ucnv_countAvailable"))?,
+            ucnv_get_available_name: sys::get_proc_address(icuuc, &symbol("ucnv_getAvailableName"))?,
+            ucnv_get_standard_name: sys::get_proc_address(icuuc, &symbol("ucnv_getStandardName"))?,
+        })
+    }
+}
+
+/// Loads the library `soname`. If it isn't there and `auto_detect` is set, this also tries
+/// the versioned names like `libicuuc.so.74`, because the unversioned one usually only comes
+/// with the development package. Returns the version if it was found that way.
+fn load_library(soname: &str, auto_detect: bool) -> Option<(NonNull<c_void>, Option<u32>)> {
+    let name = CString::new(soname).ok()?;
+    if let Some(handle) = sys::load_library(&name) {
+        return Some((handle, None));
+    }
+    if !auto_detect {
+        return None;
+    }
+
+    AUTO_DETECT_VERSIONS.rev().find_map(|version| {
+        let name = CString::new(format!("{soname}.{version}")).ok()?;
+        sys::load_library(&name).map(|handle| (handle, Some(version)))
+    })
+}
+
+/// Finds out which suffix the functions in `icuuc` have: none, or `_74` for version 74, etc.
+fn detect_suffix(icuuc: NonNull<c_void>, prefix: &str, version: Option<u32>) -> Option<String> {
+    let exists = |suffix: &str| {
+        let name = CString::new(format!("{prefix}u_errorName{suffix}")).unwrap();
+        unsafe { sys::get_proc_address::<unsafe extern "C" fn()>(icuuc, &name) }.is_some()
+    };
+
+    if exists("") {
+        return Some(String::new());
+    }
+
+    // The version of the library file is the most likely one, if we know it.
+    let versions = version.into_iter().chain(AUTO_DETECT_VERSIONS.rev());
+    versions.map(|v| format!("_{v}")).find(|suffix| exists(suffix))
+}
+
+pub fn apperr_format(f: &mut fmt::Formatter<'_>, code: u32) -> fmt::Result {
+    write!(f, "ICU Error {code}")?;
+
+    if let Ok(lib) = library() {
+        let ptr = unsafe { (lib.u_error_name)(code as UErrorCode) };
+        if !ptr.is_null() {
+            let name = unsafe { CStr::from_ptr(ptr) }.to_string_lossy();
+            write!(f, ": {name}")?;
+        }
+    }
+
     Ok(())
 }
+
+/// Compares two strings the way a human would sort them: according to the
+/// Unicode collation rules and with numbers ordered by their value.
+pub fn compare_strings(a: &str, b: &str) -> apperr::Result<Ordering> {
+    let lib = library()?;
+    let coll = lib.collator.ok_or(apperr::APP_ICU_MISSING)?;
+
+    let mut status = U_ZERO_ERROR;
+    let res = unsafe {
+        (lib.ucol_strcoll_utf8)(
+            coll.as_ptr(),
+            a.as_ptr() as *const c_char,
+            a.len().min(i32::MAX as usize) as i32,
+            b.as_ptr() as *const c_char,
+            b.len().min(i32::MAX as usize) as i32,
+            &mut status,
+        )
+    };
+    if status > U_ZERO_ERROR {
+        return Err(apperr::Error::new_icu(status as u32));
+    }
+
+    Ok(res.cmp(&0))
+}
+
+/// Returns the names of all encodings that ICU can convert from and to.
+/// Where possible, these are the IANA names, e.g. `windows-1252` instead of ICU's `ibm-5348_P100-1997`.
+pub fn encodings() -> apperr::Result<Vec<&'static str>> {
+    let lib = library()?;
+    let mut res = Vec::new();
+
+    unsafe {
+        for i in 0..(lib.ucnv_count_available)() {
+            let name = (lib.ucnv_get_available_name)(i);
+            if name.is_null() {
+                continue;
+            }
+
+            let mut status = U_ZERO_ERROR;
+            let iana = (lib.ucnv_get_standard_name)(name, c"IANA".as_ptr(), &mut status);
+            let name = if !iana.is_null() && status <= U_ZERO_ERROR { iana } else { name };
+            // The names live in ICU's data, which stays loaded until we exit.
+            if let Ok(name) = CStr::from_ptr(name).to_str() {
+                res.push(name);
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+/// Converts text from one encoding to another, e.g. from Shift_JIS to UTF-8.
+///
+/// The text can be fed in chunk by chunk. Characters that are split
+/// across two chunks are carried over to the next call.
+pub struct Converter {
+    lib: &'static Library,
+    source: NonNull<c_void>,
+    target: NonNull<c_void>,
+    /// ICU converts via UTF-16. This holds what was converted from
+    /// `source` but didn't fit into the output, between `pivot_source..pivot_target`.
+    pivot: Box<[u16]>,
+    pivot_source: usize,
+    pivot_target: usize,
+    reset: bool,
+}
+
+impl Converter {
+    pub fn new(from: &str, to: &str) -> apperr::Result<Self> {
+        let lib = library()?;
+        let invalid = apperr::Error::new_icu(U_ILLEGAL_ARGUMENT_ERROR as u32);
+        let open = |name: &str| {
+            let name = CString::new(name).map_err(|_| invalid)?;
+            let mut status = U_ZERO_ERROR;
+            // On failure, ucnv_open() returns null and there's nothing to close.
+            let cnv = unsafe { (lib.ucnv_open)(name.as_ptr(), &mut status) };
+            if status > U_ZERO_ERROR {
+                return Err(apperr::Error::new_icu(status as u32));
+            }
+            NonNull::new(cnv).ok_or(invalid)
+        };
+
+        let source = open(from)?;
+        let target = match open(to) {
+            Ok(target) => target,
+            Err(err) => {
+                unsafe { (lib.ucnv_close)(source.as_ptr()) };
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            lib,
+            source,
+            target,
+            pivot: vec![0; 1024].into_boxed_slice(),
+            pivot_source: 0,
+            pivot_target: 0,
+            reset: true,
+        })
+    }
+
+    /// Converts `input` and appends the result to `out`.
+    /// Pass `flush = true` with the last chunk, so that nothing is held back.
+    pub fn convert(&mut self, input: &[u8], flush: bool, out: &mut Vec<u8>) -> apperr::Result<()> {
+        let mut source = input.as_ptr() as *const c_char;
+        let source_limit = input.as_ptr_range().end as *const c_char;
+
+        loop {
+            out.reserve(input.len() * 2 + 16);
+
+            let spare = out.spare_capacity_mut();
+            let target_start = spare.as_mut_ptr() as *mut c_char;
+            let mut target = target_start;
+            let mut status = U_ZERO_ERROR;
+
+            unsafe {
+                let target_limit = target_start.add(spare.len());
+                let pivot_start = self.pivot.as_mut_ptr();
+                let pivot_limit = pivot_start.add(self.pivot.len());
+                let mut pivot_source = pivot_start.add(self.pivot_source);
+                let mut pivot_target = pivot_start.add(self.pivot_target);
+
+                (self.lib.ucnv_convert_ex)(
+                    self.target.as_ptr(),
+                    self.source.as_ptr(),
+                    &mut target,
+                    target_limit,
+                    &mut source,
+                    source_limit,
+                    pivot_start,
+                    &mut pivot_source,
+                    &mut pivot_target,
+                    pivot_limit,
+                    self.reset as i8,
+                    flush as i8,
+                    &mut status,
+                );
+
+                self.pivot_source = pivot_source.offset_from(pivot_start) as usize;
+                self.pivot_target = pivot_target.offset_from(pivot_start) as usize;
+                out.set_len(out.len() + target.offset_from(target_start) as usize);
+            }
+            self.reset = false;
+
+            match status {
+                U_BUFFER_OVERFLOW_ERROR => continue,
+                s if s > U_ZERO_ERROR => return Err(apperr::Error::new_icu(s as u32)),
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+impl Drop for Converter {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.ucnv_close)(self.source.as_ptr());
+            (self.lib.ucnv_close)(self.target.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_strings() {
+        // Not every machine has ICU, but where it's missing, this must fail cleanly.
+        match compare_strings("file2", "file10") {
+            Ok(ord) => {
+                assert_eq!(ord, Ordering::Less);
+                assert_eq!(compare_strings("a", "B"), Ok(Ordering::Less));
+                assert_eq!(compare_strings("same", "same"), Ok(Ordering::Equal));
+            }
+            Err(err) => assert_eq!(err, apperr::APP_ICU_MISSING),
+        }
+    }
+
+    #[test]
+    fn test_converter() {
+        let mut cnv = match Converter::new("Shift_JIS", "UTF-8") {
+            Ok(cnv) => cnv,
+            Err(err) => return assert_eq!(err, apperr::APP_ICU_MISSING),
+        };
+
+        // "日本" split in the middle of the first character.
+        let mut out = Vec::new();
+        cnv.convert(b"\x93", false, &mut out).unwrap();
+        cnv.convert(b"\xfa\x96\x7b", true, &mut out).unwrap();
+        assert_eq!(out, "日本".as_bytes());
+        assert!(encodings().unwrap().contains(&"Shift_JIS"));
+    }
+}
@@ -59,6 +59,10 @@ impl InputKey {
     pub(crate) const fn with_modifiers(&self, modifiers: InputKeyMod) -> Self {
         Self(self.0 | modifiers.0)
     }
+
+    pub(crate) const fn without_modifiers(&self, modifiers: InputKeyMod) -> Self {
+        Self(self.0 & !modifiers.0)
+    }
 }
 
 /// Keyboard keys.
@@ -6,12 +6,16 @@ pub mod base64;
 pub mod buffer;
 pub mod cell;
 pub mod clipboard;
+pub mod document;
+pub mod encoding;
 pub mod framebuffer;
+pub mod hash;
 pub mod helpers;
 pub mod icu;
 pub mod input;
 pub mod oklab;
 pub mod path;
+pub mod regex;
 pub mod simd;
 pub mod sys;
 pub mod tui;
@@ -1,9 +1,123 @@
 
-use std::path::{Path, PathBuf};
+use std::path::{Component, MAIN_SEPARATOR_STR, Path, PathBuf};
 
+/// Normalizes the given path lexically, without touching the filesystem.
+///
+/// `.` components, duplicate separators and trailing separators are removed,
+/// and `..` removes the preceding component. A `..` can't go above the root,
+/// so `/..` becomes `/`. In relative paths, leading `..` are kept as-is.
+/// An empty result is returned as `.`.
+///
+/// Since symlinks aren't resolved, `a/link/..` becomes `a`, even if `link`
+/// points elsewhere. Use [`std::fs::canonicalize`] if that matters.
 pub fn normalize(path: &Path) -> PathBuf {
     let mut res = PathBuf::with_capacity(path.as_os_str().as_encoded_bytes().len());
-    let mut root_len = 0;
+    let mut has_root = false;
+    // The number of trailing components in `res` that a `..` may remove.
+    let mut depth = 0usize;
+
+    for component in path.components() {
+        match component {
+            Component::Prefix(p) => res.push(p.as_os_str()),
+            Component::RootDir => {
+                res.push(MAIN_SEPARATOR_STR);
+                has_root = true;
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if depth > 0 {
+                    res.pop();
+                    depth -= 1;
+                } else if !has_root {
+                    res.push("..");
+                }
+            }
+            Component::Normal(p) => {
+                res.push(p);
+                depth += 1;
+            }
+        }
+    }
+
+    if res.as_os_str().is_empty() {
+        res.push(".");
+    }
 
     res
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_normalize() {
+        let cases: &[(&str, &str)] = &[
+            // Already normalized
+            ("/", "/"),
+            ("/a", "/a"),
+            ("/a/b", "/a/b"),
+            ("a", "a"),
+            ("a/b", "a/b"),
+            // Current directory
+            (".", "."),
+            ("./", "."),
+            ("./a", "a"),
+            ("a/.", "a"),
+            ("a/./b", "a/b"),
+            ("/./a/.", "/a"),
+            // Duplicate and trailing separators
+            ("//a", "/a"),
+            ("/a//b", "/a/b"),
+            ("/a/b/", "/a/b"),
+            ("a///b//", "a/b"),
+            // Parent directory
+            ("/a/..", "/"),
+            ("/a/b/..", "/a"),
+            ("/a/../b", "/b"),
+            ("/a/b/../../c", "/c"),
+            ("a/..", "."),
+            ("a/b/../c", "a/c"),
+            // Parent directory above the root
+            ("/..", "/"),
+            ("/../..", "/"),
+            ("/../a", "/a"),
+            ("/a/../../b", "/b"),
+            // Parent directory in relative paths
+            ("..", ".."),
+            ("../a", "../a"),
+            ("../..", "../.."),
+            ("a/../..", ".."),
+            ("a/../../b", "../b"),
+            ("./../a/./../b", "../b"),
+            // Empty path
+            ("", "."),
+        ];
+
+        for &(input, expected) in cases {
+            // `Path`'s `PartialEq` compares components, which would ignore trailing separators.
+            let actual = normalize(Path::new(input));
+            assert_eq!(actual.as_os_str(), OsStr::new(expected), "normalize({input:?})");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_normalize_non_utf8() {
+        use std::os::unix::ffi::OsStrExt as _;
+
+        let cases: &[(&[u8], &[u8])] = &[
+            (b"/a/\xff/../b", b"/a/b"),
+            (b"/\xfe\xff//./\x80/", b"/\xfe\xff/\x80"),
+            (b"\xc3\x28/..", b"."),
+            (b"../\xff", b"../\xff"),
+        ];
+
+        for &(input, expected) in cases {
+            let input = Path::new(OsStr::from_bytes(input));
+            let actual = normalize(input);
+            assert_eq!(actual.as_os_str(), OsStr::from_bytes(expected), "normalize({input:?})");
+        }
+    }
+}
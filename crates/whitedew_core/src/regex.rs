@@ -0,0 +1,1072 @@
+
+//! A small regular expression engine for searching in documents.
+//!
+//! Patterns are compiled into a program for a Pike VM, which runs all possible
+//! paths through the pattern in lockstep. Unlike a backtracking engine, it takes
+//! time linear in the length of the text, no matter how unfortunate the pattern.
+//! Matches are leftmost-first, like in Perl: alternatives are tried left to right
+//! and greedy quantifiers prefer to match more.
+//!
+//! The supported syntax:
+//! * `.` matches any character except line breaks
+//! * `[abc]`, `[^abc]`, `[a-z]` character classes
+//! * `\d`, `\w`, `\s` and their negations `\D`, `\W`, `\S`, also within classes
+//! * `\n`, `\r`, `\t`, `\xHH`, `\x{HHHH}` and escaped punctuation like `\.`
+//! * `^` and `$` match at the start and end of lines
+//! * `\b` and `\B` match at word boundaries and elsewhere
+//! * `*`, `+`, `?`, `{n}`, `{n,}`, `{n,m}` and their lazy variants with a trailing `?`
+//! * `(...)` capture groups, `(?:...)` non-capturing groups and `|` alternation
+
+use crate::{apperr, document::ReadableDocument, unicode::Utf8Chars};
+use std::{mem, ops::Range};
+
+/// Counted repetitions get unrolled, so they're limited to keep the programs small.
+const MAX_REPEAT: u32 = 1000;
+
+/// A compiled regular expression.
+pub struct Regex {
+    program: Vec<Inst>,
+    classes: Vec<Class>,
+    /// The number of capture groups, including the implicit group 0 for the whole match.
+    groups: usize,
+    case_insensitive: bool,
+    /// Text every match starts with. Used to skip ahead quickly while no match is in progress.
+    prefix: Vec<u8>,
+}
+
+/// The capture groups of a match, as byte ranges.
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    /// Returns the range of group `index`. Group 0 is the whole match.
+    /// Returns `None` if the group doesn't exist or didn't participate in the match.
+    pub fn get(&self, index: usize) -> Option<Range<usize>> {
+        let beg = (*self.slots.get(index * 2)?)?;
+        let end = (*self.slots.get(index * 2 + 1)?)?;
+        Some(beg..end)
+    }
+
+    /// Returns the range of the whole match.
+    pub fn range(&self) -> Range<usize> {
+        self.get(0).unwrap_or(0..0)
+    }
+}
+
+impl Regex {
+    /// Compiles `pattern`. Returns [`apperr::APP_REGEX_INVALID`] if it's malformed.
+    pub fn new(pattern: &str, case_insensitive: bool) -> apperr::Result<Self> {
+        let mut parser = Parser { chars: pattern.chars().collect(), pos: 0, groups: 1, classes: Vec::new() };
+        let node = parser.parse_alternation()?;
+        if parser.pos != parser.chars.len() {
+            // A `)` without a matching `(`.
+            return Err(apperr::APP_REGEX_INVALID);
+        }
+
+        let mut compiler = Compiler { program: Vec::new(), case_insensitive };
+        compiler.push(Inst::Save(0));
+        compiler.compile(&node);
+        compiler.push(Inst::Save(1));
+        compiler.push(Inst::Match);
+
+        let mut regex = Self {
+            program: compiler.program,
+            classes: parser.classes,
+            groups: parser.groups,
+            case_insensitive,
+            prefix: Vec::new(),
+        };
+        regex.prefix = regex.literal_prefix();
+        Ok(regex)
+    }
+
+    /// Escapes all characters in `text` that have a special meaning,
+    /// so that the resulting pattern matches `text` literally.
+    pub fn escape(text: &str) -> String {
+        let mut res = String::with_capacity(text.len());
+        for c in text.chars() {
+            if "\\.^$|?*+()[]{}".contains(c) {
+                res.push('\\');
+            }
+            res.push(c);
+        }
+        res
+    }
+
+    /// Returns the number of capture groups, including group 0 for the whole match.
+    pub fn groups(&self) -> usize {
+        self.groups
+    }
+
+    /// Returns a [`Matcher`] for running this regex over and over without allocating each time.
+    pub fn matcher(&self) -> Matcher<'_> {
+        let slots = self.groups * 2;
+        Matcher {
+            regex: self,
+            clist: Threads::new(self.program.len(), slots),
+            nlist: Threads::new(self.program.len(), slots),
+            caps: vec![None; slots],
+            stack: Vec::new(),
+        }
+    }
+
+    /// Finds the leftmost match in `text` that starts at or after the byte offset `start`.
+    /// See [`Matcher::find`].
+    pub fn find_at<D: ReadableDocument + ?Sized>(&self, text: &D, start: usize) -> Option<Captures> {
+        self.matcher().find(text, start..usize::MAX)
+    }
+
+    /// Finds all non-overlapping matches in `text`.
+    pub fn find_all<D: ReadableDocument + ?Sized>(&self, text: &D) -> Vec<Captures> {
+        let mut matcher = self.matcher();
+        let mut res = Vec::new();
+        let mut pos = 0;
+
+        while let Some(caps) = matcher.find(text, pos..usize::MAX) {
+            let range = caps.range();
+            res.push(caps);
+            pos = if !range.is_empty() {
+                range.end
+            } else if let Some((_, len)) = char_at(text, range.end) {
+                // Step over the empty match, or we'd find it again.
+                range.end + len
+            } else {
+                break;
+            };
+        }
+
+        res
+    }
+
+    /// Appends `replacement` to `out`, with `$0` to `$9` and `${N}` replaced by the
+    /// corresponding capture group of `caps` in `text`. `$$` produces a literal `$`.
+    pub fn expand<D>(caps: &Captures, text: &D, replacement: &str, out: &mut Vec<u8>)
+    where
+        D: ReadableDocument + ?Sized,
+    {
+        let bytes = replacement.as_bytes();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            if bytes[i] != b'$' {
+                out.push(bytes[i]);
+                i += 1;
+                continue;
+            }
+
+            let rest = &bytes[i + 1..];
+            let (group, len) = match rest.first() {
+                Some(b'$') => {
+                    out.push(b'$');
+                    i += 2;
+                    continue;
+                }
+                Some(d) if d.is_ascii_digit() => (Some((d - b'0') as usize), 1),
+                Some(b'{') => match rest.iter().position(|&b| b == b'}') {
+                    Some(close) => {
+                        let digits = str::from_utf8(&rest[1..close]).ok();
+                        (digits.and_then(|d| d.parse().ok()), close + 1)
+                    }
+                    None => (None, 0),
+                },
+                _ => (None, 0),
+            };
+
+            match group {
+                Some(group) => {
+                    if let Some(range) = caps.get(group) {
+                        extract(text, range, out);
+                    }
+                    i += 1 + len;
+                }
+                None => {
+                    out.push(b'$');
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Follows all jumps, splits, saves and assertions starting at `pc`, with the capture
+    /// slots in `caps`, and adds the threads that end up at a character test (or the match) to `list`.
+    /// `caps` is used as scratch space, but has its original contents again afterwards.
+    fn add_thread<D: ReadableDocument + ?Sized>(
+        &self,
+        list: &mut Threads,
+        stack: &mut Vec<Frame>,
+        caps: &mut [Option<usize>],
+        text: &D,
+        pos: usize,
+        pc: usize,
+    ) {
+        stack.push(Frame::Explore(pc));
+
+        while let Some(frame) = stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::Restore(slot, value) => {
+                    caps[slot] = value;
+                    continue;
+                }
+            };
+            if !list.insert(pc) {
+                continue;
+            }
+
+            match &self.program[pc] {
+                Inst::Jmp(target) => stack.push(Frame::Explore(*target)),
+                Inst::Split(first, second) => {
+                    // The stack is LIFO, so the preferred branch goes last.
+                    stack.push(Frame::Explore(*second));
+                    stack.push(Frame::Explore(*first));
+                }
+                Inst::Save(slot) => {
+                    // Undone once everything that follows has been explored.
+                    stack.push(Frame::Restore(*slot, caps[*slot]));
+                    caps[*slot] = Some(pos);
+                    stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::Assert(assertion) => {
+                    if assertion.holds(text, pos) {
+                        stack.push(Frame::Explore(pc + 1));
+                    }
+                }
+                Inst::Char(_) | Inst::Any | Inst::Class(_) | Inst::Match => list.push(pc, caps),
+            }
+        }
+    }
+
+    fn fold(&self, c: char) -> char {
+        if self.case_insensitive { fold_case(c) } else { c }
+    }
+
+    fn class_matches(&self, idx: usize, c: char) -> bool {
+        let class = &self.classes[idx];
+        if self.case_insensitive {
+            class.matches(c) || class.matches(fold_case(c)) || c.to_uppercase().any(|u| class.matches(u))
+        } else {
+            class.matches(c)
+        }
+    }
+
+    /// Collects the characters that every match must start with.
+    fn literal_prefix(&self) -> Vec<u8> {
+        let mut prefix = String::new();
+        if self.case_insensitive {
+            return Vec::new();
+        }
+
+        for inst in &self.program {
+            match inst {
+                Inst::Save(_) => {}
+                Inst::Char(c) => prefix.push(*c),
+                _ => break,
+            }
+        }
+        prefix.into_bytes()
+    }
+}
+
+/// Runs a [`Regex`]. Keeps the memory of the Pike VM around between searches,
+/// so that stepping through the text doesn't allocate.
+pub struct Matcher<'r> {
+    regex: &'r Regex,
+    /// The threads at the current position.
+    clist: Threads,
+    /// The threads at the next position.
+    nlist: Threads,
+    /// The capture slots of the thread that [`Regex::add_thread`] follows.
+    caps: Vec<Option<usize>>,
+    stack: Vec<Frame>,
+}
+
+impl Matcher<'_> {
+    /// Finds the leftmost match in `text` that starts within `range`. It may end past `range.end`.
+    /// `range.start` must be within `text` and at a character boundary. `range.end` may be `usize::MAX`.
+    ///
+    /// `text` doesn't need to be valid UTF-8. Invalid sequences match like U+FFFD.
+    /// The text before `range.start` is still considered for `^` and `\b`.
+    pub fn find<D: ReadableDocument + ?Sized>(&mut self, text: &D, range: Range<usize>) -> Option<Captures> {
+        let regex = self.regex;
+        let mut matched = None;
+        let mut pos = range.start;
+        self.clist.clear();
+        self.nlist.clear();
+
+        loop {
+            if matched.is_none() && pos < range.end {
+                if self.clist.is_empty() && !regex.prefix.is_empty() {
+                    // Nothing is in progress, so we can skip right to the next candidate.
+                    pos = find_bytes(text, pos, &regex.prefix).filter(|&p| p < range.end)?;
+                }
+                // New attempts have the lowest priority, so they go last.
+                self.caps.fill(None);
+                regex.add_thread(&mut self.clist, &mut self.stack, &mut self.caps, text, pos, 0);
+            }
+            if self.clist.is_empty() {
+                break;
+            }
+
+            let next = char_at(text, pos);
+            for i in 0..self.clist.dense.len() {
+                let pc = self.clist.dense[i];
+                let c = next.map(|(c, _)| c);
+                let step = match &regex.program[pc] {
+                    Inst::Char(expected) => c.is_some_and(|c| regex.fold(c) == *expected),
+                    Inst::Any => c.is_some_and(|c| c != '\n' && c != '\r'),
+                    Inst::Class(idx) => c.is_some_and(|c| regex.class_matches(*idx, c)),
+                    Inst::Match => {
+                        // All remaining threads have a lower priority than this match.
+                        matched = Some(Captures { slots: self.clist.caps(i).to_vec() });
+                        break;
+                    }
+                    _ => false,
+                };
+                if step && let Some((_, len)) = next {
+                    self.caps.copy_from_slice(self.clist.caps(i));
+                    let next_pos = pos + len;
+                    regex.add_thread(&mut self.nlist, &mut self.stack, &mut self.caps, text, next_pos, pc + 1);
+                }
+            }
+
+            let Some((_, len)) = next else {
+                break;
+            };
+            pos += len;
+            mem::swap(&mut self.clist, &mut self.nlist);
+            self.nlist.clear();
+        }
+
+        matched
+    }
+}
+
+/// The work items of [`Regex::add_thread`].
+enum Frame {
+    /// Follow the instruction at the given index.
+    Explore(usize),
+    /// Put the given value back into the capture slot, after a [`Inst::Save`] was explored.
+    Restore(usize, Option<usize>),
+}
+
+/// The instructions of the Pike VM.
+enum Inst {
+    /// Matches the given character. Already case-folded, if the regex is case-insensitive.
+    Char(char),
+    /// Matches any character except line breaks.
+    Any,
+    /// Matches a character of the class with the given index.
+    Class(usize),
+    /// Continues at both targets. The first one has the higher priority.
+    Split(usize, usize),
+    Jmp(usize),
+    /// Stores the current position in the given capture slot.
+    Save(usize),
+    Assert(Assertion),
+    Match,
+}
+
+#[derive(Clone, Copy)]
+enum Assertion {
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Assertion {
+    fn holds<D: ReadableDocument + ?Sized>(self, text: &D, pos: usize) -> bool {
+        let prev = char_before(text, pos);
+        let next = char_at(text, pos).map(|(c, _)| c);
+        match self {
+            Self::LineStart => prev.is_none_or(|c| c == '\n'),
+            Self::LineEnd => next.is_none_or(|c| c == '\n' || c == '\r'),
+            Self::WordBoundary => prev.is_some_and(is_word_char) != next.is_some_and(is_word_char),
+            Self::NotWordBoundary => prev.is_some_and(is_word_char) == next.is_some_and(is_word_char),
+        }
+    }
+}
+
+/// The shorthand classes `\d`, `\w` and `\s`.
+#[derive(Clone, Copy)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Self::Digit => c.is_ascii_digit(),
+            Self::Word => is_word_char(c),
+            Self::Space => c.is_whitespace(),
+        }
+    }
+}
+
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+}
+
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let found = self.items.iter().any(|item| match *item {
+            ClassItem::Range(beg, end) => (beg..=end).contains(&c),
+            ClassItem::Perl(perl, negated) => perl.matches(c) != negated,
+        });
+        found != self.negated
+    }
+}
+
+/// The parsed pattern.
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(usize),
+    Assert(Assertion),
+    /// A group and its capture index, if it's a capturing one.
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat { node: Box<Node>, min: u32, max: Option<u32>, greedy: bool },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// The number of capture groups so far, including group 0.
+    groups: usize,
+    classes: Vec<Class>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> apperr::Result<char> {
+        let c = self.peek().ok_or(apperr::APP_REGEX_INVALID)?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        self.pos += found as usize;
+        found
+    }
+
+    fn parse_alternation(&mut self) -> apperr::Result<Node> {
+        let mut alternatives = vec![self.parse_concat()?];
+        while self.eat('|') {
+            alternatives.push(self.parse_concat()?);
+        }
+        Ok(if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Node::Alternate(alternatives) })
+    }
+
+    fn parse_concat(&mut self) -> apperr::Result<Node> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek()
+            && c != '|'
+            && c != ')'
+        {
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_repeat(atom)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn parse_atom(&mut self) -> apperr::Result<Node> {
+        if self.peek() == Some('{') && self.parse_counts().is_some() {
+            // A quantifier without anything to repeat.
+            return Err(apperr::APP_REGEX_INVALID);
+        }
+
+        Ok(match self.next()? {
+            '.' => Node::Any,
+            '^' => Node::Assert(Assertion::LineStart),
+            '$' => Node::Assert(Assertion::LineEnd),
+            '[' => self.parse_class()?,
+            '(' => {
+                let index = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err(apperr::APP_REGEX_INVALID);
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups - 1)
+                };
+                let node = self.parse_alternation()?;
+                if !self.eat(')') {
+                    return Err(apperr::APP_REGEX_INVALID);
+                }
+                Node::Group(Box::new(node), index)
+            }
+            '\\' => match self.next()? {
+                'b' => Node::Assert(Assertion::WordBoundary),
+                'B' => Node::Assert(Assertion::NotWordBoundary),
+                c => match self.parse_escape(c)? {
+                    ClassItem::Range(c, _) => Node::Char(c),
+                    item => Node::Class(self.add_class(Class { items: vec![item], negated: false })),
+                },
+            },
+            '*' | '+' | '?' => return Err(apperr::APP_REGEX_INVALID),
+            c => Node::Char(c),
+        })
+    }
+
+    /// Parses the escape sequence after a `\`, whose first character `c` was already consumed.
+    fn parse_escape(&mut self, c: char) -> apperr::Result<ClassItem> {
+        let c = match c {
+            'd' => return Ok(ClassItem::Perl(Perl::Digit, false)),
+            'D' => return Ok(ClassItem::Perl(Perl::Digit, true)),
+            'w' => return Ok(ClassItem::Perl(Perl::Word, false)),
+            'W' => return Ok(ClassItem::Perl(Perl::Word, true)),
+            's' => return Ok(ClassItem::Perl(Perl::Space, false)),
+            'S' => return Ok(ClassItem::Perl(Perl::Space, true)),
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'x' => {
+                let digits: String = if self.eat('{') {
+                    let digits = self.take_while(|c| c != '}');
+                    if !self.eat('}') {
+                        return Err(apperr::APP_REGEX_INVALID);
+                    }
+                    digits
+                } else {
+                    let end = (self.pos + 2).min(self.chars.len());
+                    let digits = self.chars[self.pos..end].iter().collect();
+                    self.pos = end;
+                    digits
+                };
+                u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32).ok_or(apperr::APP_REGEX_INVALID)?
+            }
+            // Letters and digits are reserved for escapes that may get a meaning in the future.
+            c if c.is_ascii_alphanumeric() => return Err(apperr::APP_REGEX_INVALID),
+            c => c,
+        };
+        Ok(ClassItem::Range(c, c))
+    }
+
+    fn parse_class(&mut self) -> apperr::Result<Node> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+
+        // A `]` right at the start is taken literally.
+        let mut first = true;
+        loop {
+            let c = self.next()?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let item = if c == '\\' {
+                let c = self.next()?;
+                self.parse_escape(c)?
+            } else {
+                ClassItem::Range(c, c)
+            };
+
+            match item {
+                ClassItem::Range(beg, _) if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') => {
+                    self.pos += 1;
+                    let end = match self.next()? {
+                        '\\' => {
+                            let c = self.next()?;
+                            match self.parse_escape(c)? {
+                                ClassItem::Range(end, _) => end,
+                                ClassItem::Perl(..) => return Err(apperr::APP_REGEX_INVALID),
+                            }
+                        }
+                        c => c,
+                    };
+                    if end < beg {
+                        return Err(apperr::APP_REGEX_INVALID);
+                    }
+                    items.push(ClassItem::Range(beg, end));
+                }
+                item => items.push(item),
+            }
+        }
+
+        Ok(Node::Class(self.add_class(Class { items, negated })))
+    }
+
+    /// Parses the quantifier after `node`, if any. Quantifiers can't be stacked,
+    /// so a second one is left for [`Parser::parse_atom`] to reject.
+    fn parse_repeat(&mut self, node: Node) -> apperr::Result<Node> {
+        let (min, max) = match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                (0, None)
+            }
+            Some('+') => {
+                self.pos += 1;
+                (1, None)
+            }
+            Some('?') => {
+                self.pos += 1;
+                (0, Some(1))
+            }
+            Some('{') => match self.parse_counts() {
+                Some(counts) => counts?,
+                None => return Ok(node),
+            },
+            _ => return Ok(node),
+        };
+
+        if matches!(node, Node::Assert(_)) {
+            return Err(apperr::APP_REGEX_INVALID);
+        }
+        let greedy = !self.eat('?');
+        Ok(Node::Repeat { node: Box::new(node), min, max, greedy })
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}` at the current position. Returns `None` and consumes nothing
+    /// if there's no such thing, in which case the `{` is a literal character.
+    fn parse_counts(&mut self) -> Option<apperr::Result<(u32, Option<u32>)>> {
+        let start = self.pos;
+        self.pos += 1;
+
+        let min = self.take_while(|c| c.is_ascii_digit());
+        let max = if self.eat(',') { Some(self.take_while(|c| c.is_ascii_digit())) } else { None };
+        if min.is_empty() || !self.eat('}') {
+            self.pos = start;
+            return None;
+        }
+
+        let parse = |s: &str| s.parse::<u32>().ok().filter(|&n| n <= MAX_REPEAT);
+        let counts = match (parse(&min), max) {
+            (Some(min), None) => Ok((min, Some(min))),
+            (Some(min), Some(max)) if max.is_empty() => Ok((min, None)),
+            (Some(min), Some(max)) => match parse(&max) {
+                Some(max) if max >= min => Ok((min, Some(max))),
+                _ => Err(apperr::APP_REGEX_INVALID),
+            },
+            (None, _) => Err(apperr::APP_REGEX_INVALID),
+        };
+        Some(counts)
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+        let beg = self.pos;
+        while self.peek().is_some_and(&pred) {
+            self.pos += 1;
+        }
+        self.chars[beg..self.pos].iter().collect()
+    }
+
+    fn add_class(&mut self, class: Class) -> usize {
+        self.classes.push(class);
+        self.classes.len() - 1
+    }
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => {
+                let c = if self.case_insensitive { fold_case(*c) } else { *c };
+                self.push(Inst::Char(c));
+            }
+            Node::Any => _ = self.push(Inst::Any),
+            Node::Class(idx) => _ = self.push(Inst::Class(*idx)),
+            Node::Assert(assertion) => _ = self.push(Inst::Assert(*assertion)),
+            Node::Group(node, index) => match index {
+                Some(index) => {
+                    self.push(Inst::Save(index * 2));
+                    self.compile(node);
+                    self.push(Inst::Save(index * 2 + 1));
+                }
+                None => self.compile(node),
+            },
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node);
+                }
+            }
+            Node::Alternate(nodes) => {
+                let mut jumps = Vec::new();
+                for (i, node) in nodes.iter().enumerate() {
+                    if i + 1 == nodes.len() {
+                        self.compile(node);
+                        break;
+                    }
+                    let split = self.push(Inst::Split(0, 0));
+                    self.compile(node);
+                    jumps.push(self.push(Inst::Jmp(0)));
+                    let next = self.program.len();
+                    self.program[split] = Inst::Split(split + 1, next);
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+
+                match max {
+                    None => {
+                        // L1: split L2, L3; L2: node; jmp L1; L3:
+                        let split = self.push(Inst::Split(0, 0));
+                        self.compile(node);
+                        self.push(Inst::Jmp(split));
+                        let end = self.program.len();
+                        self.program[split] = self.split(split + 1, end, *greedy);
+                    }
+                    Some(max) => {
+                        // Each optional repetition may skip all of the remaining ones.
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.program[split] = self.split(split + 1, end, *greedy);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn split(&self, body: usize, skip: usize, greedy: bool) -> Inst {
+        if greedy { Inst::Split(body, skip) } else { Inst::Split(skip, body) }
+    }
+}
+
+/// A list of threads, one per instruction at most, in priority order.
+/// The sparse set makes clearing and membership tests O(1).
+struct Threads {
+    sparse: Vec<usize>,
+    dense: Vec<usize>,
+    /// The capture slots of each thread in `dense`, back to back.
+    caps: Vec<Option<usize>>,
+    slots: usize,
+}
+
+impl Threads {
+    fn new(len: usize, slots: usize) -> Self {
+        let caps = Vec::with_capacity(len * slots);
+        Self { sparse: vec![0; len], dense: Vec::with_capacity(len), caps, slots }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+        self.caps.clear();
+    }
+
+    /// Marks `pc` as visited. Returns `false` if it already was.
+    fn insert(&mut self, pc: usize) -> bool {
+        let i = self.sparse[pc];
+        if i < self.dense.len() && self.dense[i] == pc {
+            return false;
+        }
+        self.sparse[pc] = self.dense.len();
+        self.dense.push(pc);
+        // Placeholder until the thread is pushed. Jumps never get one.
+        self.caps.extend(std::iter::repeat_n(None, self.slots));
+        true
+    }
+
+    /// Stores the captures of the thread at `pc`, which must have been inserted already.
+    fn push(&mut self, pc: usize, caps: &[Option<usize>]) {
+        let i = self.sparse[pc] * self.slots;
+        self.caps[i..i + self.slots].copy_from_slice(caps);
+    }
+
+    fn caps(&self, i: usize) -> &[Option<usize>] {
+        &self.caps[i * self.slots..(i + 1) * self.slots]
+    }
+}
+
+/// Returns `true` for characters that make up words, as in `\w`.
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn fold_case(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        // Characters like 'İ' lowercase to multiple characters. Leave them alone.
+        _ => c,
+    }
+}
+
+/// Decodes the character at `pos`. Returns it and its length in bytes.
+pub fn char_at<D: ReadableDocument + ?Sized>(text: &D, pos: usize) -> Option<(char, usize)> {
+    let chunk = text.read_forward(pos);
+    if chunk.is_empty() {
+        return None;
+    }
+    if chunk.len() >= 4 || chunk[0] < 0x80 {
+        return decode(chunk);
+    }
+
+    // The character may continue in the next chunk.
+    let mut buf = [0; 4];
+    let mut len = 0;
+    while len < buf.len() {
+        let chunk = text.read_forward(pos + len);
+        if chunk.is_empty() {
+            break;
+        }
+        let n = chunk.len().min(buf.len() - len);
+        buf[len..len + n].copy_from_slice(&chunk[..n]);
+        len += n;
+    }
+    decode(&buf[..len])
+}
+
+/// Decodes the character that ends at `pos`.
+pub fn char_before<D: ReadableDocument + ?Sized>(text: &D, pos: usize) -> Option<char> {
+    // The (at most) 4 bytes before `pos`, which may be spread over multiple chunks.
+    let mut buf = [0; 4];
+    let mut beg = buf.len();
+    while beg > 0 {
+        let chunk = text.read_backward(pos - (buf.len() - beg));
+        if chunk.is_empty() {
+            break;
+        }
+        let n = chunk.len().min(beg);
+        buf[beg - n..beg].copy_from_slice(&chunk[chunk.len() - n..]);
+        beg -= n;
+    }
+
+    let bytes = &buf[beg..];
+    if bytes.is_empty() {
+        return None;
+    }
+
+    // Step back over at most 3 continuation bytes to the lead byte.
+    let lead = bytes.iter().rposition(|&b| b & 0b1100_0000 != 0b1000_0000).unwrap_or(0);
+    match decode(&bytes[lead..]) {
+        Some((c, len)) if lead + len == bytes.len() => Some(c),
+        // The lead byte didn't belong to the last character after all.
+        _ => Some(char::REPLACEMENT_CHARACTER),
+    }
+}
+
+fn decode(bytes: &[u8]) -> Option<(char, usize)> {
+    let mut it = Utf8Chars::new(bytes, 0);
+    let c = it.next()?;
+    Some((c, it.offset()))
+}
+
+/// Appends the bytes of `text` in `range` to `out`.
+fn extract<D: ReadableDocument + ?Sized>(text: &D, range: Range<usize>, out: &mut Vec<u8>) {
+    let mut pos = range.start;
+    while pos < range.end {
+        let chunk = text.read_forward(pos);
+        if chunk.is_empty() {
+            break;
+        }
+        let n = chunk.len().min(range.end - pos);
+        out.extend_from_slice(&chunk[..n]);
+        pos += n;
+    }
+}
+
+/// Returns the offset of the first occurrence of `needle` in `text` at or after `start`.
+fn find_bytes<D: ReadableDocument + ?Sized>(text: &D, start: usize, needle: &[u8]) -> Option<usize> {
+    let mut pos = start;
+    loop {
+        let chunk = text.read_forward(pos);
+        if chunk.is_empty() {
+            return None;
+        }
+        let Some(off) = chunk.iter().position(|&b| b == needle[0]) else {
+            pos += chunk.len();
+            continue;
+        };
+        pos += off;
+        if starts_with(text, pos, needle) {
+            return Some(pos);
+        }
+        pos += 1;
+    }
+}
+
+/// Returns `true` if the text at `pos` is `needle`, which may straddle chunks.
+fn starts_with<D: ReadableDocument + ?Sized>(text: &D, mut pos: usize, mut needle: &[u8]) -> bool {
+    while !needle.is_empty() {
+        let chunk = text.read_forward(pos);
+        let n = chunk.len().min(needle.len());
+        if n == 0 || chunk[..n] != needle[..n] {
+            return false;
+        }
+        needle = &needle[n..];
+        pos += n;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_all(pattern: &str, text: &str) -> Vec<String> {
+        find_all_with(pattern, text, false)
+    }
+
+    fn find_all_with(pattern: &str, text: &str, case_insensitive: bool) -> Vec<String> {
+        let regex = Regex::new(pattern, case_insensitive).unwrap();
+        regex.find_all(&text.as_bytes()).iter().map(|caps| text[caps.range()].to_string()).collect()
+    }
+
+    /// A text that is split into chunks of `.1` bytes, like a gap buffer, but with many gaps.
+    struct Chunked<'a>(&'a [u8], usize);
+
+    impl ReadableDocument for Chunked<'_> {
+        fn read_forward(&self, off: usize) -> &[u8] {
+            let off = off.min(self.0.len());
+            let end = (off / self.1 + 1) * self.1;
+            &self.0[off..end.min(self.0.len())]
+        }
+
+        fn read_backward(&self, off: usize) -> &[u8] {
+            let off = off.min(self.0.len());
+            let beg = off.saturating_sub(1) / self.1 * self.1;
+            &self.0[beg..off]
+        }
+    }
+
+    #[test]
+    fn test_literals_and_classes() {
+        assert_eq!(find_all("ab", "xabyab"), ["ab", "ab"]);
+        assert_eq!(find_all("a.c", "abc a\nc"), ["abc"]);
+        assert_eq!(find_all("[a-c]+", "xaabcyd"), ["aabc"]);
+        assert_eq!(find_all("[^a-c ]+", "ab de cf"), ["de", "f"]);
+        assert_eq!(find_all(r"\d+", "a12b345"), ["12", "345"]);
+        assert_eq!(find_all(r"[\d_]+", "a1_2 b"), ["1_2"]);
+        assert_eq!(find_all(r"\w+", "foo, bär_1!"), ["foo", "bär_1"]);
+        assert_eq!(find_all(r"a\.b", "a.b axb"), ["a.b"]);
+        assert_eq!(find_all(r"\x41\x{1F600}", "A😀"), ["A😀"]);
+        assert_eq!(find_all("[]a]", "]a"), ["]", "a"]);
+        assert_eq!(find_all("x{", "x{"), ["x{"]);
+    }
+
+    #[test]
+    fn test_repetition_and_alternation() {
+        assert_eq!(find_all("a*", "aab"), ["aa", "", ""]);
+        assert_eq!(find_all("a+?", "aa"), ["a", "a"]);
+        assert_eq!(find_all("<.*>", "<a><b>"), ["<a><b>"]);
+        assert_eq!(find_all("<.*?>", "<a><b>"), ["<a>", "<b>"]);
+        assert_eq!(find_all("a{2}", "aaaaa"), ["aa", "aa"]);
+        assert_eq!(find_all("a{2,3}", "aaaaa"), ["aaa", "aa"]);
+        assert_eq!(find_all("a{2,}", "a aaaa"), ["aaaa"]);
+        assert_eq!(find_all("cat|category", "category"), ["cat"]);
+        assert_eq!(find_all("(?:ab)+|c", "ababc"), ["abab", "c"]);
+        assert_eq!(find_all("(a*)*b", "aaab"), ["aaab"]);
+    }
+
+    #[test]
+    fn test_assertions() {
+        assert_eq!(find_all("^a", "ab\na\r\nba"), ["a", "a"]);
+        assert_eq!(find_all("a$", "ba\r\nab\na"), ["a", "a"]);
+        assert_eq!(find_all(r"\bfoo\b", "foo foobar barfoo foo"), ["foo", "foo"]);
+        assert_eq!(find_all(r"\Boo", "foo oo"), ["oo"]);
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        assert_eq!(find_all_with("straSSe", "STRASSE strasse", true), ["STRASSE", "strasse"]);
+        assert_eq!(find_all_with("[a-c]+", "xAbC", true), ["AbC"]);
+        assert_eq!(find_all_with("Ä", "äÄ", true), ["ä", "Ä"]);
+    }
+
+    #[test]
+    fn test_captures_and_expand() {
+        let regex = Regex::new(r"(\w+)=(\d+)?", false).unwrap();
+        let text: &[u8] = b"key=42 other=";
+        let all = regex.find_all(&text);
+        assert_eq!(regex.groups(), 3);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].get(1), Some(0..3));
+        assert_eq!(all[0].get(2), Some(4..6));
+        assert_eq!(all[1].get(2), None);
+
+        let mut out = Vec::new();
+        Regex::expand(&all[0], &text, "$2:${1}$$ $9 $x", &mut out);
+        assert_eq!(out, b"42:key$  $x");
+    }
+
+    #[test]
+    fn test_invalid() {
+        for pattern in ["(", "a)", "[a", "*", "a**", r"\q", "a{3,2}", "(?a)", "[z-a]", "a{1001}"] {
+            assert!(Regex::new(pattern, false).is_err(), "{pattern:?}");
+        }
+        assert_eq!(Regex::escape("a.b*(c)"), r"a\.b\*\(c\)");
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let regex = Regex::new("a.b", false).unwrap();
+        let text: &[u8] = b"a\xffb a\xe2\x82\xffb";
+        let all = regex.find_all(&text);
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].range(), 0..3);
+    }
+
+    #[test]
+    fn test_chunked() {
+        let text = ["Grüße,\r\nwörld 😀 ".as_bytes(), b"\xff", " ab\nÄb".as_bytes()].concat();
+        let cases = [r"\w+", r"\bw\S+", "ö|😀", r"^.", r".$", "ab", r"(?:ü|\x{1F600})\W", "[^a-z]+"];
+        for pattern in cases {
+            let regex = Regex::new(pattern, true).unwrap();
+            let expected: Vec<_> = regex.find_all(&&text[..]).iter().map(Captures::range).collect();
+            assert!(!expected.is_empty(), "{pattern:?}");
+            for size in 1..=5 {
+                let actual: Vec<_> =
+                    regex.find_all(&Chunked(&text, size)).iter().map(Captures::range).collect();
+                assert_eq!(actual, expected, "{pattern:?} in chunks of {size}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_matcher_range() {
+        let regex = Regex::new("ab+", false).unwrap();
+        let mut matcher = regex.matcher();
+        let text: &[u8] = b"xabbb ab";
+        assert_eq!(matcher.find(&text, 0..2).map(|c| c.range()), Some(1..5));
+        assert_eq!(matcher.find(&text, 2..6).map(|c| c.range()), None);
+        assert_eq!(matcher.find(&text, 2..7).map(|c| c.range()), Some(6..8));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::helpers::CoordType;
+use std::ptr;
+
+/// Starting from the `offset` in the `haystack` this will seek forward,
+/// counting `\n` characters, until `line_stop` is reached.
+///
+/// `line` is the line number at `offset`. If `line` reaches `line_stop`, this
+/// returns the offset right after the `\n` that got it there, i.e. the start
+/// of line `line_stop`. Otherwise it returns `haystack.len()` and the number
+/// of the line the `haystack` ends in. Pass [`CoordType::MAX`] to simply count lines.
+pub fn lines_fwd(
+    haystack: &[u8],
+    offset: usize,
+    line: CoordType,
+    line_stop: CoordType,
+) -> (usize, CoordType) {
+    unsafe {
+        let beg = haystack.as_ptr();
+        let end = beg.add(haystack.len());
+        let it = beg.add(offset.min(haystack.len()));
+        let (it, line) = lines_fwd_raw(it, end, line, line_stop);
+        (it.offset_from_unsigned(beg), line)
+    }
+}
+
+unsafe fn lines_fwd_raw(
+    beg: *const u8,
+    end: *const u8,
+    line: CoordType,
+    line_stop: CoordType,
+) -> (*const u8, CoordType) {
+    #[cfg(target_arch = "aarch64")]
+    return unsafe { lines_fwd_neon(beg, end, line, line_stop) };
+
+    #[cfg(target_arch = "x86_64")]
+    return unsafe { lines_fwd_sse2(beg, end, line, line_stop) };
+
+    #[allow(unreachable_code)]
+    return unsafe { lines_fwd_fallback(beg, end, line, line_stop) };
+}
+
+#[cfg(target_arch = "aarch64")]
+unsafe fn lines_fwd_neon(
+    mut beg: *const u8,
+    end: *const u8,
+    mut line: CoordType,
+    line_stop: CoordType,
+) -> (*const u8, CoordType) {
+    unsafe {
+        use std::arch::aarch64::*;
+
+        if line < line_stop && end.offset_from_unsigned(beg) >= 16 {
+            let n = vdupq_n_u8(b'\n');
+            let one = vdupq_n_u8(1);
+
+            loop {
+                let v = vld1q_u8(beg as *const _);
+                let c = vandq_u8(vceqq_u8(v, n), one);
+                let count = vaddvq_u8(c) as CoordType;
+
+                // If this block would reach `line_stop`, let the fallback find the exact spot.
+                if count >= line_stop - line {
+                    break;
+                }
+
+                line += count;
+                beg = beg.add(16);
+                if end.offset_from_unsigned(beg) < 16 {
+                    break;
+                }
+            }
+        }
+
+        lines_fwd_fallback(beg, end, line, line_stop)
+    }
+}
+
+// SSE2 is part of the x86-64 baseline, so there's no need for runtime dispatch.
+#[cfg(target_arch = "x86_64")]
+unsafe fn lines_fwd_sse2(
+    mut beg: *const u8,
+    end: *const u8,
+    mut line: CoordType,
+    line_stop: CoordType,
+) -> (*const u8, CoordType) {
+    unsafe {
+        use std::arch::x86_64::*;
+
+        if line < line_stop && end.offset_from_unsigned(beg) >= 16 {
+            let n = _mm_set1_epi8(b'\n' as i8);
+
+            loop {
+                let v = _mm_loadu_si128(beg as *const _);
+                let m = _mm_movemask_epi8(_mm_cmpeq_epi8(v, n));
+                let count = m.count_ones() as CoordType;
+
+                // If this block would reach `line_stop`, let the fallback find the exact spot.
+                if count >= line_stop - line {
+                    break;
+                }
+
+                line += count;
+                beg = beg.add(16);
+                if end.offset_from_unsigned(beg) < 16 {
+                    break;
+                }
+            }
+        }
+
+        lines_fwd_fallback(beg, end, line, line_stop)
+    }
+}
+
+unsafe fn lines_fwd_fallback(
+    mut beg: *const u8,
+    end: *const u8,
+    mut line: CoordType,
+    line_stop: CoordType,
+) -> (*const u8, CoordType) {
+    unsafe {
+        while line < line_stop && !ptr::eq(beg, end) {
+            let ch = *beg;
+            beg = beg.add(1);
+            if ch == b'\n' {
+                line += 1;
+            }
+        }
+        (beg, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The straightforward implementation that the SIMD versions must agree with.
+    fn reference(
+        haystack: &[u8],
+        offset: usize,
+        mut line: CoordType,
+        line_stop: CoordType,
+    ) -> (usize, CoordType) {
+        let mut off = offset.min(haystack.len());
+        while line < line_stop && off < haystack.len() {
+            if haystack[off] == b'\n' {
+                line += 1;
+            }
+            off += 1;
+        }
+        (off, line)
+    }
+
+    #[test]
+    fn test_lines_fwd() {
+        // Newlines in varying density, from none to every byte, so that blocks
+        // hold anywhere from 0 to 16 of them. xorshift keeps it reproducible.
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut haystack = Vec::new();
+        for density in [0, 1, 4, 16, 64, 256] {
+            for _ in 0..300 {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                haystack.push(if (state % 256) < density { b'\n' } else { b'a' });
+            }
+        }
+
+        let newlines = haystack.iter().filter(|&&b| b == b'\n').count() as CoordType;
+        for offset in [0, 1, 15, 16, 17, 333, 1000, haystack.len() - 5, haystack.len(), haystack.len() + 1] {
+            for line_stop in [0, 1, 2, 15, 16, 17, 100, newlines, CoordType::MAX] {
+                for line in [0, 3] {
+                    assert_eq!(
+                        lines_fwd(&haystack, offset, line, line_stop),
+                        reference(&haystack, offset, line, line_stop),
+                        "offset={offset} line={line} line_stop={line_stop}"
+                    );
+                }
+            }
+        }
+    }
+}
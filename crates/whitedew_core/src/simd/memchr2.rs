@@ -17,16 +17,17 @@ pub fn memchr2(needle1: u8, needle2: u8, haystack: &[u8], offset: usize) -> usiz
 }
 
 unsafe fn memchr2_raw(needle1: u8, needle2: u8, beg: *const u8, end: *const u8) -> *const u8 {
-    // target_arch가 "aarch64"가 아니면 다른 코드 필요
+    #[cfg(target_arch = "aarch64")]
     return unsafe { memchr2_neon(needle1, needle2, beg, end) };
 
-    // #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "loongarch64"))]
-    // return unsafe { MEMCHR2_DISPATCH(needle1, needle2, beg, end) };
+    #[cfg(target_arch = "x86_64")]
+    return unsafe { memchr2_sse2(needle1, needle2, beg, end) };
 
-    // #[allow(unreachable_code)]
-    // return unsafe { memchr2_fallback(needle1, needle2, beg, end) };
+    #[allow(unreachable_code)]
+    return unsafe { memchr2_fallback(needle1, needle2, beg, end) };
 }
 
+#[cfg(target_arch = "aarch64")]
 unsafe fn memchr2_neon(needle1: u8, needle2: u8, mut beg: *const u8, end: *const u8) -> *const u8 {
     unsafe {
         use std::arch::aarch64::*;
@@ -62,6 +63,38 @@ unsafe fn memchr2_neon(needle1: u8, needle2: u8, mut beg: *const u8, end: *const
     }
 }
 
+// SSE2 is part of the x86-64 baseline, so there's no need for runtime dispatch.
+#[cfg(target_arch = "x86_64")]
+unsafe fn memchr2_sse2(needle1: u8, needle2: u8, mut beg: *const u8, end: *const u8) -> *const u8 {
+    unsafe {
+        use std::arch::x86_64::*;
+
+        if end.offset_from_unsigned(beg) >= 16 {
+            let n1 = _mm_set1_epi8(needle1 as i8);
+            let n2 = _mm_set1_epi8(needle2 as i8);
+
+            loop {
+                let v = _mm_loadu_si128(beg as *const _);
+                let a = _mm_cmpeq_epi8(v, n1);
+                let b = _mm_cmpeq_epi8(v, n2);
+                let c = _mm_or_si128(a, b);
+                let m = _mm_movemask_epi8(c);
+
+                if m != 0 {
+                    return beg.add(m.trailing_zeros() as usize);
+                }
+
+                beg = beg.add(16);
+                if end.offset_from_unsigned(beg) < 16 {
+                    break;
+                }
+            }
+        }
+
+        memchr2_fallback(needle1, needle2, beg, end)
+    }
+}
+
 unsafe fn memchr2_fallback(
     needle1: u8,
     needle2: u8,
@@ -1,4 +1,6 @@
 
+mod lines_fwd;
 mod memchr2;
 
+pub use lines_fwd::*;
 pub use memchr2::*;
@@ -4,11 +4,14 @@ use crate::{
     helpers::{GIBI, KIBI},
 };
 use std::{
-    ffi::c_int,
-    fs::File,
+    ffi::{CStr, c_int, c_void},
+    fs::{self, File},
     mem::{self, ManuallyDrop, MaybeUninit},
-    os::fd::FromRawFd as _,
-    ptr::null_mut,
+    os::{
+        fd::FromRawFd as _,
+        unix::fs::{MetadataExt as _, PermissionsExt as _, fchown},
+    },
+    ptr::{NonNull, null_mut},
     thread,
     time,
 };
@@ -338,11 +341,13 @@ pub fn switch_modes() -> apperr::Result<()> {
     }
 }
 
-/// switch_modes() 보다 먼저 호출되기 때문에 항상 None이 리턴되는 것으로 보임
+/// Returns stdin as a [`File`] if it's redirected (e.g. `cat foo | wd`).
+///
+/// This gets called before `switch_modes()` reopens stdin as `/dev/tty`,
+/// so we can't look at `STATE.stdin` and have to ask the fd directly.
 pub fn open_stdin_if_redirected() -> Option<File> {
     unsafe {
-        // Did we reopen stdin during `init()`?
-        if STATE.stdin != libc::STDIN_FILENO {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
             Some(File::from_raw_fd(libc::STDIN_FILENO))
         } else {
             None
@@ -375,12 +380,57 @@ fn get_window_size() -> (u16, u16) {
     (winsz.ws_col, winsz.ws_row)
 }
 
+/// Copies the permissions and, if possible, the ownership of `src` over to `dst`.
+///
+/// Only root may give a file away to another user and only members of a group
+/// may hand a file to that group. As such, failing to change the ownership
+/// is not an error. The result is as close to the original as we're allowed to get.
+pub fn copy_file_metadata(dst: &File, src: &fs::Metadata) -> apperr::Result<()> {
+    // Change the ownership first, because chown() may clear the setuid/setgid bits.
+    if (src.uid() != unsafe { libc::geteuid() } || src.gid() != unsafe { libc::getegid() })
+        && fchown(dst, Some(src.uid()), Some(src.gid())).is_err()
+    {
+        _ = fchown(dst, None, Some(src.gid()));
+    }
+
+    dst.set_permissions(fs::Permissions::from_mode(src.mode()))?;
+    Ok(())
+}
+
 pub fn apperr_format(f: &mut std::fmt::Formatter<'_>, code: u32) -> std::fmt::Result {
     write!(f, "Error {code}")?;
-    
+
+    // strerror() returns a static, human-readable description of the errno.
+    unsafe {
+        let ptr = libc::strerror(code as c_int);
+        if !ptr.is_null() {
+            let msg = CStr::from_ptr(ptr).to_string_lossy();
+            write!(f, ": {msg}")?;
+        }
+    }
+
     Ok(())
 }
 
+/// Loads the shared library `name`. Returns `None` if it isn't installed.
+///
+/// The library stays loaded for the rest of the process' lifetime.
+pub fn load_library(name: &CStr) -> Option<NonNull<c_void>> {
+    NonNull::new(unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_LAZY) })
+}
+
+/// Looks up the function `symbol` in a library returned by [`load_library`].
+///
+/// # Safety
+///
+/// `T` must be a function pointer type that matches the function's signature.
+pub unsafe fn get_proc_address<T>(handle: NonNull<c_void>, symbol: &CStr) -> Option<T> {
+    unsafe {
+        let ptr = libc::dlsym(handle.as_ptr(), symbol.as_ptr());
+        if ptr.is_null() { None } else { Some(mem::transmute_copy(&ptr)) }
+    }
+}
+
 pub fn preferred_languages(arena: &Arena) -> Vec<ArenaString<'_>, &Arena> {
     let mut locales = Vec::new_in(arena);
 
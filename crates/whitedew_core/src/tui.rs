@@ -1,18 +1,71 @@
 
 use crate::{
     apperr,
+    buffer::{RcTextBuffer, TextBuffer, VisualRow},
     clipboard::Clipboard,
-    framebuffer::{Framebuffer, IndexedColor, INDEXED_COLORS_COUNT},
-    helpers::Size,
-    input,
+    framebuffer::{Attributes, Framebuffer, IndexedColor, INDEXED_COLORS_COUNT},
+    hash::{INITIAL_HASH, hash_str, hash_u64},
+    helpers::{CoordType, Point, Rect, Size},
+    input::{self, kbmod, vk},
     oklab::StraightRgba,
+    unicode::{Cursor, MeasurementConfig, char_width},
 };
-use std::{mem, time};
+use std::{collections::HashMap, fmt::Write as _, mem, ops::Range, time};
 use stdext::arena::{Arena, ArenaString};
 
 type Input<'input> = input::Input<'input>;
 type InputKey = input::InputKey;
 type InputMouseState = input::InputMouseState;
+type InputKeyMod = input::InputKeyMod;
+
+/// The ID of the root node. Every other ID is derived from it.
+const ROOT_ID: u64 = INITIAL_HASH;
+
+/// How many extra passes [`Tui::needs_settling`] allows per input event.
+/// Guards against widgets that keep moving the focus back and forth.
+const SETTLING_MAX: i32 = 20;
+
+/// How quickly clicks at the same spot must follow each other to count as a double or triple click.
+const MULTI_CLICK_TIME: time::Duration = time::Duration::from_millis(500);
+
+/// How the children of a container are arranged.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Top to bottom.
+    #[default]
+    Column,
+    /// Left to right.
+    Row,
+    /// Left to right, wrapping into a new row after the given number of columns.
+    /// Columns are as wide as their widest cell and rows as high as their highest cell.
+    Grid(CoordType),
+}
+
+/// What a floating node is positioned relative to. See [`FloatSpec`].
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Anchor {
+    /// The node the floater was declared in.
+    #[default]
+    Parent,
+    /// The entire viewport.
+    Root,
+}
+
+/// Describes the position of a floating node, like a dropdown or a dialog.
+///
+/// Floating nodes don't take part in the layout of their parent.
+/// They are sized to their contents and drawn on top of everything else.
+#[derive(Clone, Copy, Default)]
+pub struct FloatSpec {
+    pub anchor: Anchor,
+    /// The point of the floater that gets placed at the offset,
+    /// from 0.0 (left/top edge) to 1.0 (right/bottom edge).
+    pub gravity_x: f32,
+    pub gravity_y: f32,
+    /// The position relative to the top left corner of the anchor.
+    pub offset_x: f32,
+    pub offset_y: f32,
+}
 
 /// In order for the TUI to show the correct Ctrl/Alt/Shift
 /// translations, this struct lets you set them.
@@ -41,6 +94,36 @@ pub struct Tui {
     size: Size,
     /// The clipboard contents.
     clipboard: Clipboard,
+
+    /// The tree of the frame that is currently being built, or was built last.
+    tree: Tree,
+    /// The tree of the frame before that. Its layout is what's on the screen
+    /// while the next frame is built, so it's used for hit-testing.
+    prev_tree: Tree,
+    /// The ID of the node that has the keyboard focus. 0 if none.
+    focused_id: u64,
+    /// The last known mouse state and position.
+    mouse_state: InputMouseState,
+    mouse_position: Point,
+    /// The node under the mouse when a button was pressed.
+    mouse_down_id: u64,
+    /// The node that got clicked during this frame, i.e. that was
+    /// under the mouse both when it was pressed and released.
+    clicked_id: u64,
+    /// When and where the left button was last pressed,
+    /// and how many clicks in quick succession that made: 1, 2 or 3.
+    mouse_click_time: time::Instant,
+    mouse_click_position: Point,
+    mouse_click_count: u8,
+}
+
+/// What the left mouse button did during a frame. See [`Context::mouse_input`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MouseAction {
+    /// It was pressed. `clicks` is 2 for a double click and 3 for a triple click.
+    Press { clicks: u8, modifiers: InputKeyMod },
+    /// The mouse moved while it's held down.
+    Drag,
 }
 
 impl Tui {
@@ -61,8 +144,23 @@ impl Tui {
             settling_want: 0,
             size: Size { width: 0, height: 0 },
             clipboard: Default::default(),
+
+            tree: Default::default(),
+            prev_tree: Default::default(),
+            focused_id: 0,
+            mouse_state: InputMouseState::None,
+            mouse_position: Point::MIN,
+            mouse_down_id: 0,
+            clicked_id: 0,
+            mouse_click_time: time::Instant::now(),
+            mouse_click_position: Point::MIN,
+            mouse_click_count: 0,
         };
 
+        // Both trees always contain at least the root node.
+        tui.tree.reset(tui.size);
+        tui.prev_tree.reset(tui.size);
+
         Ok(tui)
     }
 
@@ -144,7 +242,109 @@ impl Tui {
         &'a mut self,
         input: Option<Input<'input>>,
     ) -> Context<'a, 'input> {
+        mem::swap(&mut self.tree, &mut self.prev_tree);
+        self.tree.reset(self.size);
+        self.clicked_id = 0;
+
+        // While a modal is open, only its contents receive input.
+        let modal_id = self.prev_tree.modal_id();
+
+        let mut input_text = None;
+        let mut input_keyboard = None;
+        let mut input_paste = None;
+        let mut input_scroll = None;
+        let mut input_mouse = None;
+
+        match input {
+            None => {}
+            Some(Input::Resize(size)) => {
+                self.size = Size { width: size.width.max(1), height: size.height.max(1) };
+                self.tree.reset(self.size);
+            }
+            Some(Input::Text(text)) => input_text = Some(text),
+            Some(Input::Paste(paste)) => input_paste = Some(paste),
+            Some(Input::Keyboard(key)) => input_keyboard = Some(key),
+            Some(Input::Mouse(mouse)) => {
+                let mut next_state = mouse.state;
+                let prev_state = self.mouse_state;
+                let pressed = matches!(
+                    next_state,
+                    InputMouseState::Left | InputMouseState::Middle | InputMouseState::Right
+                );
+
+                // The parser reports the release of a button as "no button".
+                if next_state == InputMouseState::None
+                    && matches!(
+                        prev_state,
+                        InputMouseState::Left | InputMouseState::Middle | InputMouseState::Right
+                    )
+                {
+                    next_state = InputMouseState::Release;
+                }
 
+                match next_state {
+                    _ if pressed && prev_state != next_state => {
+                        let (hit, focusable) = self.prev_tree.hit_test(mouse.position);
+                        if modal_id != 0 && hit != modal_id && !self.prev_tree.is_descendant(hit, modal_id) {
+                            self.mouse_down_id = 0;
+                        } else {
+                            self.mouse_down_id = hit;
+                            // Clicking somewhere unfocusable, like empty space, removes the focus.
+                            // This also closes menus, for instance.
+                            self.focused_id = if focusable { hit } else { 0 };
+                        }
+
+                        if next_state == InputMouseState::Left {
+                            let now = time::Instant::now();
+                            let repeated = mouse.position == self.mouse_click_position
+                                && now.duration_since(self.mouse_click_time) < MULTI_CLICK_TIME;
+                            self.mouse_click_count = if repeated { self.mouse_click_count % 3 + 1 } else { 1 };
+                            self.mouse_click_time = now;
+                            self.mouse_click_position = mouse.position;
+                            input_mouse = Some(MouseAction::Press {
+                                clicks: self.mouse_click_count,
+                                modifiers: mouse.modifiers,
+                            });
+                        }
+                    }
+                    InputMouseState::Left if mouse.position != self.mouse_position => {
+                        input_mouse = Some(MouseAction::Drag);
+                    }
+                    InputMouseState::Release => {
+                        let (hit, _) = self.prev_tree.hit_test(mouse.position);
+                        if hit != 0 && hit == self.mouse_down_id {
+                            self.clicked_id = hit;
+                        }
+                        self.mouse_down_id = 0;
+                    }
+                    InputMouseState::Scroll => input_scroll = Some(mouse.scroll),
+                    _ => {}
+                }
+
+                self.mouse_position = mouse.position;
+                self.mouse_state = match next_state {
+                    InputMouseState::Release | InputMouseState::Scroll => InputMouseState::None,
+                    state => state,
+                };
+            }
+        }
+
+        self.settling_have += 1;
+
+        Context {
+            tui: self,
+            input_text,
+            input_keyboard,
+            input_paste,
+            input_scroll,
+            input_mouse,
+            input_consumed: false,
+            modal_id,
+            modal_stack: Vec::new(),
+            menubar_titles: Vec::new(),
+            menubar_open: None,
+            menu_items: Vec::new(),
+        }
     }
 
     /// After you finished processing all input, continue redrawing your UI until this returns false.
@@ -152,9 +352,179 @@ impl Tui {
         self.settling_have <= self.settling_want
     }
 
+    /// Requests another pass, because the frame that was just built is stale.
+    fn needs_more_settling(&mut self) {
+        self.settling_want = (self.settling_have + 1).min(SETTLING_MAX);
+    }
+
     /// Renders the last frame into the framebuffer and returns the VT output.
     pub fn render<'a>(&mut self, arena: &'a Arena) -> ArenaString<'a> {
+        self.framebuffer.flip(self.size);
+        self.tree.render(&mut self.framebuffer, self.focused_id);
 
+        let mut output = ArenaString::new_in(arena);
+        self.framebuffer.render(&mut output);
+
+        self.settling_have = 0;
+        self.settling_want = 0;
+        output
+    }
+
+    /// Called when a [`Context`] is dropped. Finishes the frame by validating
+    /// the focus and computing the layout.
+    fn finish_frame(&mut self, unconsumed_key: Option<InputKey>) {
+        // A modal traps the focus: only its contents can be focused.
+        let modal_id = self.tree.modal_id();
+        let focusable: Vec<u64> = self
+            .tree
+            .nodes
+            .iter()
+            .filter(|n| n.attributes.focusable)
+            .map(|n| n.id)
+            .filter(|&id| modal_id == 0 || self.tree.is_descendant(id, modal_id))
+            .collect();
+
+        // If the focused node disappeared (e.g. a dialog closed), drop the focus.
+        // Widgets that want it by default pick it up via `Context::is_focus_unset`.
+        // Within a modal, the focus moves to its first focusable node instead.
+        let focus_lost = self.focused_id != 0 && !focusable.contains(&self.focused_id);
+        if focus_lost || (modal_id != 0 && self.focused_id == 0) {
+            let focus = if modal_id != 0 { focusable.first().copied().unwrap_or(0) } else { 0 };
+            if self.focused_id != focus {
+                self.focused_id = focus;
+                self.needs_more_settling();
+            }
+        }
+
+        // The input of this frame was filtered by the modal of the previous one.
+        // If it's gone now, the next frame must not be filtered anymore.
+        if modal_id != self.prev_tree.modal_id() {
+            self.needs_more_settling();
+        }
+
+        // Tab and Shift+Tab cycle through the focusable nodes, unless a widget wanted them.
+        if let Some(key) = unconsumed_key
+            && (key == vk::TAB || key == kbmod::SHIFT | vk::TAB)
+            && !focusable.is_empty()
+        {
+            let len = focusable.len();
+            let idx = focusable.iter().position(|&id| id == self.focused_id);
+            let next = match idx {
+                None => 0,
+                Some(i) if key == vk::TAB => (i + 1) % len,
+                Some(i) => (i + len - 1) % len,
+            };
+            self.focused_id = focusable[next];
+            self.needs_more_settling();
+        }
+
+        // Up and Down move the focus between the entries of a scrollarea.
+        if let Some(key) = unconsumed_key
+            && (key == vk::UP || key == vk::DOWN)
+            && let Some(area) = self.tree.scrollarea_of(self.focused_id)
+        {
+            let entries: Vec<u64> =
+                focusable.iter().copied().filter(|&id| self.tree.is_descendant(id, area)).collect();
+            if let Some(i) = entries.iter().position(|&id| id == self.focused_id) {
+                let next = if key == vk::UP { i.saturating_sub(1) } else { (i + 1).min(entries.len() - 1) };
+                self.focused_id = entries[next];
+                self.needs_more_settling();
+            }
+        }
+
+        self.tree.layout(self.size);
+        if self.tree.scroll_into_view(self.focused_id) {
+            self.tree.layout(self.size);
+        }
+
+        // Now that we know the size of the text inputs, scroll their cursors into view.
+        for node in &mut self.tree.nodes {
+            match &mut node.content {
+                NodeContent::Textarea(ta) => {
+                    let mut tb = ta.buffer.borrow_mut();
+                    let size = Size { width: node.inner.width().max(1), height: node.inner.height().max(1) };
+                    if tb.is_word_wrap_enabled() {
+                        // The extra column is for the cursor at the end of a line.
+                        tb.set_word_wrap_width(size.width - 1);
+                        ta.scroll.x = 0;
+                    }
+                    if !ta.follow_cursor {
+                        continue;
+                    }
+
+                    let cursor = tb.cursor_offset();
+                    if !tb.is_word_wrap_enabled() {
+                        let x = tb.offset_to_logical(cursor).x;
+                        ta.scroll.x = ta.scroll.x.min(x).max(x - size.width + 1).max(0);
+                    }
+
+                    let cursor = tb.visual_row_of(cursor);
+                    let mut top = tb.visual_row_move(VisualRow { line: ta.scroll.y, row: ta.scroll_row }, 0);
+                    if cursor < top {
+                        top = cursor;
+                    } else if cursor > tb.visual_row_move(top, size.height - 1) {
+                        top = tb.visual_row_move(cursor, 1 - size.height);
+                    }
+                    ta.scroll.y = top.line;
+                    ta.scroll_row = top.row;
+                }
+                NodeContent::Editline(el) => {
+                    let column = text_width(&el.text[..el.cursor]);
+                    let width = node.inner.width().max(1);
+                    el.scroll = el.scroll.min(column).max(column - width + 1).max(0);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Formats `shortcut` for display, e.g. "Ctrl+Shift+S", using the [`ModifierTranslations`].
+    /// Returns an empty string for [`vk::NULL`].
+    fn format_shortcut(&self, shortcut: InputKey) -> String {
+        let mut res = String::new();
+        let key = shortcut.key();
+        if key == vk::NULL {
+            return res;
+        }
+
+        let modifiers = shortcut.modifiers();
+        for (modifier, name) in [
+            (kbmod::CTRL, self.modifier_translations.ctrl),
+            (kbmod::ALT, self.modifier_translations.alt),
+            (kbmod::SHIFT, self.modifier_translations.shift),
+        ] {
+            if modifiers.contains(modifier) {
+                res.push_str(name);
+                res.push('+');
+            }
+        }
+
+        let name = match key {
+            vk::BACK => "Backspace",
+            vk::TAB => "Tab",
+            vk::RETURN => "Enter",
+            vk::ESCAPE => "Esc",
+            vk::SPACE => "Space",
+            vk::PRIOR => "PgUp",
+            vk::NEXT => "PgDn",
+            vk::END => "End",
+            vk::HOME => "Home",
+            vk::LEFT => "Left",
+            vk::UP => "Up",
+            vk::RIGHT => "Right",
+            vk::DOWN => "Down",
+            vk::INSERT => "Ins",
+            vk::DELETE => "Del",
+            _ => "",
+        };
+        if !name.is_empty() {
+            res.push_str(name);
+        } else if (vk::F1.value()..=vk::F24.value()).contains(&key.value()) {
+            _ = write!(res, "F{}", key.value() - vk::F1.value() + 1);
+        } else if let Some(c) = char::from_u32(key.value()) {
+            res.push(c);
+        }
+        res
     }
 }
 
@@ -164,4 +534,1595 @@ pub struct Context<'a, 'input> {
     tui: &'a mut Tui,
     /// Current text input, if any.
     input_text: Option<&'input str>,
+    /// Current keyboard input, if any.
+    input_keyboard: Option<InputKey>,
+    /// Current clipboard paste, if any.
+    input_paste: Option<Vec<u8>>,
+    /// Current mouse wheel scroll delta, if any.
+    input_scroll: Option<Point>,
+    /// Current left mouse button action, if any.
+    input_mouse: Option<MouseAction>,
+    /// Set once a widget handled the input, so that no one else does.
+    input_consumed: bool,
+    /// The ID of the topmost modal of the previous frame, or 0.
+    modal_id: u64,
+    /// The IDs of the modals that are being built.
+    modal_stack: Vec<u64>,
+
+    /// The IDs of the menus in the menubar that's being built.
+    menubar_titles: Vec<u64>,
+    /// The index of the menu that's open, if any.
+    menubar_open: Option<usize>,
+    /// The IDs of the items in the menu that's open.
+    menu_items: Vec<u64>,
+}
+
+impl Drop for Context<'_, '_> {
+    fn drop(&mut self) {
+        debug_assert!(self.tui.tree.current == 0, "unbalanced block_begin/block_end");
+        let unconsumed_key = if self.input_consumed { None } else { self.input_keyboard };
+        self.tui.finish_frame(unconsumed_key);
+    }
+}
+
+impl<'input> Context<'_, 'input> {
+    /// Returns the viewport size.
+    pub fn size(&self) -> Size {
+        self.tui.size
+    }
+
+    /// Returns an indexed color from the framebuffer.
+    pub fn indexed(&self, index: IndexedColor) -> StraightRgba {
+        self.tui.framebuffer.indexed(index)
+    }
+
+    /// Returns an indexed color from the framebuffer with the given alpha.
+    /// See [`Framebuffer::indexed_alpha()`].
+    pub fn indexed_alpha(
+        &self,
+        index: IndexedColor,
+        numerator: u32,
+        denominator: u32,
+    ) -> StraightRgba {
+        self.tui.framebuffer.indexed_alpha(index, numerator, denominator)
+    }
+
+    /// Returns a color in contrast with the given color.
+    /// See [`Framebuffer::contrasted()`].
+    pub fn contrasted(&self, color: StraightRgba) -> StraightRgba {
+        self.tui.framebuffer.contrasted(color)
+    }
+
+    /// Returns the clipboard.
+    pub fn clipboard_mut(&mut self) -> &mut Clipboard {
+        &mut self.tui.clipboard
+    }
+
+    /// Returns `false` if the input was consumed already,
+    /// or if a modal is open and we're not building its contents.
+    fn is_input_available(&self) -> bool {
+        !self.input_consumed && (self.modal_id == 0 || self.modal_stack.last() == Some(&self.modal_id))
+    }
+
+    /// Returns the text input of this frame, unless it was consumed already.
+    pub fn text_input(&self) -> Option<&'input str> {
+        if self.is_input_available() { self.input_text } else { None }
+    }
+
+    /// Returns the keyboard input of this frame, unless it was consumed already.
+    pub fn keyboard_input(&self) -> Option<InputKey> {
+        if self.is_input_available() { self.input_keyboard } else { None }
+    }
+
+    /// Returns the clipboard paste of this frame, unless it was consumed already.
+    pub fn paste_input(&mut self) -> Option<Vec<u8>> {
+        if self.is_input_available() { self.input_paste.take() } else { None }
+    }
+
+    /// Returns the mouse wheel scroll delta of this frame, unless it was consumed already.
+    pub fn scroll_input(&self) -> Option<Point> {
+        if self.is_input_available() { self.input_scroll } else { None }
+    }
+
+    /// Returns what the left mouse button did during this frame, unless it was consumed already.
+    /// The node it was pressed on is [`Tui::mouse_down_id`].
+    fn mouse_input(&self) -> Option<MouseAction> {
+        if self.is_input_available() { self.input_mouse } else { None }
+    }
+
+    /// Marks the input of this frame as handled.
+    pub fn set_input_consumed(&mut self) {
+        self.input_consumed = true;
+    }
+
+    /// Returns `true` and consumes the input, if it's the given `shortcut`.
+    pub fn consume_shortcut(&mut self, shortcut: InputKey) -> bool {
+        if self.keyboard_input() == Some(shortcut) {
+            self.set_input_consumed();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Mixes `id` into the ID of the next node. Use this to tell apart
+    /// siblings that share a classname, e.g. the items of a list.
+    pub fn next_block_id_mixin(&mut self, id: u64) {
+        self.tui.tree.next_id_mixin = Some(id);
+    }
+
+    /// Begins a new node as a child of the current one.
+    /// Every call must be paired with a call to [`Context::block_end`].
+    pub fn block_begin(&mut self, classname: &'static str) {
+        self.tui.tree.push(classname);
+    }
+
+    /// Ends the node started by the last unmatched [`Context::block_begin`].
+    pub fn block_end(&mut self) {
+        self.tui.tree.pop();
+    }
+
+    /// Sets how the children of the last node are arranged.
+    pub fn attr_layout(&mut self, layout: Layout) {
+        self.tui.tree.last_mut().attributes.layout = layout;
+    }
+
+    /// Sets the space between the border of the last node and its contents.
+    pub fn attr_padding(&mut self, padding: Rect) {
+        self.tui.tree.last_mut().attributes.padding = padding;
+    }
+
+    /// Draws a border around the last node.
+    pub fn attr_border(&mut self) {
+        self.tui.tree.last_mut().attributes.bordered = true;
+    }
+
+    /// Sets the background color of the last node.
+    pub fn attr_background_rgba(&mut self, bg: StraightRgba) {
+        self.tui.tree.last_mut().attributes.bg = bg;
+    }
+
+    /// Sets the foreground color of the last node.
+    pub fn attr_foreground_rgba(&mut self, fg: StraightRgba) {
+        self.tui.tree.last_mut().attributes.fg = fg;
+    }
+
+    /// Gives the last node a fixed size, including padding and border,
+    /// instead of the size of its contents.
+    pub fn attr_intrinsic_size(&mut self, size: Size) {
+        self.tui.tree.last_mut().attributes.intrinsic_size = Some(size);
+    }
+
+    /// Makes the last node take up the space that its siblings leave free
+    /// along the main axis of the parent. Shared evenly, if there are several.
+    pub fn attr_fill(&mut self) {
+        self.tui.tree.last_mut().attributes.fill = true;
+    }
+
+    /// Makes the last node focusable via mouse clicks and Tab.
+    pub fn attr_focusable(&mut self) {
+        self.tui.tree.last_mut().attributes.focusable = true;
+    }
+
+    /// Returns `true` if the last node has the keyboard focus.
+    pub fn is_focused(&self) -> bool {
+        self.tui.tree.last().id == self.tui.focused_id
+    }
+
+    /// Makes the next frame redraw the entire screen, because something else wrote to the terminal.
+    pub fn invalidate(&mut self) {
+        self.tui.framebuffer.invalidate();
+    }
+
+    /// Requests another frame right away, even without any input,
+    /// for work that is spread over several frames.
+    pub fn needs_rerender(&mut self) {
+        self.tui.read_timeout = time::Duration::ZERO;
+    }
+
+    /// Moves the keyboard focus to the last node.
+    pub fn steal_focus(&mut self) {
+        let id = self.tui.tree.last().id;
+        if self.tui.focused_id != id {
+            self.tui.focused_id = id;
+            // The nodes before this one were built with the old focus.
+            self.tui.needs_more_settling();
+        }
+    }
+
+    /// Returns `true` if the last node was clicked during this frame.
+    pub fn was_clicked(&self) -> bool {
+        let id = self.tui.tree.last().id;
+        id == self.tui.clicked_id
+    }
+
+    /// Returns `true` if the last node or one of its descendants has the keyboard focus.
+    ///
+    /// Since the descendants of the last node haven't been built yet,
+    /// this is based on the tree of the previous frame.
+    pub fn is_focus_within(&self) -> bool {
+        let id = self.tui.tree.last().id;
+        id == self.tui.focused_id || self.tui.prev_tree.is_descendant(self.tui.focused_id, id)
+    }
+
+    /// Returns `true` if no node has the keyboard focus.
+    pub fn is_focus_unset(&self) -> bool {
+        self.tui.focused_id == 0
+    }
+
+    /// Removes the keyboard focus from whatever node has it.
+    pub fn clear_focus(&mut self) {
+        if self.tui.focused_id != 0 {
+            self.tui.focused_id = 0;
+            self.tui.needs_more_settling();
+        }
+    }
+
+    /// Turns the last node into a floater. See [`FloatSpec`].
+    ///
+    /// It gets the default floater colors, which [`Context::attr_background_rgba`]
+    /// and [`Context::attr_foreground_rgba`] can override afterwards.
+    pub fn attr_float(&mut self, spec: FloatSpec) {
+        let attr = &mut self.tui.tree.last_mut().attributes;
+        attr.float = Some(spec);
+        attr.bg = self.tui.floater_default_bg;
+        attr.fg = self.tui.floater_default_fg;
+    }
+
+    /// Begins a modal dialog in the middle of the screen, titled `title`.
+    ///
+    /// Until it's closed, everything else is dimmed and doesn't receive any input,
+    /// and the focus is trapped inside of it. End it with [`Context::modal_end`].
+    pub fn modal_begin(&mut self, classname: &'static str, title: &str) {
+        let size = self.size();
+
+        self.block_begin(classname);
+        self.attr_float(FloatSpec {
+            anchor: Anchor::Root,
+            gravity_x: 0.5,
+            gravity_y: 0.5,
+            offset_x: size.width as f32 * 0.5,
+            offset_y: size.height as f32 * 0.5,
+        });
+        self.attr_border();
+        self.attr_padding(Rect::two(0, 1));
+        self.attr_background_rgba(self.tui.modal_default_bg);
+        self.attr_foreground_rgba(self.tui.modal_default_fg);
+
+        let node = self.tui.tree.last_mut();
+        node.attributes.modal = true;
+        self.modal_stack.push(node.id);
+
+        self.label("title", title);
+        self.attr_padding(Rect { bottom: 1, ..Default::default() });
+    }
+
+    /// Ends the modal started with [`Context::modal_begin`].
+    /// Returns `true` if the user asked to close it by pressing Escape.
+    pub fn modal_end(&mut self) -> bool {
+        let close = self.consume_shortcut(vk::ESCAPE);
+        self.modal_stack.pop();
+        self.block_end();
+        close
+    }
+
+    /// Adds a single line of text.
+    pub fn label(&mut self, classname: &'static str, text: &str) {
+        self.block_begin(classname);
+        self.tui.tree.last_mut().content = NodeContent::Text(TextContent::new(text));
+        self.block_end();
+    }
+
+    /// Adds a single line of text with an underlined `accelerator` character.
+    /// If `text` doesn't contain the accelerator, as is common with translations,
+    /// it's appended in parentheses, e.g. "파일(F)".
+    pub fn label_with_accelerator(&mut self, classname: &'static str, text: &str, accelerator: char) {
+        self.block_begin(classname);
+        self.tui.tree.last_mut().content = NodeContent::Text(TextContent::with_accelerator(text, accelerator));
+        self.block_end();
+    }
+
+    /// Adds a button. Returns `true` if it got activated, by clicking
+    /// on it or by pressing Enter or Space while it's focused.
+    pub fn button(&mut self, classname: &'static str, text: &str) -> bool {
+        self.activatable_text(classname, &format!("[ {text} ]"))
+    }
+
+    /// Adds an entry for a list, e.g. in a scrollarea. It works like a button,
+    /// but it's drawn without brackets and takes up the full width.
+    pub fn list_item(&mut self, classname: &'static str, text: &str) -> bool {
+        self.activatable_text(classname, text)
+    }
+
+    fn activatable_text(&mut self, classname: &'static str, text: &str) -> bool {
+        self.block_begin(classname);
+        self.attr_focusable();
+        self.tui.tree.last_mut().content = NodeContent::Text(TextContent::new(text));
+
+        if self.is_focused() {
+            let bg = self.indexed(IndexedColor::BrightBlue);
+            let fg = self.contrasted(bg);
+            self.attr_background_rgba(bg);
+            self.attr_foreground_rgba(fg);
+        }
+
+        let mut activated = self.was_clicked();
+        if !activated && self.is_focused() {
+            activated = self.consume_shortcut(vk::RETURN);
+            if !activated && self.text_input() == Some(" ") {
+                self.set_input_consumed();
+                activated = true;
+            }
+        }
+
+        self.block_end();
+        activated
+    }
+
+    /// Adds a checkbox labeled `text`. Returns `true` if it got toggled,
+    /// by clicking on it or by pressing Enter or Space while it's focused.
+    pub fn checkbox(&mut self, classname: &'static str, text: &str, checked: &mut bool) -> bool {
+        self.block_begin(classname);
+        self.attr_focusable();
+
+        let focused = self.is_focused();
+        if focused {
+            let bg = self.indexed(IndexedColor::BrightBlue);
+            let fg = self.contrasted(bg);
+            self.attr_background_rgba(bg);
+            self.attr_foreground_rgba(fg);
+        }
+
+        let mut toggled = self.was_clicked();
+        if !toggled && focused {
+            toggled = self.consume_shortcut(vk::RETURN);
+            if !toggled && self.text_input() == Some(" ") {
+                self.set_input_consumed();
+                toggled = true;
+            }
+        }
+        if toggled {
+            *checked = !*checked;
+        }
+
+        let mark = if *checked { 'x' } else { ' ' };
+        self.tui.tree.last_mut().content = NodeContent::Text(TextContent::new(&format!("[{mark}] {text}")));
+        self.block_end();
+        toggled
+    }
+
+    /// Adds a single-line text input for `text`. Returns `true` if the user changed the text.
+    ///
+    /// It doesn't handle Enter, Escape or Tab, so that the caller can give them a meaning.
+    pub fn editline(&mut self, classname: &'static str, text: &mut String) -> bool {
+        self.block_begin(classname);
+        self.attr_focusable();
+        self.attr_background_rgba(self.indexed_alpha(IndexedColor::Background, 2, 3));
+
+        // If the caller changed the text, e.g. to complete it, the cursor moves to its end.
+        let id = self.tui.tree.last().id;
+        let (mut cursor, scroll) = match self.tui.prev_tree.content(id) {
+            Some(NodeContent::Editline(el)) if el.text == *text => (el.cursor, el.scroll),
+            _ => (text.len(), 0),
+        };
+
+        let mut changed = false;
+        if self.is_focused() {
+            changed = self.editline_handle_input(text, &mut cursor);
+        }
+
+        self.tui.tree.last_mut().content =
+            NodeContent::Editline(EditlineContent { text: text.clone(), cursor, scroll });
+        self.block_end();
+        changed
+    }
+
+    /// Returns `true` if the input changed the text.
+    fn editline_handle_input(&mut self, text: &mut String, cursor: &mut usize) -> bool {
+        let prev_char = |text: &str, cursor: usize| text[..cursor].chars().next_back().map_or(0, char::len_utf8);
+        let next_char = |text: &str, cursor: usize| text[cursor..].chars().next().map_or(0, char::len_utf8);
+
+        let mut insert = None;
+        if let Some(input) = self.text_input() {
+            insert = Some(input.to_string());
+        } else if let Some(paste) = self.paste_input() {
+            // Only the first line, since there's only one.
+            let paste = String::from_utf8_lossy(&paste);
+            insert = Some(paste.lines().next().unwrap_or("").to_string());
+        }
+
+        if let Some(mut insert) = insert {
+            self.set_input_consumed();
+            insert.retain(|c| !c.is_control());
+            text.insert_str(*cursor, &insert);
+            *cursor += insert.len();
+            return !insert.is_empty();
+        }
+
+        let Some(key) = self.keyboard_input() else {
+            return false;
+        };
+
+        let mut changed = false;
+        match key {
+            vk::BACK => {
+                let len = prev_char(text, *cursor);
+                *cursor -= len;
+                text.replace_range(*cursor..*cursor + len, "");
+                changed = len != 0;
+            }
+            vk::DELETE => {
+                let len = next_char(text, *cursor);
+                text.replace_range(*cursor..*cursor + len, "");
+                changed = len != 0;
+            }
+            vk::LEFT => *cursor -= prev_char(text, *cursor),
+            vk::RIGHT => *cursor += next_char(text, *cursor),
+            vk::HOME => *cursor = 0,
+            vk::END => *cursor = text.len(),
+            _ => return false,
+        }
+
+        self.set_input_consumed();
+        changed
+    }
+
+    /// Begins an area of the given `size` whose children are stacked vertically
+    /// and can be scrolled with the mouse wheel. Up and Down move the focus between them,
+    /// and the focused child is always scrolled into view. End it with [`Context::scrollarea_end`].
+    pub fn scrollarea_begin(&mut self, classname: &'static str, size: Size) {
+        self.block_begin(classname);
+        self.attr_intrinsic_size(size);
+
+        let id = self.tui.tree.last().id;
+        let mut scroll = match self.tui.prev_tree.content(id) {
+            Some(NodeContent::Scrollarea(scroll)) => *scroll,
+            _ => 0,
+        };
+
+        // The scroll position gets clamped during the layout.
+        if let Some(delta) = self.scroll_input()
+            && self.tui.prev_tree.outer(id).is_some_and(|r| r.contains(self.tui.mouse_position))
+        {
+            self.set_input_consumed();
+            scroll += delta.y;
+        }
+
+        self.tui.tree.last_mut().content = NodeContent::Scrollarea(scroll);
+    }
+
+    /// Ends the area started with [`Context::scrollarea_begin`].
+    pub fn scrollarea_end(&mut self) {
+        self.block_end();
+    }
+
+    /// Returns `true` if `accelerator` was typed, with or without Alt, and consumes the input.
+    fn consume_accelerator(&mut self, accelerator: char) -> bool {
+        let Some(key) = InputKey::from_ascii(accelerator.to_ascii_lowercase()) else {
+            return false;
+        };
+
+        let typed = self.text_input().and_then(|text| {
+            let mut chars = text.chars();
+            chars.next().filter(|_| chars.next().is_none())
+        });
+
+        if typed.is_some_and(|c| c.eq_ignore_ascii_case(&accelerator))
+            || self.keyboard_input() == Some(key)
+            || self.keyboard_input() == Some(kbmod::ALT | key)
+        {
+            self.set_input_consumed();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Begins a menubar. Add menus to it with [`Context::menubar_menu_begin`].
+    pub fn menubar_begin(&mut self) {
+        self.block_begin("menubar");
+        self.attr_layout(Layout::Row);
+        self.menubar_titles.clear();
+        self.menubar_open = None;
+    }
+
+    /// Ends the menubar and handles the keyboard navigation between its menus.
+    pub fn menubar_end(&mut self) {
+        let titles = mem::take(&mut self.menubar_titles);
+        let len = titles.len();
+
+        if let Some(open) = self.menubar_open {
+            let next = match self.keyboard_input() {
+                Some(vk::LEFT) => Some((open + len - 1) % len),
+                Some(vk::RIGHT) => Some((open + 1) % len),
+                _ => None,
+            };
+            if let Some(next) = next {
+                self.set_input_consumed();
+                self.tui.focused_id = titles[next];
+                self.tui.needs_more_settling();
+            }
+        } else if len > 0 && self.consume_shortcut(vk::F10) {
+            self.tui.focused_id = titles[0];
+            self.tui.needs_more_settling();
+        }
+
+        self.block_end();
+    }
+
+    /// Adds a menu titled `text` to the menubar. It can be opened with a click or Alt+`accelerator`.
+    ///
+    /// Returns `true` if the menu is open. In that case, add the entries with
+    /// [`Context::menubar_menu_button`] and close it with [`Context::menubar_menu_end`].
+    pub fn menubar_menu_begin(&mut self, text: &str, accelerator: char) -> bool {
+        let index = self.menubar_titles.len();
+
+        self.next_block_id_mixin(index as u64);
+        self.block_begin("menu");
+        self.attr_focusable();
+        self.attr_padding(Rect::two(0, 1));
+        self.tui.tree.last_mut().content = NodeContent::Text(TextContent::with_accelerator(text, accelerator));
+        self.menubar_titles.push(self.tui.tree.last().id);
+
+        if let Some(key) = InputKey::from_ascii(accelerator.to_ascii_lowercase())
+            && self.consume_shortcut(kbmod::ALT | key)
+        {
+            self.steal_focus();
+        }
+
+        if !self.is_focus_within() {
+            self.block_end();
+            return false;
+        }
+
+        let bg = self.indexed(IndexedColor::BrightBlue);
+        let fg = self.contrasted(bg);
+        self.attr_background_rgba(bg);
+        self.attr_foreground_rgba(fg);
+
+        self.menubar_open = Some(index);
+        self.menu_items.clear();
+
+        self.block_begin("dropdown");
+        self.attr_float(FloatSpec { anchor: Anchor::Parent, offset_y: 1.0, ..Default::default() });
+        self.attr_border();
+        self.attr_padding(Rect::two(0, 1));
+        true
+    }
+
+    /// Adds an entry to the open menu. `shortcut` is only displayed; pass [`vk::NULL`] if there's none.
+    ///
+    /// Returns `true` if the entry was activated, which also closes the menu.
+    pub fn menubar_menu_button(&mut self, text: &str, accelerator: char, shortcut: InputKey) -> bool {
+        self.menubar_menu_item(text, accelerator, shortcut, None)
+    }
+
+    /// Like [`Context::menubar_menu_button`], but with a checkmark in front of it if `checked`.
+    pub fn menubar_menu_checkbox(
+        &mut self,
+        text: &str,
+        accelerator: char,
+        shortcut: InputKey,
+        checked: bool,
+    ) -> bool {
+        self.menubar_menu_item(text, accelerator, shortcut, Some(checked))
+    }
+
+    fn menubar_menu_item(
+        &mut self,
+        text: &str,
+        accelerator: char,
+        shortcut: InputKey,
+        checked: Option<bool>,
+    ) -> bool {
+        self.next_block_id_mixin(self.menu_items.len() as u64);
+        self.block_begin("item");
+        self.attr_layout(Layout::Row);
+        self.attr_focusable();
+        self.menu_items.push(self.tui.tree.last().id);
+
+        let focused = self.is_focused();
+        if focused {
+            let bg = self.indexed(IndexedColor::BrightBlue);
+            let fg = self.contrasted(bg);
+            self.attr_background_rgba(bg);
+            self.attr_foreground_rgba(fg);
+        }
+
+        let mut activated = self.was_clicked() || self.consume_accelerator(accelerator);
+        if !activated && focused {
+            activated = self.consume_shortcut(vk::RETURN) || self.consume_shortcut(vk::SPACE);
+        }
+
+        self.label("check", if checked == Some(true) { "✓ " } else { "  " });
+        self.label_with_accelerator("text", text, accelerator);
+        self.attr_fill();
+
+        let shortcut = self.tui.format_shortcut(shortcut);
+        if !shortcut.is_empty() {
+            self.label("shortcut", &shortcut);
+            self.attr_padding(Rect { left: 2, ..Default::default() });
+        }
+
+        self.block_end();
+
+        if activated {
+            self.clear_focus();
+        }
+        activated
+    }
+
+    /// Closes the menu opened with [`Context::menubar_menu_begin`]
+    /// and handles the keyboard navigation within it.
+    pub fn menubar_menu_end(&mut self) {
+        let items = mem::take(&mut self.menu_items);
+        let focused = items.iter().position(|&id| id == self.tui.focused_id);
+
+        match self.keyboard_input() {
+            Some(vk::ESCAPE) => {
+                self.set_input_consumed();
+                self.clear_focus();
+            }
+            Some(key @ (vk::UP | vk::DOWN)) if !items.is_empty() => {
+                self.set_input_consumed();
+                let len = items.len();
+                let next = match focused {
+                    Some(i) if key == vk::UP => (i + len - 1) % len,
+                    Some(i) => (i + 1) % len,
+                    None if key == vk::UP => len - 1,
+                    None => 0,
+                };
+                self.tui.focused_id = items[next];
+                self.tui.needs_more_settling();
+            }
+            _ => {
+                // The menu was just opened. Focus its first entry.
+                if focused.is_none() && !self.is_focus_unset() && !items.is_empty() {
+                    self.tui.focused_id = items[0];
+                    self.tui.needs_more_settling();
+                }
+            }
+        }
+
+        self.block_end(); // dropdown
+        self.block_end(); // menu
+    }
+
+    /// Adds a multi-line text editor for `tb`.
+    ///
+    /// It handles typing, deleting, moving the cursor, the clipboard and undo/redo while it's focused.
+    /// The cursor moves by grapheme clusters, words (Ctrl), lines and pages.
+    /// With word wrap, long lines are broken at the width of the textarea.
+    /// Give it a size with [`Context::attr_fill`] or [`Context::attr_intrinsic_size`].
+    pub fn textarea(&mut self, classname: &'static str, tb: RcTextBuffer) {
+        self.block_begin(classname);
+        self.attr_focusable();
+
+        let id = self.tui.tree.last().id;
+        let (mut scroll, mut scroll_row, prev_cursor, mut drag) = match self.tui.prev_tree.content(id) {
+            Some(NodeContent::Textarea(ta)) => (ta.scroll, ta.scroll_row, Some(ta.cursor), ta.drag.clone()),
+            _ => (Point::default(), 0, None, TextareaDrag::default()),
+        };
+        let mut follow_cursor = false;
+
+        if let Some(action) = self.mouse_input()
+            && self.tui.mouse_down_id == id
+            && let Some(inner) = self.tui.prev_tree.inner(id)
+        {
+            self.set_input_consumed();
+            let pos = self.tui.mouse_position;
+            let pos = Point { x: pos.x - inner.left + scroll.x, y: pos.y - inner.top };
+            let top = VisualRow { line: scroll.y, row: scroll_row };
+            Self::textarea_handle_mouse(&tb, action, pos, top, &mut drag);
+            follow_cursor = true;
+        }
+
+        if self.is_focused() {
+            let page = self.tui.prev_tree.inner(id).map_or(1, |r| r.height().max(1));
+            let mut top = VisualRow { line: scroll.y, row: scroll_row };
+            follow_cursor = self.textarea_handle_input(&tb, page, &mut top);
+            scroll.y = top.line;
+            scroll_row = top.row;
+
+            if let Some(delta) = self.scroll_input() {
+                self.set_input_consumed();
+                let mut tb = tb.borrow_mut();
+                let top = tb.visual_row_move(VisualRow { line: scroll.y, row: scroll_row }, delta.y);
+                scroll.y = top.line;
+                scroll_row = top.row;
+                if !tb.is_word_wrap_enabled() {
+                    scroll.x = (scroll.x + delta.x).max(0);
+                }
+            }
+        }
+
+        // The cursor may also have been moved from elsewhere, e.g. by a search.
+        let cursor = tb.borrow().cursor_offset();
+        follow_cursor |= prev_cursor.is_some_and(|c| c != cursor);
+
+        self.tui.tree.last_mut().content = NodeContent::Textarea(TextareaContent {
+            buffer: tb,
+            scroll,
+            scroll_row,
+            cursor,
+            follow_cursor,
+            drag,
+        });
+        self.block_end();
+    }
+
+    /// Returns `true` if the input changed the text or moved the cursor.
+    /// `page` is the height of the textarea and `top` the first visual line shown in it,
+    /// which PageUp and PageDown scroll along with the cursor.
+    fn textarea_handle_input(&mut self, tb: &RcTextBuffer, page: CoordType, top: &mut VisualRow) -> bool {
+        if let Some(text) = self.text_input() {
+            self.set_input_consumed();
+            tb.borrow_mut().write(text.as_bytes());
+            return true;
+        }
+
+        if let Some(paste) = self.paste_input() {
+            self.set_input_consumed();
+            tb.borrow_mut().write(&paste);
+            return true;
+        }
+
+        let Some(key) = self.keyboard_input() else {
+            return false;
+        };
+
+        let mut tb = tb.borrow_mut();
+        if Self::textarea_move_cursor(&mut tb, key, page, top) {
+            self.set_input_consumed();
+            return true;
+        }
+
+        match key {
+            vk::BACK => tb.delete_backward(),
+            vk::DELETE => tb.delete_forward(),
+            vk::RETURN => {
+                let newline = tb.newline();
+                tb.write(newline);
+            }
+            vk::TAB => tb.indent(),
+            _ if key == kbmod::SHIFT | vk::TAB => tb.unindent(),
+            _ if key == kbmod::CTRL | vk::X => tb.cut(&mut self.tui.clipboard),
+            _ if key == kbmod::CTRL | vk::C => tb.copy(&mut self.tui.clipboard),
+            _ if key == kbmod::CTRL | vk::V => tb.paste(&self.tui.clipboard),
+            _ if key == kbmod::CTRL | vk::Z => tb.undo(),
+            _ if key == kbmod::CTRL | vk::Y => tb.redo(),
+            _ if key == kbmod::CTRL | vk::A => tb.select_all(),
+            _ => return false,
+        }
+
+        self.set_input_consumed();
+        true
+    }
+
+    /// Handles the keys that move the cursor. With Shift, they extend the selection instead.
+    /// Returns `true` if `key` was one of them.
+    fn textarea_move_cursor(tb: &mut TextBuffer, key: InputKey, page: CoordType, top: &mut VisualRow) -> bool {
+        let select = key.modifiers_contains(kbmod::SHIFT);
+        let anchor = tb.selection_anchor().unwrap_or(tb.cursor_offset());
+        // Without Shift, Left and Right first collapse the selection to its respective end.
+        let collapse = if select { None } else { tb.selection_range() };
+
+        match (key.without_modifiers(kbmod::SHIFT), collapse) {
+            (vk::LEFT, Some(selection)) => tb.set_cursor_offset(selection.start),
+            (vk::RIGHT, Some(selection)) => tb.set_cursor_offset(selection.end),
+            (vk::LEFT, _) => tb.move_cursor_horizontal(-1),
+            (vk::RIGHT, _) => tb.move_cursor_horizontal(1),
+            (vk::UP, _) => tb.move_cursor_vertical(-1),
+            (vk::DOWN, _) => tb.move_cursor_vertical(1),
+            (vk::HOME, _) => tb.move_cursor_home(),
+            (vk::END, _) => tb.move_cursor_end(),
+            (vk::PRIOR | vk::NEXT, _) => {
+                let delta = if key.key() == vk::PRIOR { -page } else { page };
+                *top = tb.visual_row_move(*top, delta);
+                tb.move_cursor_vertical(delta);
+            }
+            (k, _) if k == kbmod::CTRL | vk::LEFT => tb.move_cursor_word(false),
+            (k, _) if k == kbmod::CTRL | vk::RIGHT => tb.move_cursor_word(true),
+            (k, _) if k == kbmod::CTRL | vk::HOME => tb.set_cursor_offset(0),
+            (k, _) if k == kbmod::CTRL | vk::END => {
+                let len = tb.text_length();
+                tb.set_cursor_offset(len);
+            }
+            _ => return false,
+        }
+
+        if select {
+            let cursor = tb.cursor_offset();
+            tb.set_selection(anchor, cursor);
+        }
+        true
+    }
+
+    /// Moves the cursor to where the mouse is, or selects text by dragging it.
+    /// A double click selects a word and a triple click a line. Dragging after them
+    /// extends the selection by whole words or lines. Shift+click extends the selection.
+    /// `pos` is relative to the top left corner of the textarea, in logical columns,
+    /// and `top` is the first visual line that is shown.
+    fn textarea_handle_mouse(
+        tb: &RcTextBuffer,
+        action: MouseAction,
+        pos: Point,
+        top: VisualRow,
+        drag: &mut TextareaDrag,
+    ) {
+        let mut tb = tb.borrow_mut();
+        let row = tb.visual_row_move(top, pos.y);
+        let off = tb.visual_row_to_offset(row, pos.x);
+
+        match action {
+            MouseAction::Press { clicks: 1, modifiers } if modifiers.contains(kbmod::SHIFT) => {
+                let anchor = tb.selection_anchor().unwrap_or(tb.cursor_offset());
+                tb.set_selection(anchor, off);
+                *drag = TextareaDrag { clicks: 1, origin: anchor..anchor };
+            }
+            MouseAction::Press { clicks, .. } => {
+                let origin = match clicks {
+                    1 => off..off,
+                    2 => tb.word_range_at(off),
+                    _ => tb.line_range_at(off),
+                };
+                if origin.is_empty() {
+                    tb.set_cursor_offset(off);
+                } else {
+                    tb.set_selection(origin.start, origin.end);
+                }
+                *drag = TextareaDrag { clicks, origin };
+            }
+            MouseAction::Drag => {
+                let unit = match drag.clicks {
+                    2 => tb.word_range_at(off),
+                    3 => tb.line_range_at(off),
+                    _ => off..off,
+                };
+                let origin = drag.origin.clone();
+                if unit.start < origin.start {
+                    tb.set_selection(origin.end, unit.start);
+                } else {
+                    tb.set_selection(origin.start, unit.end.max(origin.end));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+struct NodeAttributes {
+    layout: Layout,
+    padding: Rect,
+    bordered: bool,
+    bg: StraightRgba,
+    fg: StraightRgba,
+    intrinsic_size: Option<Size>,
+    fill: bool,
+    focusable: bool,
+    float: Option<FloatSpec>,
+    modal: bool,
+}
+
+#[derive(Default)]
+enum NodeContent {
+    #[default]
+    None,
+    Text(TextContent),
+    Textarea(TextareaContent),
+    Editline(EditlineContent),
+    /// A scrollarea and its vertical scroll position.
+    Scrollarea(CoordType),
+}
+
+struct TextContent {
+    text: String,
+    /// The column of the character to underline, if any.
+    underline: Option<CoordType>,
+}
+
+impl TextContent {
+    fn new(text: &str) -> Self {
+        Self { text: text.to_string(), underline: None }
+    }
+
+    fn with_accelerator(text: &str, accelerator: char) -> Self {
+        let mut column = 0;
+        for c in text.chars() {
+            if c.eq_ignore_ascii_case(&accelerator) {
+                return Self { text: text.to_string(), underline: Some(column) };
+            }
+            column += char_width(c);
+        }
+
+        let text = format!("{text}({})", accelerator.to_ascii_uppercase());
+        Self { text, underline: Some(column + 1) }
+    }
+}
+
+struct EditlineContent {
+    text: String,
+    /// The byte offset of the cursor in `text`.
+    cursor: usize,
+    /// The column shown at the left edge.
+    scroll: CoordType,
+}
+
+struct TextareaContent {
+    buffer: RcTextBuffer,
+    /// The logical position shown in the top left corner.
+    scroll: Point,
+    /// Which of the visual lines of line `scroll.y` is at the top, if word wrap splits it up.
+    scroll_row: CoordType,
+    /// The offset of the cursor when the node was built, to notice when it moves.
+    cursor: usize,
+    /// Set if the text or cursor changed, to scroll the cursor into view after the layout.
+    follow_cursor: bool,
+    /// The state of selecting text with the mouse.
+    drag: TextareaDrag,
+}
+
+/// What a mouse drag in a textarea selects, depending on how it started.
+#[derive(Clone, Default)]
+struct TextareaDrag {
+    /// 1 for characters, 2 for words (after a double click) and 3 for lines (after a triple click).
+    clicks: u8,
+    /// The word or line that the drag started on. The selection always includes it.
+    origin: Range<usize>,
+}
+
+/// A node of the UI tree. Nodes refer to each other by their index in [`Tree::nodes`].
+#[derive(Default)]
+struct Node {
+    /// Derived from the parent's ID and the classname. Stable across frames.
+    id: u64,
+    parent: Option<usize>,
+    first_child: Option<usize>,
+    last_child: Option<usize>,
+    next_sibling: Option<usize>,
+
+    attributes: NodeAttributes,
+    content: NodeContent,
+
+    /// The size the node would like to have. Computed by [`Tree::layout`].
+    intrinsic_size: Size,
+    /// The area the node occupies, including border and padding. Computed by [`Tree::layout`].
+    outer: Rect,
+    /// The area of the contents. Computed by [`Tree::layout`].
+    inner: Rect,
+}
+
+/// The UI tree of a single frame. Index 0 is the root node, which covers the entire viewport.
+#[derive(Default)]
+struct Tree {
+    nodes: Vec<Node>,
+    /// Maps node IDs to their index in `nodes`.
+    ids: HashMap<u64, usize>,
+    /// The container that new nodes are added to.
+    current: usize,
+    /// The node that was added (or closed) last. Attributes apply to it.
+    last: usize,
+    next_id_mixin: Option<u64>,
+}
+
+impl Tree {
+    fn reset(&mut self, size: Size) {
+        self.nodes.clear();
+        self.ids.clear();
+        self.nodes.push(Node { id: ROOT_ID, ..Default::default() });
+        self.nodes[0].outer = size.as_rect();
+        self.nodes[0].inner = size.as_rect();
+        self.ids.insert(ROOT_ID, 0);
+        self.current = 0;
+        self.last = 0;
+        self.next_id_mixin = None;
+    }
+
+    fn last(&self) -> &Node {
+        &self.nodes[self.last]
+    }
+
+    fn last_mut(&mut self) -> &mut Node {
+        &mut self.nodes[self.last]
+    }
+
+    fn push(&mut self, classname: &'static str) {
+        let parent = self.current;
+        let mut id = hash_str(self.nodes[parent].id, classname);
+        if let Some(mixin) = self.next_id_mixin.take() {
+            id = hash_u64(id, mixin);
+        }
+        // Siblings with the same classname and no mixin would collide. Number the duplicates,
+        // so that the ID only depends on the same-named siblings that came before it.
+        let base = id;
+        let mut duplicate = 0;
+        while self.ids.contains_key(&id) {
+            duplicate += 1;
+            id = hash_u64(base, duplicate);
+        }
+
+        let idx = self.nodes.len();
+        self.nodes.push(Node { id, parent: Some(parent), ..Default::default() });
+        self.ids.insert(id, idx);
+
+        match self.nodes[parent].last_child {
+            Some(prev) => self.nodes[prev].next_sibling = Some(idx),
+            None => self.nodes[parent].first_child = Some(idx),
+        }
+        self.nodes[parent].last_child = Some(idx);
+
+        self.current = idx;
+        self.last = idx;
+    }
+
+    fn pop(&mut self) {
+        self.last = self.current;
+        self.current = self.nodes[self.current].parent.unwrap_or(0);
+    }
+
+    /// Returns the ID of the last modal in the tree, which is the one on top, or 0.
+    fn modal_id(&self) -> u64 {
+        self.nodes.iter().rev().find(|n| n.attributes.modal).map_or(0, |n| n.id)
+    }
+
+    /// Returns the outer rectangle of the node with the given `id`, if it exists.
+    fn outer(&self, id: u64) -> Option<Rect> {
+        self.ids.get(&id).map(|&idx| self.nodes[idx].outer)
+    }
+
+    /// Returns the inner rectangle of the node with the given `id`, if it exists.
+    fn inner(&self, id: u64) -> Option<Rect> {
+        self.ids.get(&id).map(|&idx| self.nodes[idx].inner)
+    }
+
+    /// Returns the ID of the scrollarea that contains the node `id`, if any.
+    fn scrollarea_of(&self, id: u64) -> Option<u64> {
+        let mut idx = self.ids.get(&id).and_then(|&idx| self.nodes[idx].parent);
+        while let Some(i) = idx {
+            if matches!(self.nodes[i].content, NodeContent::Scrollarea(_)) {
+                return Some(self.nodes[i].id);
+            }
+            idx = self.nodes[i].parent;
+        }
+        None
+    }
+
+    /// Scrolls the scrollarea that contains the node `id`, so that the node is visible.
+    /// Returns `true` if it had to scroll, in which case the layout must be redone.
+    fn scroll_into_view(&mut self, id: u64) -> bool {
+        let Some(area) = self.scrollarea_of(id) else {
+            return false;
+        };
+        let node = self.outer(id).unwrap();
+        let area = self.ids[&area];
+        let inner = self.nodes[area].inner;
+        let NodeContent::Scrollarea(scroll) = &mut self.nodes[area].content else {
+            unreachable!();
+        };
+
+        let prev = *scroll;
+        if node.top < inner.top {
+            *scroll -= inner.top - node.top;
+        } else if node.bottom > inner.bottom {
+            *scroll += (node.bottom - inner.bottom).min(node.top - inner.top);
+        }
+        *scroll != prev
+    }
+
+    /// Returns the content of the node with the given `id`, if it exists.
+    fn content(&self, id: u64) -> Option<&NodeContent> {
+        self.ids.get(&id).map(|&idx| &self.nodes[idx].content)
+    }
+
+    /// Returns `true` if the node `id` is a descendant of the node `ancestor`.
+    fn is_descendant(&self, id: u64, ancestor: u64) -> bool {
+        let mut idx = self.ids.get(&id).and_then(|&idx| self.nodes[idx].parent);
+        while let Some(i) = idx {
+            if self.nodes[i].id == ancestor {
+                return true;
+            }
+            idx = self.nodes[i].parent;
+        }
+        false
+    }
+
+    /// Like [`Tree::children`], but skips floaters.
+    fn inline_children(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        self.children(idx).filter(|&c| self.nodes[c].attributes.float.is_none())
+    }
+
+    fn children(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut next = self.nodes[idx].first_child;
+        std::iter::from_fn(move || {
+            let idx = next?;
+            next = self.nodes[idx].next_sibling;
+            Some(idx)
+        })
+    }
+
+    /// Returns the ID of the deepest node at `pos` and whether it's focusable.
+    /// If the deepest node isn't focusable, but one of its ancestors is, that one is returned.
+    fn hit_test(&self, pos: Point) -> (u64, bool) {
+        if self.nodes.is_empty() || !self.nodes[0].outer.contains(pos) {
+            return (0, false);
+        }
+
+        // Floaters are drawn on top of everything else, the later ones on top of the earlier ones.
+        let mut hit = (1..self.nodes.len())
+            .rev()
+            .find(|&idx| self.nodes[idx].attributes.float.is_some() && self.nodes[idx].outer.contains(pos))
+            .unwrap_or(0);
+
+        let mut clip = self.nodes[hit].outer;
+        'outer: loop {
+            // Children are clipped to the inside of their parent, e.g. when it's scrolled.
+            clip = clip.intersect(self.nodes[hit].inner);
+            // Later siblings are drawn on top of earlier ones.
+            let mut found = None;
+            for child in self.inline_children(hit) {
+                if self.nodes[child].outer.intersect(clip).contains(pos) {
+                    found = Some(child);
+                }
+            }
+            match found {
+                Some(child) => hit = child,
+                None => break 'outer,
+            }
+        }
+
+        let mut idx = Some(hit);
+        while let Some(i) = idx {
+            if self.nodes[i].attributes.focusable {
+                return (self.nodes[i].id, true);
+            }
+            idx = self.nodes[i].parent;
+        }
+        (self.nodes[hit].id, false)
+    }
+
+    /// Computes the position and size of all nodes.
+    fn layout(&mut self, size: Size) {
+        let screen = size.as_rect();
+        self.measure(0);
+        self.arrange(0, screen);
+
+        // Floaters are positioned after their parents, which come first in `nodes`.
+        for idx in 1..self.nodes.len() {
+            let Some(spec) = self.nodes[idx].attributes.float else {
+                continue;
+            };
+
+            let anchor = match spec.anchor {
+                Anchor::Parent => self.nodes[self.nodes[idx].parent.unwrap_or(0)].outer,
+                Anchor::Root => screen,
+            };
+            let size = self.nodes[idx].intrinsic_size;
+            let width = size.width.min(screen.width());
+            let height = size.height.min(screen.height());
+
+            // Keep the floater on the screen.
+            let x = anchor.left + spec.offset_x as CoordType - (spec.gravity_x * width as f32) as CoordType;
+            let y = anchor.top + spec.offset_y as CoordType - (spec.gravity_y * height as f32) as CoordType;
+            let x = x.min(screen.right - width).max(0);
+            let y = y.min(screen.bottom - height).max(0);
+
+            self.arrange(idx, Rect { left: x, top: y, right: x + width, bottom: y + height });
+        }
+    }
+
+    /// Computes the intrinsic sizes, bottom-up.
+    fn measure(&mut self, idx: usize) -> Size {
+        let children: Vec<usize> = self.children(idx).collect();
+        let mut sizes = Vec::with_capacity(children.len());
+        for &child in &children {
+            let size = self.measure(child);
+            // Floaters don't take up space in their parent.
+            if self.nodes[child].attributes.float.is_none() {
+                sizes.push(size);
+            }
+        }
+
+        let node = &self.nodes[idx];
+        let attr = &node.attributes;
+        let content = match &node.content {
+            NodeContent::Text(text) => Size { width: text_width(&text.text), height: 1 },
+            NodeContent::Textarea(_) => Size::default(),
+            // The extra column is for the cursor at the end.
+            NodeContent::Editline(el) => Size { width: text_width(&el.text) + 1, height: 1 },
+            NodeContent::None | NodeContent::Scrollarea(_) => match attr.layout {
+                Layout::Column => Size {
+                    width: sizes.iter().map(|s| s.width).max().unwrap_or(0),
+                    height: sizes.iter().map(|s| s.height).sum(),
+                },
+                Layout::Row => Size {
+                    width: sizes.iter().map(|s| s.width).sum(),
+                    height: sizes.iter().map(|s| s.height).max().unwrap_or(0),
+                },
+                Layout::Grid(columns) => {
+                    let (widths, heights) = grid_tracks(&sizes, columns);
+                    Size { width: widths.iter().sum(), height: heights.iter().sum() }
+                }
+            },
+        };
+
+        let size = attr.intrinsic_size.unwrap_or_else(|| {
+            let frame = frame_thickness(attr);
+            Size {
+                width: content.width + frame.left + frame.right,
+                height: content.height + frame.top + frame.bottom,
+            }
+        });
+
+        self.nodes[idx].intrinsic_size = size;
+        size
+    }
+
+    /// Assigns the rectangles, top-down.
+    fn arrange(&mut self, idx: usize, outer: Rect) {
+        let frame = frame_thickness(&self.nodes[idx].attributes);
+        let inner = Rect {
+            left: (outer.left + frame.left).min(outer.right),
+            top: (outer.top + frame.top).min(outer.bottom),
+            right: (outer.right - frame.right).max(outer.left + frame.left).max(outer.left),
+            bottom: (outer.bottom - frame.bottom).max(outer.top + frame.top).max(outer.top),
+        };
+        let inner = inner.intersect(outer);
+
+        self.nodes[idx].outer = outer;
+        self.nodes[idx].inner = inner;
+
+        let children: Vec<usize> = self.inline_children(idx).collect();
+        if children.is_empty() {
+            return;
+        }
+
+        let sizes: Vec<Size> = children.iter().map(|&c| self.nodes[c].intrinsic_size).collect();
+        let fills: Vec<bool> = children.iter().map(|&c| self.nodes[c].attributes.fill).collect();
+
+        // The children of a scrollarea are laid out at their full height, shifted up by the scroll
+        // position. Whatever ends up outside of it gets clipped.
+        let mut inner = inner;
+        if let NodeContent::Scrollarea(scroll) = &mut self.nodes[idx].content {
+            let height: CoordType = sizes.iter().map(|s| s.height).sum();
+            *scroll = (*scroll).clamp(0, (height - inner.height()).max(0));
+            inner.top -= *scroll;
+            inner.bottom = inner.top + height.max(inner.height());
+        }
+
+        match self.nodes[idx].attributes.layout {
+            Layout::Column => {
+                let used: CoordType = sizes.iter().map(|s| s.height).sum();
+                let extra = distribute(inner.height() - used, &fills);
+                let mut y = inner.top;
+                for (i, &child) in children.iter().enumerate() {
+                    let bottom = (y + sizes[i].height + extra[i]).min(inner.bottom);
+                    let rect = Rect { left: inner.left, top: y, right: inner.right, bottom };
+                    self.arrange(child, rect);
+                    y = bottom;
+                }
+            }
+            Layout::Row => {
+                let used: CoordType = sizes.iter().map(|s| s.width).sum();
+                let extra = distribute(inner.width() - used, &fills);
+                let mut x = inner.left;
+                for (i, &child) in children.iter().enumerate() {
+                    let right = (x + sizes[i].width + extra[i]).min(inner.right);
+                    let rect = Rect { left: x, top: inner.top, right, bottom: inner.bottom };
+                    self.arrange(child, rect);
+                    x = right;
+                }
+            }
+            Layout::Grid(columns) => {
+                let columns = columns.max(1) as usize;
+                let (widths, heights) = grid_tracks(&sizes, columns as CoordType);
+                let mut y = inner.top;
+                for (row, chunk) in children.chunks(columns).enumerate() {
+                    let bottom = (y + heights[row]).min(inner.bottom);
+                    let mut x = inner.left;
+                    for (col, &child) in chunk.iter().enumerate() {
+                        let right = (x + widths[col]).min(inner.right);
+                        self.arrange(child, Rect { left: x, top: y, right, bottom });
+                        x = right;
+                    }
+                    y = bottom;
+                }
+            }
+        }
+    }
+
+    /// Draws the tree into the framebuffer. Floaters are drawn last, so that they're on top.
+    fn render(&self, fb: &mut Framebuffer, focused_id: u64) {
+        let screen = fb.size().as_rect();
+        self.render_node(fb, 0, screen, focused_id);
+
+        for idx in 1..self.nodes.len() {
+            let attr = &self.nodes[idx].attributes;
+            if attr.float.is_some() {
+                // Dim everything behind a modal.
+                if attr.modal {
+                    fb.blend_bg(screen, fb.indexed_alpha(IndexedColor::Background, 1, 2));
+                    fb.blend_fg(screen, fb.indexed_alpha(IndexedColor::Background, 1, 2));
+                }
+                self.render_node(fb, idx, screen, focused_id);
+            }
+        }
+    }
+
+    /// Draws the node at `idx` and its children into the framebuffer, clipped to `clip`.
+    fn render_node(&self, fb: &mut Framebuffer, idx: usize, clip: Rect, focused_id: u64) {
+        let node = &self.nodes[idx];
+        let attr = &node.attributes;
+        let outer = node.outer.intersect(clip);
+        if outer.is_empty() {
+            return;
+        }
+
+        if attr.bg.alpha() != 0 {
+            fb.blend_bg(outer, attr.bg);
+        }
+        if attr.fg.alpha() != 0 {
+            fb.blend_fg(outer, attr.fg);
+        }
+        if attr.bordered {
+            fb.draw_border(node.outer, clip);
+        }
+
+        let inner = node.inner.intersect(clip);
+        match &node.content {
+            NodeContent::None => {}
+            NodeContent::Text(text) => {
+                fb.replace_text(node.inner.top, node.inner.left, inner, &text.text);
+                if let Some(column) = text.underline {
+                    let x = node.inner.left + column;
+                    let rect = Rect { left: x, top: node.inner.top, right: x + 1, bottom: node.inner.top + 1 };
+                    fb.replace_attr(rect.intersect(inner), Attributes::UNDERLINED, Attributes::UNDERLINED);
+                }
+            }
+            NodeContent::Textarea(ta) => {
+                Self::render_textarea(fb, ta, node.inner, inner, node.id == focused_id);
+            }
+            NodeContent::Editline(el) => {
+                let mut column = 0;
+                let mut text = String::new();
+                for c in el.text.chars() {
+                    let width = char_width(c);
+                    if column >= el.scroll {
+                        text.push(c);
+                    } else if column + width > el.scroll {
+                        // The right half of a wide character.
+                        text.push(' ');
+                    }
+                    column += width;
+                }
+                fb.replace_text(node.inner.top, node.inner.left, inner, &text);
+
+                if node.id == focused_id {
+                    let x = node.inner.left + text_width(&el.text[..el.cursor]) - el.scroll;
+                    fb.set_cursor(Point { x, y: node.inner.top });
+                }
+            }
+            NodeContent::Scrollarea(_) => {}
+        }
+
+        for child in self.inline_children(idx) {
+            self.render_node(fb, child, inner, focused_id);
+        }
+    }
+
+    fn render_textarea(fb: &mut Framebuffer, ta: &TextareaContent, rect: Rect, clip: Rect, focused: bool) {
+        let mut tb = ta.buffer.borrow_mut();
+        let scroll = ta.scroll;
+        let visible = tb.line_start_offset(scroll.y)..tb.line_end_offset(scroll.y + rect.height() - 1);
+        let matches = tb.search_matches_in(visible.start..visible.end + 1);
+
+        let logical =
+            |tb: &TextBuffer, r: Range<usize>| (tb.offset_to_logical(r.start), tb.offset_to_logical(r.end));
+        let matches: Vec<_> = matches.into_iter().map(|m| logical(&tb, m)).collect();
+        let selection = tb.selection_range().map(|s| logical(&tb, s));
+        let selection_bg = fb.indexed_alpha(IndexedColor::BrightBlue, 1, 2);
+        let match_bg = fb.indexed_alpha(IndexedColor::BrightYellow, 1, 3);
+        let tab_size = tb.tab_size();
+        let cursor = tb.cursor_offset();
+        let cursor_row = tb.visual_row_of(cursor);
+
+        let mut bytes = Vec::new();
+        let mut text = String::new();
+        let mut pos = tb.visual_row_move(VisualRow { line: scroll.y, row: ta.scroll_row }, 0);
+
+        for row in 0..rect.height() {
+            if row > 0 {
+                let next = tb.visual_row_move(pos, 1);
+                if next == pos {
+                    break;
+                }
+                pos = next;
+            }
+
+            let y = rect.top + row;
+            let line = pos.line;
+            let (range, row_column) = tb.visual_row_range(pos);
+            let line_end = tb.line_end_offset(line);
+            let is_last_row = range.end == line_end;
+            // The columns of this line that are shown, in logical columns.
+            let left = row_column + scroll.x;
+            let right = left + rect.width();
+            bytes.clear();
+            tb.extract_raw(range.clone(), &mut bytes);
+
+            // Expand tabs and cut off everything left of the horizontal scroll position.
+            text.clear();
+            let doc = bytes.as_slice();
+            let mut cfg = MeasurementConfig::new(&doc)
+                .with_tab_size(tab_size)
+                .with_cursor(Cursor { offset: 0, logical_pos: Point { x: row_column, y: line } });
+            while let Some(g) = cfg.next_grapheme() {
+                let column = g.column;
+                if column + g.width > left {
+                    if column < left || g.base == '\t' {
+                        // Half of a wide grapheme, or a tab.
+                        for _ in column.max(left)..column + g.width {
+                            text.push(' ');
+                        }
+                    } else {
+                        text.push_str(&String::from_utf8_lossy(&bytes[g.range]));
+                    }
+                }
+                if column + g.width >= right {
+                    break;
+                }
+            }
+            fb.replace_text(y, rect.left, clip, &text);
+
+            // The logical columns that this visual line covers. Highlighted line breaks
+            // are shown as one extra column after the last visual line.
+            let row_end = if is_last_row { CoordType::MAX } else { tb.offset_to_logical(range.end).x };
+            let line_end_x = tb.offset_to_logical(line_end).x + 1;
+            let mut highlight = |(beg, end): (Point, Point), bg| {
+                if !(beg.y..=end.y).contains(&line) {
+                    return;
+                }
+                let l = if line == beg.y { beg.x } else { 0 }.max(row_column);
+                let r = if line == end.y { end.x } else { line_end_x }.min(row_end);
+                if r <= l {
+                    return;
+                }
+                let l = rect.left + l - left;
+                let r = rect.left + r - left;
+                fb.blend_bg(Rect { left: l, top: y, right: r, bottom: y + 1 }.intersect(clip), bg);
+            };
+
+            for &m in &matches {
+                highlight(m, match_bg);
+            }
+            if let Some(selection) = selection {
+                highlight(selection, selection_bg);
+            }
+
+            if focused && pos == cursor_row {
+                let x = tb.offset_to_logical(cursor).x;
+                fb.set_cursor(Point { x: rect.left + x - left, y });
+            }
+        }
+    }
+}
+
+/// Returns the combined thickness of the border and padding on each side.
+fn frame_thickness(attr: &NodeAttributes) -> Rect {
+    let border = attr.bordered as CoordType;
+    Rect {
+        left: attr.padding.left + border,
+        top: attr.padding.top + border,
+        right: attr.padding.right + border,
+        bottom: attr.padding.bottom + border,
+    }
+}
+
+/// Computes the column widths and row heights of a grid.
+fn grid_tracks(sizes: &[Size], columns: CoordType) -> (Vec<CoordType>, Vec<CoordType>) {
+    let columns = columns.max(1) as usize;
+    let mut widths = vec![0; columns.min(sizes.len())];
+    let mut heights = vec![0; sizes.len().div_ceil(columns)];
+
+    for (i, size) in sizes.iter().enumerate() {
+        let (row, col) = (i / columns, i % columns);
+        widths[col] = widths[col].max(size.width);
+        heights[row] = heights[row].max(size.height);
+    }
+
+    (widths, heights)
+}
+
+/// Shares `free` space evenly among the `fills`. The first ones get the remainder.
+fn distribute(free: CoordType, fills: &[bool]) -> Vec<CoordType> {
+    let count = fills.iter().filter(|&&f| f).count() as CoordType;
+    let mut extra = vec![0; fills.len()];
+    if free <= 0 || count == 0 {
+        return extra;
+    }
+
+    let mut remainder = free % count;
+    for (i, _) in fills.iter().enumerate().filter(|&(_, &f)| f) {
+        extra[i] = free / count + (remainder > 0) as CoordType;
+        remainder -= 1;
+    }
+    extra
+}
+
+/// Returns the number of columns `text` occupies.
+fn text_width(text: &str) -> CoordType {
+    let bytes = text.as_bytes();
+    MeasurementConfig::new(&bytes).goto_offset(text.len()).logical_pos.x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribute() {
+        assert_eq!(distribute(7, &[true, false, true, true]), [3, 0, 2, 2]);
+        assert_eq!(distribute(-3, &[true, true]), [0, 0]);
+        assert_eq!(distribute(5, &[false, false]), [0, 0]);
+    }
+
+    #[test]
+    fn test_grid_tracks() {
+        let size = |width, height| Size { width, height };
+        let sizes = [size(3, 1), size(1, 2), size(5, 1), size(2, 1), size(4, 3)];
+        assert_eq!(grid_tracks(&sizes, 2), (vec![5, 2], vec![2, 1, 3]));
+        assert_eq!(grid_tracks(&sizes[..1], 3), (vec![3], vec![1]));
+    }
+
+    #[test]
+    fn test_layout() {
+        let mut tree = Tree::default();
+        tree.reset(Size { width: 20, height: 10 });
+
+        // A bordered row with a fixed and a filling child, above a label.
+        tree.push("row");
+        tree.last_mut().attributes.layout = Layout::Row;
+        tree.last_mut().attributes.bordered = true;
+        tree.push("fixed");
+        tree.last_mut().attributes.intrinsic_size = Some(Size { width: 4, height: 2 });
+        tree.pop();
+        tree.push("fill");
+        tree.last_mut().attributes.intrinsic_size = Some(Size { width: 1, height: 1 });
+        tree.last_mut().attributes.fill = true;
+        tree.pop();
+        tree.pop();
+        tree.push("label");
+        tree.last_mut().content = NodeContent::Text(TextContent { text: "abc".to_string(), underline: None });
+        tree.pop();
+
+        tree.layout(Size { width: 20, height: 10 });
+
+        let rect = |left, top, right, bottom| Rect { left, top, right, bottom };
+        assert_eq!(tree.nodes[1].intrinsic_size, Size { width: 7, height: 4 });
+        assert_eq!(tree.nodes[1].outer, rect(0, 0, 20, 4));
+        assert_eq!(tree.nodes[1].inner, rect(1, 1, 19, 3));
+        assert_eq!(tree.nodes[2].outer, rect(1, 1, 5, 3));
+        assert_eq!(tree.nodes[3].outer, rect(5, 1, 19, 3));
+        assert_eq!(tree.nodes[4].outer, rect(0, 4, 20, 5));
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let mut tree = Tree::default();
+        let mut ids = Vec::new();
+        for conditional in [false, true] {
+            tree.reset(Size { width: 10, height: 10 });
+            tree.push("a");
+            tree.pop();
+            if conditional {
+                tree.push("other");
+                tree.pop();
+            }
+            tree.push("a");
+            ids.push(tree.last().id);
+            tree.pop();
+        }
+        // The second "a" gets its own ID, which another sibling in between doesn't change.
+        assert_ne!(ids[0], tree.nodes[1].id);
+        assert_eq!(ids[0], ids[1]);
+    }
 }
@@ -1,5 +1,10 @@
 
-use crate::helpers::CoordType;
+use super::{Utf8Chars, tables::*};
+use crate::{
+    document::ReadableDocument,
+    helpers::{CoordType, Point},
+};
+use std::ops::Range;
 
 // On one hand it's disgusting that I wrote this as a global variable, but on the
 // other hand, this isn't a public library API, and it makes the code a lot cleaner,
@@ -12,3 +17,329 @@ static mut AMBIGUOUS_WIDTH: usize = 1;
 pub fn setup_ambiguous_width(ambiguous_width: CoordType) {
     unsafe { AMBIGUOUS_WIDTH = ambiguous_width as usize };
 }
+
+/// Returns the number of columns `c` occupies in a terminal, as per "UAX #11: East Asian Width".
+///
+/// Combining marks and other zero-width characters are 0 wide.
+/// Control characters are 1 wide, because we display them as a replacement glyph.
+/// Prefer [`MeasurementConfig`] for measuring text, since the width of a grapheme cluster
+/// isn't always the sum of its characters.
+pub fn char_width(c: char) -> CoordType {
+    width_of(ucd_lookup(c))
+}
+
+fn width_of(props: u8) -> CoordType {
+    match props >> WIDTH_SHIFT {
+        WIDTH_ZERO => 0,
+        WIDTH_NARROW => 1,
+        WIDTH_WIDE => 2,
+        _ => unsafe { AMBIGUOUS_WIDTH as CoordType },
+    }
+}
+
+fn grapheme_break_of(props: u8) -> u8 {
+    props & ((1 << WIDTH_SHIFT) - 1)
+}
+
+/// A position in a document: its byte offset and its logical position,
+/// as in [`crate::buffer::TextBuffer::offset_to_logical`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Cursor {
+    pub offset: usize,
+    pub logical_pos: Point,
+}
+
+/// A grapheme cluster, as returned by [`MeasurementConfig::next_grapheme`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Grapheme {
+    /// The bytes it occupies in the document.
+    pub range: Range<usize>,
+    /// The column it starts at.
+    pub column: CoordType,
+    /// The number of columns it occupies. Tabs extend to the next tab stop
+    /// and line breaks are 0 wide.
+    pub width: CoordType,
+    /// The first character of the cluster.
+    pub base: char,
+    /// Whether this is a line break: `\n` or `\r\n`.
+    pub newline: bool,
+}
+
+/// Walks a document grapheme cluster by grapheme cluster, as per "UAX #29: Unicode Text Segmentation",
+/// and keeps track of the logical position along the way.
+///
+/// A cluster is as wide as its widest character. That makes emoji ZWJ sequences 2 wide,
+/// and combining marks don't add to the width of their base. On top of that, emoji that
+/// request their emoji presentation with U+FE0F are 2 wide, and so are pairs of regional
+/// indicators, which are shown as flags.
+pub struct MeasurementConfig<'doc> {
+    buffer: &'doc dyn ReadableDocument,
+    tab_size: CoordType,
+    cursor: Cursor,
+}
+
+impl<'doc> MeasurementConfig<'doc> {
+    /// Starts at the beginning of `buffer`, with a tab size of 8.
+    pub fn new(buffer: &'doc dyn ReadableDocument) -> Self {
+        Self { buffer, tab_size: 8, cursor: Cursor::default() }
+    }
+
+    pub fn with_tab_size(mut self, tab_size: CoordType) -> Self {
+        self.tab_size = tab_size.max(1);
+        self
+    }
+
+    /// Starts at `cursor` instead. Its offset must be at the start of a grapheme cluster,
+    /// e.g. the start of a line, and its logical position must match it.
+    pub fn with_cursor(mut self, cursor: Cursor) -> Self {
+        self.cursor = cursor;
+        self
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        self.cursor
+    }
+
+    /// Returns the grapheme cluster at the cursor and moves the cursor past it.
+    /// Returns `None` at the end of the document.
+    pub fn next_grapheme(&mut self) -> Option<Grapheme> {
+        let g = self.peek_grapheme()?;
+        self.advance(&g);
+        Some(g)
+    }
+
+    /// Moves the cursor forward to `offset`. If `offset` is inside a grapheme cluster,
+    /// the cursor stops at the start of that cluster. Offsets behind the cursor are ignored.
+    pub fn goto_offset(&mut self, offset: usize) -> Cursor {
+        while self.cursor.offset < offset {
+            let Some(g) = self.peek_grapheme() else { break };
+            if g.range.end > offset {
+                break;
+            }
+            self.advance(&g);
+        }
+        self.cursor
+    }
+
+    /// Moves the cursor forward to the logical position `pos`. Positions past the end of a line
+    /// stop at the end of that line, in front of the line break. Positions inside a wide
+    /// grapheme cluster or a tab stop at the start of it. Positions behind the cursor are ignored.
+    pub fn goto_logical(&mut self, pos: Point) -> Cursor {
+        while self.cursor.logical_pos.y < pos.y
+            || (self.cursor.logical_pos.y == pos.y && self.cursor.logical_pos.x < pos.x)
+        {
+            let Some(g) = self.peek_grapheme() else { break };
+            if self.cursor.logical_pos.y == pos.y && (g.newline || g.column + g.width > pos.x) {
+                break;
+            }
+            self.advance(&g);
+        }
+        self.cursor
+    }
+
+    fn advance(&mut self, g: &Grapheme) {
+        self.cursor.offset = g.range.end;
+        if g.newline {
+            self.cursor.logical_pos = Point { x: 0, y: self.cursor.logical_pos.y + 1 };
+        } else {
+            self.cursor.logical_pos.x = g.column + g.width;
+        }
+    }
+
+    fn peek_grapheme(&self) -> Option<Grapheme> {
+        let beg = self.cursor.offset;
+        let column = self.cursor.logical_pos.x;
+        let (base, mut end) = self.decode(beg)?;
+
+        let props = ucd_lookup(base);
+        let mut state = GraphemeState::new(grapheme_break_of(props));
+        let mut width = width_of(props);
+        let mut newline = base == '\n';
+        let mut chars = 1;
+
+        while let Some((c, next)) = self.decode(end) {
+            let props = ucd_lookup(c);
+            if state.advance(grapheme_break_of(props)) {
+                break;
+            }
+            width = width.max(width_of(props));
+            newline |= c == '\n';
+            if c == '\u{FE0F}' && state.first == GB_EXTENDED_PICTOGRAPHIC {
+                width = 2;
+            }
+            chars += 1;
+            end = next;
+        }
+
+        if base == '\t' {
+            width = self.tab_size - column % self.tab_size;
+        } else if newline {
+            width = 0;
+        } else if state.first == GB_REGIONAL_INDICATOR && chars > 1 {
+            width = 2;
+        }
+
+        Some(Grapheme { range: beg..end, column, width, base, newline })
+    }
+
+    /// Decodes the character at `offset`. Returns it and the offset past it.
+    fn decode(&self, offset: usize) -> Option<(char, usize)> {
+        let chunk = self.buffer.read_forward(offset);
+        if chunk.is_empty() {
+            return None;
+        }
+
+        if chunk.len() >= 4 {
+            let mut it = Utf8Chars::new(chunk, 0);
+            let c = it.next()?;
+            return Some((c, offset + it.offset()));
+        }
+
+        // A chunk may end in the middle of a character, if there's more text after it.
+        // Stitch the character together from both sides.
+        let mut buf = [0; 4];
+        let mut len = 0;
+        while len < buf.len() {
+            let Some(&b) = self.buffer.read_forward(offset + len).first() else { break };
+            buf[len] = b;
+            len += 1;
+        }
+        let mut it = Utf8Chars::new(&buf[..len], 0);
+        let c = it.next()?;
+        Some((c, offset + it.offset()))
+    }
+}
+
+/// The state of the grapheme cluster boundary rules of "UAX #29", from one character to the next.
+struct GraphemeState {
+    /// The break property of the first character of the cluster.
+    first: u8,
+    /// The break property of the previous character.
+    prev: u8,
+    /// Whether an odd number of regional indicators precede, for GB12 and GB13.
+    ri_odd: bool,
+    /// How far into `ExtPict Extend* ZWJ` the text before is, for GB11.
+    emoji: Emoji,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Emoji {
+    None,
+    /// After `ExtPict Extend*`.
+    Pictographic,
+    /// After `ExtPict Extend* ZWJ`.
+    Joined,
+}
+
+impl GraphemeState {
+    fn new(first: u8) -> Self {
+        let mut state = Self { first, prev: GB_OTHER, ri_odd: false, emoji: Emoji::None };
+        state.update(first);
+        state
+    }
+
+    /// Returns `true` if there's a boundary between the previous character and the next one,
+    /// which has the break property `next`.
+    fn advance(&mut self, next: u8) -> bool {
+        let prev = self.prev;
+        let boundary = match (prev, next) {
+            // GB3
+            (GB_CR, GB_LF) => false,
+            // GB4, GB5
+            (GB_CONTROL | GB_CR | GB_LF, _) | (_, GB_CONTROL | GB_CR | GB_LF) => true,
+            // GB6, GB7, GB8
+            (GB_L, GB_L | GB_V | GB_LV | GB_LVT) | (GB_LV | GB_V, GB_V | GB_T) | (GB_LVT | GB_T, GB_T) => false,
+            // GB9, GB9a
+            (_, GB_EXTEND | GB_ZWJ | GB_SPACING_MARK) => false,
+            // GB9b
+            (GB_PREPEND, _) => false,
+            // GB11
+            (GB_ZWJ, GB_EXTENDED_PICTOGRAPHIC) => self.emoji != Emoji::Joined,
+            // GB12, GB13
+            (GB_REGIONAL_INDICATOR, GB_REGIONAL_INDICATOR) => !self.ri_odd,
+            // GB999
+            _ => true,
+        };
+        self.update(next);
+        boundary
+    }
+
+    fn update(&mut self, next: u8) {
+        self.ri_odd = next == GB_REGIONAL_INDICATOR && !(self.prev == GB_REGIONAL_INDICATOR && self.ri_odd);
+        self.emoji = match (next, self.emoji) {
+            (GB_EXTENDED_PICTOGRAPHIC, _) => Emoji::Pictographic,
+            (GB_EXTEND, Emoji::Pictographic) => Emoji::Pictographic,
+            (GB_ZWJ, Emoji::Pictographic) => Emoji::Joined,
+            _ => Emoji::None,
+        };
+        self.prev = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graphemes(text: &str) -> Vec<(&str, CoordType)> {
+        let bytes = text.as_bytes();
+        let mut cfg = MeasurementConfig::new(&bytes).with_tab_size(4);
+        let mut out = Vec::new();
+        while let Some(g) = cfg.next_grapheme() {
+            out.push((&text[g.range], g.width));
+        }
+        out
+    }
+
+    #[test]
+    fn test_segmentation() {
+        assert_eq!(graphemes("e\u{301}x"), [("e\u{301}", 1), ("x", 1)]);
+        assert_eq!(graphemes("a\r\n\nb"), [("a", 1), ("\r\n", 0), ("\n", 0), ("b", 1)]);
+        // Hangul syllables made of jamo.
+        assert_eq!(graphemes("\u{1100}\u{1161}\u{11A8}가"), [("\u{1100}\u{1161}\u{11A8}", 2), ("가", 2)]);
+        // Flags are pairs of regional indicators.
+        assert_eq!(graphemes("🇩🇪🇫🇷🇮"), [("🇩🇪", 2), ("🇫🇷", 2), ("🇮", 1)]);
+        // ZWJ sequences and emoji presentation.
+        assert_eq!(graphemes("👨‍👩‍👧!"), [("👨‍👩‍👧", 2), ("!", 1)]);
+        assert_eq!(graphemes("❤\u{FE0F}a\u{200D}b"), [("❤\u{FE0F}", 2), ("a\u{200D}", 1), ("b", 1)]);
+    }
+
+    #[test]
+    fn test_goto() {
+        let text = "a\tb\n漢字\u{301}z".as_bytes();
+        let cfg = || MeasurementConfig::new(&text).with_tab_size(4);
+
+        assert_eq!(cfg().goto_offset(2).logical_pos, Point { x: 4, y: 0 });
+        // Inside a cluster.
+        assert_eq!(cfg().goto_offset(11).offset, 7);
+        assert_eq!(cfg().goto_offset(text.len()).logical_pos, Point { x: 5, y: 1 });
+
+        assert_eq!(cfg().goto_logical(Point { x: 3, y: 0 }).offset, 1);
+        assert_eq!(cfg().goto_logical(Point { x: 99, y: 0 }).offset, 3);
+        assert_eq!(cfg().goto_logical(Point { x: 3, y: 1 }), Cursor {
+            offset: 7,
+            logical_pos: Point { x: 2, y: 1 }
+        });
+        assert_eq!(cfg().goto_logical(Point { x: 4, y: 1 }).offset, 12);
+    }
+
+    #[test]
+    fn test_char_width() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(char_width('漢'), 2);
+        assert_eq!(char_width('\u{1F600}'), 2);
+        assert_eq!(char_width('\u{7}'), 1);
+
+        // East Asian Width N: Greek, Hebrew and Thai.
+        assert_eq!(char_width('\u{1F00}'), 1);
+        assert_eq!(char_width('\u{5D0}'), 1);
+        assert_eq!(char_width('\u{E01}'), 1);
+        // Unassigned code points are N as well, even next to wide emoji,
+        // unless they're in one of the blocks reserved for CJK ideographs.
+        for c in ['\u{378}', '\u{1BF4}', '\u{1BFB}', '\u{1C38}', '\u{1F58}', '\u{1F7E}', '\u{1F02C}'] {
+            assert_eq!(char_width(c), 1, "{c:?}");
+        }
+        assert_eq!(char_width('\u{FA6E}'), 2);
+        assert_eq!(char_width('\u{3FFFD}'), 2);
+    }
+}
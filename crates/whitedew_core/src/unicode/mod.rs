@@ -1,6 +1,9 @@
 
 mod measurement;
+mod tables;
 mod utf8;
+mod word_break;
 
 pub use measurement::*;
 pub use utf8::*;
+pub use word_break::*;
@@ -0,0 +1,8 @@
+
+//! The Unicode properties that [`super::measurement`] and [`super::word_break`] need,
+//! as generated by `build/main.rs`.
+
+// The segmentation rules don't need to refer to every property by name.
+#![allow(dead_code)]
+
+include!(concat!(env!("OUT_DIR"), "/unicode_tables.rs"));
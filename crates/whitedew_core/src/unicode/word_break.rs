@@ -0,0 +1,41 @@
+use super::tables::*;
+
+/// The Word_Break property of "UAX #29: Unicode Text Segmentation".
+///
+/// CR, LF and Newline don't occur within a line, and Extend, ZWJ and Regional_Indicator
+/// only matter within grapheme clusters, so those are all reported as [`WordBreak::Other`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WordBreak {
+    Other,
+    Format,
+    Katakana,
+    HebrewLetter,
+    ALetter,
+    SingleQuote,
+    DoubleQuote,
+    MidNumLet,
+    MidLetter,
+    MidNum,
+    Numeric,
+    ExtendNumLet,
+    WSegSpace,
+}
+
+/// Returns the Word_Break property of `c`.
+pub fn word_break(c: char) -> WordBreak {
+    match word_break_lookup(c) {
+        WB_FORMAT => WordBreak::Format,
+        WB_KATAKANA => WordBreak::Katakana,
+        WB_HEBREW_LETTER => WordBreak::HebrewLetter,
+        WB_ALETTER => WordBreak::ALetter,
+        WB_SINGLE_QUOTE => WordBreak::SingleQuote,
+        WB_DOUBLE_QUOTE => WordBreak::DoubleQuote,
+        WB_MID_NUM_LET => WordBreak::MidNumLet,
+        WB_MID_LETTER => WordBreak::MidLetter,
+        WB_MID_NUM => WordBreak::MidNum,
+        WB_NUMERIC => WordBreak::Numeric,
+        WB_EXTEND_NUM_LET => WordBreak::ExtendNumLet,
+        WB_WSEG_SPACE => WordBreak::WSegSpace,
+        _ => WordBreak::Other,
+    }
+}